@@ -0,0 +1,369 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An 8-bit sRGB color with straight (not premultiplied) alpha.
+/// Every channel is a `u8`, so there's no such thing as an out-of-range `Color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// Why a string isn't a color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseColorError {
+    Empty,
+    BadHex { input: String, reason: String },
+    BadFunction { input: String, reason: String },
+    UnknownName(String),
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseColorError::Empty => write!(f, "empty color"),
+            ParseColorError::BadHex { input, reason } => write!(f, "invalid hex color {:?}: {}", input, reason),
+            ParseColorError::BadFunction { input, reason } => write!(f, "invalid color function {:?}: {}", input, reason),
+            ParseColorError::UnknownName(name) => write!(f, "unknown color name {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const TRANSPARENT: Color = Color::rgba(0, 0, 0, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// A CSS color name, in any case.
+    pub fn named(name: &str) -> Option<Color> {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color::TRANSPARENT);
+        }
+        let index = NAMED.binary_search_by_key(&name.as_str(), |&(name, _)| name).ok()?;
+        let rgb = NAMED[index].1;
+        Some(Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+    }
+
+    /// `#rrggbb`, or `#rrggbbaa` when not fully opaque.
+    pub fn to_hex(&self) -> String {
+        if self.a == 255 {
+            format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+        }
+    }
+
+    /// Paints this color on top of `background` (Porter-Duff source-over).
+    /// A fully transparent color leaves `background` exactly as it was.
+    pub fn over(&self, background: Color) -> Color {
+        if self.a == 0 {
+            return background;
+        }
+        let source_alpha = f64::from(self.a) / 255.0;
+        let background_alpha = f64::from(background.a) / 255.0 * (1.0 - source_alpha);
+        let alpha = source_alpha + background_alpha;
+        let mix = |source: u8, background: u8| {
+            channel((f64::from(source) * source_alpha + f64::from(background) * background_alpha) / alpha)
+        };
+        Color {
+            r: mix(self.r, background.r),
+            g: mix(self.g, background.g),
+            b: mix(self.b, background.b),
+            a: channel(alpha * 255.0),
+        }
+    }
+
+    /// The color `t` of the way from this one to `other`, channel by channel.
+    /// `t` is clamped to [0, 1].
+    pub fn lerp(&self, other: Color, t: f64) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |from: u8, to: u8| channel(f64::from(from) + (f64::from(to) - f64::from(from)) * t);
+        Color { r: mix(self.r, other.r), g: mix(self.g, other.g), b: mix(self.b, other.b), a: mix(self.a, other.a) }
+    }
+
+    /// WCAG relative luminance, from 0 for black to 1 for white. Alpha is ignored.
+    pub fn luminance(&self) -> f64 {
+        let linear = |value: u8| {
+            let value = f64::from(value) / 255.0;
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.r) + 0.7152 * linear(self.g) + 0.0722 * linear(self.b)
+    }
+
+    /// WCAG contrast ratio, from 1 for the same luminance to 21 for black on white.
+    /// The order of the two colors doesn't matter.
+    pub fn contrast_ratio(&self, other: Color) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+/// Accepts `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb(...)`/`rgba(...)`
+/// with commas or spaces and a slash, and the CSS color names.
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(input: &str) -> Result<Color, ParseColorError> {
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            return Err(ParseColorError::Empty);
+        }
+        if let Some(digits) = trimmed.strip_prefix('#') {
+            return parse_hex(input, digits);
+        }
+        let lower = trimmed.to_ascii_lowercase();
+        if let Some(arguments) = lower.strip_prefix("rgba(").or_else(|| lower.strip_prefix("rgb(")) {
+            return parse_function(input, arguments);
+        }
+        Color::named(trimmed).ok_or_else(|| ParseColorError::UnknownName(trimmed.to_string()))
+    }
+}
+
+fn channel(value: f64) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn parse_hex(input: &str, digits: &str) -> Result<Color, ParseColorError> {
+    let bad = |reason: String| ParseColorError::BadHex { input: input.to_string(), reason };
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(bad(format!("{:?} is not a hex digit", c)));
+    }
+    let nibbles: Vec<u8> = digits.chars().map(|c| c.to_digit(16).unwrap() as u8).collect();
+    let channels: Vec<u8> = match nibbles.len() {
+        3 | 4 => nibbles.iter().map(|n| n * 17).collect(),
+        6 | 8 => nibbles.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect(),
+        n => return Err(bad(format!("expected 3, 4, 6 or 8 digits, found {}", n))),
+    };
+    Ok(Color { r: channels[0], g: channels[1], b: channels[2], a: channels.get(3).copied().unwrap_or(255) })
+}
+
+/// The inside of `rgb(...)`, closing parenthesis still attached.
+fn parse_function(input: &str, arguments: &str) -> Result<Color, ParseColorError> {
+    let bad = |reason: String| ParseColorError::BadFunction { input: input.to_string(), reason };
+    let arguments = arguments.strip_suffix(')').ok_or_else(|| bad(String::from("missing closing parenthesis")))?;
+
+    let (channels, alpha): (Vec<&str>, Option<&str>) = if arguments.contains(',') {
+        let mut parts: Vec<&str> = arguments.split(',').map(str::trim).collect();
+        let alpha = if parts.len() == 4 { parts.pop() } else { None };
+        (parts, alpha)
+    } else {
+        let mut halves = arguments.splitn(2, '/');
+        let channels = halves.next().unwrap_or("").split_whitespace().collect();
+        (channels, halves.next().map(str::trim))
+    };
+    if channels.len() != 3 {
+        return Err(bad(format!("expected 3 channels, found {}", channels.len())));
+    }
+
+    let mut rgb = [0u8; 3];
+    for (slot, text) in rgb.iter_mut().zip(&channels) {
+        *slot = match text.strip_suffix('%') {
+            Some(percent) => channel(number(percent, 100.0).map_err(bad)? / 100.0 * 255.0),
+            None => text
+                .parse::<u8>()
+                .map_err(|_| bad(format!("channel {:?} should be a whole number from 0 to 255", text)))?,
+        };
+    }
+    let a = match alpha {
+        None => 255,
+        Some(text) => match text.strip_suffix('%') {
+            Some(percent) => channel(number(percent, 100.0).map_err(bad)? / 100.0 * 255.0),
+            None => channel(number(text, 1.0).map_err(bad)? * 255.0),
+        },
+    };
+    Ok(Color { r: rgb[0], g: rgb[1], b: rgb[2], a })
+}
+
+/// A number from 0 to `max`.
+fn number(text: &str, max: f64) -> Result<f64, String> {
+    match text.trim().parse::<f64>() {
+        Ok(value) if (0.0..=max).contains(&value) => Ok(value),
+        _ => Err(format!("{:?} should be a number from 0 to {}", text, max)),
+    }
+}
+
+/// The CSS named colors, sorted for binary search. `transparent` is the odd one
+/// out with an alpha of zero, so it's handled on its own.
+const NAMED: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff), ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff), ("beige", 0xf5f5dc), ("bisque", 0xffe4c4), ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd), ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00), ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed), ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff), ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9), ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f), ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000), ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1), ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff), ("dimgray", 0x696969), ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff), ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff), ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520), ("gray", 0x808080), ("green", 0x008000), ("greenyellow", 0xadff2f), ("grey", 0x808080),
+    ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c), ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0), ("khaki", 0xf0e68c), ("lavender", 0xe6e6fa), ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00), ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3), ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a), ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899), ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000), ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3), ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee), ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585), ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080), ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000), ("olivedrab", 0x6b8e23), ("orange", 0xffa500), ("orangered", 0xff4500),
+    ("orchid", 0xda70d6), ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9), ("peru", 0xcd853f),
+    ("pink", 0xffc0cb), ("plum", 0xdda0dd), ("powderblue", 0xb0e0e6), ("purple", 0x800080),
+    ("rebeccapurple", 0x663399), ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513), ("salmon", 0xfa8072), ("sandybrown", 0xf4a460), ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee), ("sienna", 0xa0522d), ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd), ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f), ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080), ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347), ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3), ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+
+    fn parse(input: &str) -> Color {
+        input.parse().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn random_color(rng: &mut XorShift) -> Color {
+        let mut channel = || rng.range(0, 256) as u8;
+        Color::rgba(channel(), channel(), channel(), channel())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse("#f80"), Color::rgb(0xff, 0x88, 0x00));
+        assert_eq!(parse("#F808"), Color::rgba(0xff, 0x88, 0x00, 0x88));
+        assert_eq!(parse("#1e90ff"), Color::rgb(0x1e, 0x90, 0xff));
+        assert_eq!(parse("  #1E90FF80 "), Color::rgba(0x1e, 0x90, 0xff, 0x80));
+    }
+
+    #[test]
+    fn parses_functions() {
+        assert_eq!(parse("rgb(30, 144, 255)"), Color::rgb(30, 144, 255));
+        assert_eq!(parse("RGBA(30,144,255,0.5)"), Color::rgba(30, 144, 255, 128));
+        assert_eq!(parse("rgb(100% 0% 50%)"), Color::rgb(255, 0, 128));
+        assert_eq!(parse("rgb(30 144 255 / 25%)"), Color::rgba(30, 144, 255, 64));
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(parse("rebeccapurple"), Color::rgb(0x66, 0x33, 0x99));
+        assert_eq!(parse("DodgerBlue"), parse("#1e90ff"));
+        assert_eq!(parse("transparent"), Color::TRANSPARENT);
+        assert!(NAMED.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for (name, _) in NAMED.iter() {
+            assert!(Color::named(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn explains_bad_input() {
+        let message = |input: &str| input.parse::<Color>().unwrap_err().to_string();
+        assert_eq!(message(""), "empty color");
+        assert_eq!(message("#12"), "invalid hex color \"#12\": expected 3, 4, 6 or 8 digits, found 2");
+        assert_eq!(message("#12345g"), "invalid hex color \"#12345g\": 'g' is not a hex digit");
+        assert_eq!(message("rgb(1, 2)"), "invalid color function \"rgb(1, 2)\": expected 3 channels, found 2");
+        assert_eq!(
+            message("rgb(1, 2, 256)"),
+            "invalid color function \"rgb(1, 2, 256)\": channel \"256\" should be a whole number from 0 to 255"
+        );
+        assert_eq!(
+            message("rgba(1, 2, 3, 1.5)"),
+            "invalid color function \"rgba(1, 2, 3, 1.5)\": \"1.5\" should be a number from 0 to 1"
+        );
+        assert_eq!(message("rgb(1, 2, 3"), "invalid color function \"rgb(1, 2, 3\": missing closing parenthesis");
+        assert_eq!(message("blurple"), "unknown color name \"blurple\"");
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let mut rng = XorShift::new(19);
+        for _ in 0..1000 {
+            let color = random_color(&mut rng);
+            assert_eq!(parse(&color.to_hex()), color);
+        }
+        assert_eq!(Color::rgb(255, 0, 16).to_string(), "#ff0010");
+        assert_eq!(Color::rgba(255, 0, 16, 0).to_string(), "#ff001000");
+    }
+
+    #[test]
+    fn composites_source_over() {
+        let mut rng = XorShift::new(190);
+        for _ in 0..1000 {
+            let (source, background) = (random_color(&mut rng), random_color(&mut rng));
+            assert_eq!(Color { a: 255, ..source }.over(background), Color { a: 255, ..source });
+            assert_eq!(Color::TRANSPARENT.over(background), background);
+            assert_eq!(source.over(Color { a: 255, ..background }).a, 255);
+            assert!(source.over(background).a >= source.a.max(background.a));
+        }
+        assert_eq!(Color::rgba(255, 0, 0, 128).over(Color::rgb(0, 0, 255)), Color::rgb(128, 0, 127));
+    }
+
+    #[test]
+    fn interpolates() {
+        let mut rng = XorShift::new(191);
+        for _ in 0..1000 {
+            let (from, to) = (random_color(&mut rng), random_color(&mut rng));
+            assert_eq!(from.lerp(to, 0.0), from);
+            assert_eq!(from.lerp(to, 1.0), to);
+            assert_eq!(from.lerp(to, -3.0), from);
+            assert_eq!(from.lerp(from, 0.37), from);
+        }
+        assert_eq!(Color::BLACK.lerp(Color::WHITE, 0.5), Color::rgb(128, 128, 128));
+    }
+
+    #[test]
+    fn measures_luminance_and_contrast() {
+        assert!(close(Color::BLACK.luminance(), 0.0));
+        assert!(close(Color::WHITE.luminance(), 1.0));
+        assert!(close(Color::BLACK.contrast_ratio(Color::WHITE), 21.0));
+        assert!(close(Color::WHITE.contrast_ratio(Color::BLACK), 21.0));
+        // the usual reference: #777 on white just misses the 4.5 AA threshold
+        let grey = parse("#777");
+        assert!((grey.contrast_ratio(Color::WHITE) - 4.48).abs() < 0.01);
+
+        let mut rng = XorShift::new(192);
+        for _ in 0..1000 {
+            let (a, b) = (random_color(&mut rng), random_color(&mut rng));
+            assert!(close(a.contrast_ratio(b), b.contrast_ratio(a)));
+            assert!((1.0..=21.0 + 1e-9).contains(&a.contrast_ratio(b)));
+            assert!(close(a.contrast_ratio(a), 1.0));
+        }
+    }
+}
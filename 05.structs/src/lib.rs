@@ -0,0 +1,19 @@
+//! The structs from the chapter, grown into something other code can use.
+
+pub mod color;
+pub mod packing;
+pub mod point;
+pub mod quadtree;
+pub mod rectangle;
+pub mod render;
+pub mod user;
+
+#[cfg(test)]
+mod rng;
+
+pub use color::Color;
+pub use point::Point;
+pub use quadtree::QuadTree;
+pub use rectangle::Rectangle;
+pub use render::Scene;
+pub use user::{User, UserRegistry};
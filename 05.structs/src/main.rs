@@ -1,108 +1,64 @@
-/// 1. By using structs, you can keep associated pieces of 
-/// data connected to each other and name each piece to 
-/// make your code clear.
-
-/// 2. Methods let you specify the behavior that instances 
-/// of your structs have.
-
-/// 3. Associated functions let you namespace functionality 
-/// that is particular to your struct without having an 
-/// instance available.
-
-/// struct
-struct User {
-    name: String,
-    email: String,
-    account: u64,
-    active: bool,
-}
-
+//! 1. By using structs, you can keep associated pieces of
+//!    data connected to each other and name each piece to
+//!    make your code clear.
+//!
+//! 2. Methods let you specify the behavior that instances
+//!    of your structs have.
+//!
+//! 3. Associated functions let you namespace functionality
+//!    that is particular to your struct without having an
+//!    instance available.
+
+use structs::user::UserError;
+use structs::{Color, Point, Rectangle, Scene, User, UserRegistry};
+
+// struct, see user.rs
 // instance of User
-fn new_user() -> User {
-    // Note that the entire instance must be mutable;
-    // Rust doesn’t allow us to mark only certain fields as mutable.
-    let mut user1 = User {
-        email: String::from("ddd@gmail.com"),
-        name: String::from("Alex"),
-        account: 33,
-        active: true,
-    };
-
-    user1.account = 3434;
-    user1.name = String::from("Hex");
-    user1.email = "dd@yahoo.com".to_string();
-    user1.active = false;
-    println!("{}", user1.account);
-
-    user1
-}
-
-/// tuple structs
-struct Color(i32, i32, i32);
-struct Point(i32, i32, i32);
-
-/// example - rectangle
-#[derive(Debug)]
-struct Rectangle {
-    width: u32,
-    height: u32,
-}
-
-/// method
-/// methods are different from functions in that they’re defined within the context of a struct (or an enum or a trait object)
-/// their first parameter is always self, which represents the instance of the struct the method is being called on.
-impl Rectangle {
-    fn area(&self) -> u32 {
-        self.width * self.height
-    }
-
-    fn cycle(&self) -> u32 {
-        (self.width + self.height) * 2
-    }
-
-    fn can_hold(&self, other: &Rectangle) -> bool {
-        self.width > other.width && self.height > other.height
-    }
+fn new_user() -> Result<User, UserError> {
+    // The registry owns its users, so it's the registry that must be
+    // mutable; changes go through it and stay valid.
+    let mut registry = UserRegistry::new();
+    let account = registry.create("Alex", "ddd@gmail.com")?;
+    registry.deactivate(account)?;
+    println!("{}", account);
+
+    Ok(registry.by_account(account).cloned().unwrap())
 }
 
-
-/// associated function
-/// Another useful feature of impl blocks is that we’re allowed to define functions within
-/// impl blocks that don’t take self as a parameter. 
-/// These are called associated functions because they’re associated with the struct. They’re still functions, not methods
-/// because they don’t have an instance of the struct to work with
-impl Rectangle {
-    fn square(size: u32) -> Rectangle {
-        Rectangle { width: size, height: size }
-    }
-}
+// tuple structs, see point.rs
+// example - rectangle, see rectangle.rs
+//
+// method
+// methods are different from functions in that they’re defined within the context of a struct (or an enum or a trait object)
+// their first parameter is always self, which represents the instance of the struct the method is being called on.
+//
+// associated function
+// Another useful feature of impl blocks is that we’re allowed to define functions within
+// impl blocks that don’t take self as a parameter.
+// These are called associated functions because they’re associated with the struct. They’re still functions, not methods
+// because they don’t have an instance of the struct to work with
 
 /// borrow other than ownership
-fn area(rectangle: &Rectangle) -> u32 {
-    rectangle.width * rectangle.height
+fn area(rectangle: &Rectangle) -> u64 {
+    u64::from(rectangle.width) * u64::from(rectangle.height)
 }
 
 
 fn main() {
-   let _user1 = new_user();
+   let _user1 = new_user().unwrap();
 
-   let _black = Color(0, 0, 0);
+   let black: Color = "black".parse().unwrap();
    let _origin = Point(0, 0, 0);
 
-   let rect1 = Rectangle {
-       width: 30,
-       height: 50,
-   };
+   let rect1 = Rectangle::new(0, 0, 30, 50);
 
    let area_size = area(&rect1);
    assert_eq!(area_size, rect1.area());
 
-   let rect2 = Rectangle {
-       width: 40,
-       height: 51
-   };
+   let rect2 = Rectangle::new(10, 10, 40, 51);
 
-   assert_eq!(rect2.can_hold(&rect1), true);
+   assert!(rect2.can_hold(&rect1));
+   assert_eq!(rect1.intersection(&rect2), Some(Rectangle::new(10, 10, 20, 40)));
 
    // call method
    // when you call a method with object.something(), Rust automatically adds in &, &mut, or * so object matches the signature of the method.
@@ -113,19 +69,10 @@ fn main() {
    println!("The cycle size of rectangle is {}", cycle_size);
    println!("{:#?}", rect1);
 
+   let mut scene = Scene::new();
+   scene.add(rect1, "rect1", Color::named("steelblue").unwrap()).add(rect2, "rect2", black);
+   print!("{}", scene.to_ascii(None, 5));
+
    // call associate function
    Rectangle::square(40);
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn larger_can_hold_small() {
-        let rect1 = Rectangle { width: 30, height: 50 };
-        let rect2 = Rectangle { width: 40, height: 51 };
-
-        assert!(rect2.can_hold(&rect1));
-    }
-}
@@ -0,0 +1,423 @@
+use std::convert::TryFrom;
+
+use crate::rectangle::Rectangle;
+
+/// How free space is tracked and picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heuristic {
+    /// Rows of items, each row as tall as its first item. Fast and predictable,
+    /// wasteful when heights vary a lot.
+    Shelf,
+    /// Every placement cuts its free rectangle in two; picks the free
+    /// rectangle that leaves the least area over.
+    Guillotine,
+    /// Keeps every maximal free rectangle, overlapping ones included, and
+    /// picks the one with the snuggest short side. Usually the tightest.
+    MaxRects,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub heuristic: Heuristic,
+    /// Whether items may be turned 90 degrees to fit.
+    pub allow_rotation: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { heuristic: Heuristic::MaxRects, allow_rotation: false }
+    }
+}
+
+/// Where one item ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    /// Position of the item in the list given to `pack`.
+    pub index: usize,
+    /// The space it takes in the container, already turned if `rotated`.
+    pub rectangle: Rectangle,
+    pub rotated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packing {
+    /// Sorted by `index`.
+    pub placements: Vec<Placement>,
+    /// Indices of the items that didn't fit, in order.
+    pub unplaced: Vec<usize>,
+    /// Container area nothing was placed on.
+    pub wasted_area: u64,
+}
+
+/// Places as many of `items` as it can inside `container`.
+/// Only the sizes of `items` matter; placements are in the container's coordinates.
+/// A container reaching past `i32::MAX` only takes items whose corner
+/// still fits in an `i32`, so the far end of it may stay empty.
+pub fn pack(container: &Rectangle, items: &[Rectangle], options: Options) -> Packing {
+    // big ones first, they're the hardest to fit later on
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by_key(|&index| {
+        let item = &items[index];
+        std::cmp::Reverse((item.width.max(item.height), item.area()))
+    });
+
+    let mut packer: Box<dyn Packer> = match options.heuristic {
+        Heuristic::Shelf => Box::new(Shelves::new(*container)),
+        Heuristic::Guillotine => Box::new(FreeList::new(*container, Heuristic::Guillotine)),
+        Heuristic::MaxRects => Box::new(FreeList::new(*container, Heuristic::MaxRects)),
+    };
+
+    let mut placements = Vec::new();
+    let mut unplaced = Vec::new();
+    for index in order {
+        let item = &items[index];
+        let placed = if item.is_empty() {
+            // takes up no room, so anywhere it fits will do
+            orientations(item, options.allow_rotation)
+                .into_iter()
+                .find(|candidate| container.can_hold(candidate))
+                .map(|candidate| sized(container.x, container.y, &candidate))
+        } else {
+            packer.place(item, options.allow_rotation)
+        };
+        match placed {
+            Some(rectangle) => {
+                let rotated = rectangle.width != item.width || rectangle.height != item.height;
+                placements.push(Placement { index, rectangle, rotated });
+            },
+            None => unplaced.push(index),
+        }
+    }
+
+    placements.sort_by_key(|placement| placement.index);
+    unplaced.sort_unstable();
+    let used: u64 = placements.iter().map(|placement| placement.rectangle.area()).sum();
+    Packing { placements, unplaced, wasted_area: container.area() - used }
+}
+
+/// `item`'s size with its corner at `(x, y)`.
+fn sized(x: i32, y: i32, item: &Rectangle) -> Rectangle {
+    Rectangle::new(x, y, item.width, item.height)
+}
+
+/// `item`'s size with its corner at `(x, y)`, or `None` if that corner
+/// is outside the `i32` range.
+fn sized_at(x: i64, y: i64, item: &Rectangle) -> Option<Rectangle> {
+    Some(sized(i32::try_from(x).ok()?, i32::try_from(y).ok()?, item))
+}
+
+fn turned(item: &Rectangle) -> Rectangle {
+    Rectangle::new(item.x, item.y, item.height, item.width)
+}
+
+/// The ways `item` may be laid down.
+fn orientations(item: &Rectangle, allow_rotation: bool) -> Vec<Rectangle> {
+    if allow_rotation && item.width != item.height {
+        vec![*item, turned(item)]
+    } else {
+        vec![*item]
+    }
+}
+
+trait Packer {
+    /// Finds room for `item`, and takes it.
+    fn place(&mut self, item: &Rectangle, allow_rotation: bool) -> Option<Rectangle>;
+}
+
+struct Shelf {
+    y: i32,
+    height: u32,
+    /// Width already taken from the left.
+    used: u32,
+}
+
+impl Shelf {
+    fn top(&self) -> i64 {
+        i64::from(self.y)
+    }
+}
+
+struct Shelves {
+    container: Rectangle,
+    shelves: Vec<Shelf>,
+}
+
+impl Shelves {
+    fn new(container: Rectangle) -> Shelves {
+        Shelves { container, shelves: Vec::new() }
+    }
+}
+
+impl Packer for Shelves {
+    fn place(&mut self, item: &Rectangle, allow_rotation: bool) -> Option<Rectangle> {
+        let candidates = orientations(item, allow_rotation);
+
+        // first shelf with room, in the orientation that wastes the least of its height
+        let left = self.container.left();
+        for shelf in &mut self.shelves {
+            let room = Rectangle::new(0, 0, self.container.width - shelf.used, shelf.height);
+            let best = candidates.iter().filter(|candidate| room.can_hold(candidate)).max_by_key(|candidate| candidate.height);
+            let placed = best.and_then(|candidate| sized_at(left + i64::from(shelf.used), shelf.top(), candidate));
+            if let Some(placed) = placed {
+                shelf.used += placed.width;
+                return Some(placed);
+            }
+        }
+
+        // otherwise open a new shelf as low as the item allows
+        let top = self.shelves.last().map_or(self.container.top(), |shelf| shelf.top() + i64::from(shelf.height));
+        let room = Rectangle::new(0, 0, self.container.width, (self.container.bottom() - top) as u32);
+        let candidate = candidates.iter().filter(|candidate| room.can_hold(candidate)).min_by_key(|candidate| candidate.height)?;
+        let placed = sized_at(self.container.left(), top, candidate)?;
+        self.shelves.push(Shelf { y: placed.y, height: placed.height, used: placed.width });
+        Some(placed)
+    }
+}
+
+/// Free space as a list of rectangles, for the guillotine and maxrects heuristics.
+struct FreeList {
+    heuristic: Heuristic,
+    free: Vec<Rectangle>,
+}
+
+impl FreeList {
+    fn new(container: Rectangle, heuristic: Heuristic) -> FreeList {
+        let free = if container.is_empty() { Vec::new() } else { vec![container] };
+        FreeList { heuristic, free }
+    }
+
+    /// Lower is better.
+    fn score(&self, free: &Rectangle, candidate: &Rectangle) -> (u64, u64) {
+        let spare_width = u64::from(free.width - candidate.width);
+        let spare_height = u64::from(free.height - candidate.height);
+        match self.heuristic {
+            Heuristic::MaxRects => (spare_width.min(spare_height), spare_width.max(spare_height)),
+            _ => (free.area() - candidate.area(), spare_width.min(spare_height)),
+        }
+    }
+
+    /// Cuts what's left of `free` after `placed` went into its corner, along
+    /// the axis that keeps the bigger piece whole. A piece whose corner is
+    /// past `i32::MAX` could never take an item, so it's dropped.
+    fn guillotine(&mut self, free: Rectangle, placed: &Rectangle) {
+        let spare_width = free.width - placed.width;
+        let spare_height = free.height - placed.height;
+        let (right, below) = if spare_width < spare_height {
+            (
+                Rectangle::new(0, 0, spare_width, placed.height),
+                Rectangle::new(0, 0, free.width, spare_height),
+            )
+        } else {
+            (
+                Rectangle::new(0, 0, spare_width, free.height),
+                Rectangle::new(0, 0, placed.width, spare_height),
+            )
+        };
+        let right = sized_at(placed.right(), free.top(), &right);
+        let below = sized_at(free.left(), placed.bottom(), &below);
+        self.free.extend(right.iter().chain(below.iter()).filter(|piece| !piece.is_empty()));
+    }
+
+    /// Carves `placed` out of every free rectangle it touches, keeping the
+    /// up to four maximal pieces around it, then drops pieces another one covers.
+    fn carve(&mut self, placed: &Rectangle) {
+        let mut pieces = Vec::new();
+        for free in self.free.drain(..) {
+            if !free.intersects(placed) {
+                pieces.push(free);
+                continue;
+            }
+            let left = Rectangle::new(free.x, free.y, (placed.left() - free.left()).max(0) as u32, free.height);
+            let right_x = placed.right().min(free.right());
+            let right = sized_at(right_x, free.top(), &Rectangle::new(0, 0, (free.right() - right_x) as u32, free.height));
+            let top = Rectangle::new(free.x, free.y, free.width, (placed.top() - free.top()).max(0) as u32);
+            let bottom_y = placed.bottom().min(free.bottom());
+            let bottom = sized_at(free.left(), bottom_y, &Rectangle::new(0, 0, free.width, (free.bottom() - bottom_y) as u32));
+            // pieces starting past `i32::MAX` can't take an item anyway
+            pieces.extend([Some(left), right, Some(top), bottom].iter().flatten().filter(|piece| !piece.is_empty()));
+        }
+
+        let mut kept: Vec<Rectangle> = Vec::with_capacity(pieces.len());
+        for (i, piece) in pieces.iter().enumerate() {
+            // of two equal pieces, keep the first
+            let covered = pieces
+                .iter()
+                .enumerate()
+                .any(|(j, other)| j != i && other.contains_rect(piece) && (other != piece || j < i));
+            if !covered {
+                kept.push(*piece);
+            }
+        }
+        self.free = kept;
+    }
+}
+
+impl Packer for FreeList {
+    fn place(&mut self, item: &Rectangle, allow_rotation: bool) -> Option<Rectangle> {
+        let mut best: Option<((u64, u64), usize, Rectangle)> = None;
+        for (slot, free) in self.free.iter().enumerate() {
+            for candidate in orientations(item, allow_rotation) {
+                if !free.can_hold(&candidate) {
+                    continue;
+                }
+                let score = self.score(free, &candidate);
+                if best.as_ref().is_none_or(|(best, _, _)| score < *best) {
+                    best = Some((score, slot, sized(free.x, free.y, &candidate)));
+                }
+            }
+        }
+
+        let (_, slot, placed) = best?;
+        match self.heuristic {
+            Heuristic::MaxRects => self.carve(&placed),
+            _ => {
+                let free = self.free.swap_remove(slot);
+                self.guillotine(free, &placed);
+            },
+        }
+        Some(placed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+
+    const HEURISTICS: [Heuristic; 3] = [Heuristic::Shelf, Heuristic::Guillotine, Heuristic::MaxRects];
+
+    fn all_options() -> Vec<Options> {
+        let mut all = Vec::new();
+        for &heuristic in &HEURISTICS {
+            for &allow_rotation in &[false, true] {
+                all.push(Options { heuristic, allow_rotation });
+            }
+        }
+        all
+    }
+
+    /// Everything a packing promises, whatever the heuristic.
+    fn check(container: &Rectangle, items: &[Rectangle], options: Options, packing: &Packing) {
+        let mut seen = vec![false; items.len()];
+        for placement in &packing.placements {
+            let item = &items[placement.index];
+            let rectangle = &placement.rectangle;
+            assert!(!seen[placement.index], "{:?}: item {} placed twice", options, placement.index);
+            seen[placement.index] = true;
+            assert!(container.contains_rect(rectangle), "{:?}: {:?} sticks out of {:?}", options, rectangle, container);
+            if placement.rotated {
+                assert!(options.allow_rotation);
+                assert_eq!((rectangle.width, rectangle.height), (item.height, item.width));
+            } else {
+                assert_eq!((rectangle.width, rectangle.height), (item.width, item.height));
+            }
+        }
+        for &index in &packing.unplaced {
+            assert!(!seen[index], "{:?}: item {} both placed and unplaced", options, index);
+            seen[index] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen), "{:?}: an item went missing", options);
+
+        for (i, a) in packing.placements.iter().enumerate() {
+            for b in &packing.placements[i + 1..] {
+                assert!(!a.rectangle.intersects(&b.rectangle), "{:?}: {:?} overlaps {:?}", options, a, b);
+            }
+        }
+        let used: u64 = packing.placements.iter().map(|placement| placement.rectangle.area()).sum();
+        assert_eq!(packing.wasted_area + used, container.area());
+    }
+
+    #[test]
+    fn fills_a_container_exactly() {
+        let container = Rectangle::new(0, 0, 10, 10);
+        let items = [Rectangle::square(5); 4];
+        for options in all_options() {
+            let packing = pack(&container, &items, options);
+            check(&container, &items, options, &packing);
+            assert_eq!(packing.wasted_area, 0, "{:?}", options);
+            assert!(packing.unplaced.is_empty());
+        }
+    }
+
+    #[test]
+    fn rotates_only_when_allowed() {
+        let container = Rectangle::new(0, 0, 2, 10);
+        let items = [Rectangle::new(0, 0, 10, 2)];
+        for options in all_options() {
+            let packing = pack(&container, &items, options);
+            check(&container, &items, options, &packing);
+            if options.allow_rotation {
+                assert_eq!(packing.placements[0].rectangle, Rectangle::new(0, 0, 2, 10));
+                assert!(packing.placements[0].rotated);
+            } else {
+                assert_eq!(packing.unplaced, [0]);
+                assert_eq!(packing.wasted_area, 20);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_what_did_not_fit() {
+        let container = Rectangle::new(100, -50, 8, 8);
+        let items = [Rectangle::new(0, 0, 9, 1), Rectangle::square(4), Rectangle::new(0, 0, 0, 3), Rectangle::square(8)];
+        for options in all_options() {
+            let packing = pack(&container, &items, options);
+            check(&container, &items, options, &packing);
+            // the 8x8 goes in first and leaves no room for the 4x4
+            assert_eq!(packing.unplaced, [0, 1], "{:?}", options);
+            assert_eq!(packing.placements[1].rectangle, Rectangle::new(100, -50, 8, 8));
+            assert_eq!(packing.wasted_area, 0);
+        }
+    }
+
+    #[test]
+    fn keeps_everything_apart_and_inside() {
+        let mut rng = XorShift::new(17);
+        for _ in 0..100 {
+            let container = Rectangle::new(
+                rng.range(-50, 50) as i32,
+                rng.range(-50, 50) as i32,
+                rng.range(1, 120) as u32,
+                rng.range(1, 120) as u32,
+            );
+            let items: Vec<Rectangle> = (0..rng.range(0, 60))
+                .map(|_| Rectangle::new(0, 0, rng.range(0, 40) as u32, rng.range(0, 40) as u32))
+                .collect();
+            for options in all_options() {
+                let packing = pack(&container, &items, options);
+                check(&container, &items, options, &packing);
+            }
+        }
+    }
+
+    #[test]
+    fn stays_within_the_i32_range() {
+        // corners past i32::MAX can't be placed on
+        let container = Rectangle::new(i32::MAX - 10, i32::MAX - 10, 100, 100);
+        let items = [Rectangle::square(4); 30];
+        for options in all_options() {
+            let packing = pack(&container, &items, options);
+            check(&container, &items, options, &packing);
+            assert_eq!(packing.placements.len(), 9, "{:?}", options);
+        }
+
+        // shelves more than i32::MAX apart
+        let container = Rectangle::new(i32::MIN, i32::MIN, 10, u32::MAX);
+        let items = [Rectangle::new(0, 0, 10, 1 << 31), Rectangle::new(0, 0, 10, 1), Rectangle::new(0, 0, 10, 1)];
+        for options in all_options() {
+            let packing = pack(&container, &items, options);
+            check(&container, &items, options, &packing);
+            assert!(packing.unplaced.is_empty(), "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn maxrects_packs_at_least_as_well_as_shelves_on_sprites() {
+        let mut rng = XorShift::new(1717);
+        let items: Vec<Rectangle> =
+            (0..200).map(|_| Rectangle::new(0, 0, rng.range(4, 33) as u32, rng.range(4, 33) as u32)).collect();
+        let container = Rectangle::new(0, 0, 256, 256);
+        let wasted = |heuristic| pack(&container, &items, Options { heuristic, allow_rotation: true }).wasted_area;
+        assert!(wasted(Heuristic::MaxRects) <= wasted(Heuristic::Shelf));
+    }
+}
@@ -0,0 +1,283 @@
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub};
+
+/// What a `Point` can be made of: the signed integers and floats.
+pub trait Scalar:
+    Copy
+    + PartialEq
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+{
+    const ZERO: Self;
+
+    fn to_f64(self) -> f64;
+}
+
+/// Scalars with a square root, which is what it takes to normalize.
+pub trait Float: Scalar {
+    fn sqrt(self) -> Self;
+}
+
+macro_rules! scalar {
+    ($($t:ty),*) => {$(
+        impl Scalar for $t {
+            const ZERO: $t = 0 as $t;
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+        }
+
+        /// `k * p`, the same as `p * k`.
+        impl Mul<Point<$t>> for $t {
+            type Output = Point<$t>;
+
+            fn mul(self, point: Point<$t>) -> Point<$t> {
+                point * self
+            }
+        }
+    )*};
+}
+
+scalar!(i32, i64, f32, f64);
+
+impl Float for f32 {
+    fn sqrt(self) -> f32 {
+        f32::sqrt(self)
+    }
+}
+
+impl Float for f64 {
+    fn sqrt(self) -> f64 {
+        f64::sqrt(self)
+    }
+}
+
+/// A point, or the vector from the origin to it, in three dimensions.
+/// Integer points overflow the way their integers do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Point<T>(pub T, pub T, pub T);
+
+impl<T: Scalar> Point<T> {
+    pub fn origin() -> Point<T> {
+        Point(T::ZERO, T::ZERO, T::ZERO)
+    }
+
+    pub fn dot(self, other: Point<T>) -> T {
+        self.0 * other.0 + self.1 * other.1 + self.2 * other.2
+    }
+
+    /// Perpendicular to both, following the right-hand rule.
+    pub fn cross(self, other: Point<T>) -> Point<T> {
+        Point(
+            self.1 * other.2 - self.2 * other.1,
+            self.2 * other.0 - self.0 * other.2,
+            self.0 * other.1 - self.1 * other.0,
+        )
+    }
+
+    /// Exact for integers, unlike `length`.
+    pub fn length_squared(self) -> T {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> f64 {
+        let (x, y, z) = (self.0.to_f64(), self.1.to_f64(), self.2.to_f64());
+        (x * x + y * y + z * z).sqrt()
+    }
+
+    pub fn distance(self, other: Point<T>) -> f64 {
+        (self - other).length()
+    }
+}
+
+impl<T: Float> Point<T> {
+    /// The same direction with a length of one, or `None` for the zero vector.
+    pub fn normalize(self) -> Option<Point<T>> {
+        let length = self.length_squared().sqrt();
+        if length == T::ZERO {
+            None
+        } else {
+            Some(self / length)
+        }
+    }
+}
+
+impl<T: Scalar> Add for Point<T> {
+    type Output = Point<T>;
+
+    fn add(self, other: Point<T>) -> Point<T> {
+        Point(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+impl<T: Scalar> AddAssign for Point<T> {
+    fn add_assign(&mut self, other: Point<T>) {
+        *self = *self + other;
+    }
+}
+
+impl<T: Scalar> Sub for Point<T> {
+    type Output = Point<T>;
+
+    fn sub(self, other: Point<T>) -> Point<T> {
+        Point(self.0 - other.0, self.1 - other.1, self.2 - other.2)
+    }
+}
+
+impl<T: Scalar> Neg for Point<T> {
+    type Output = Point<T>;
+
+    fn neg(self) -> Point<T> {
+        Point(-self.0, -self.1, -self.2)
+    }
+}
+
+impl<T: Scalar> Mul<T> for Point<T> {
+    type Output = Point<T>;
+
+    fn mul(self, k: T) -> Point<T> {
+        Point(self.0 * k, self.1 * k, self.2 * k)
+    }
+}
+
+/// Integer points divide like their integers, rounding toward zero.
+impl<T: Scalar> Div<T> for Point<T> {
+    type Output = Point<T>;
+
+    fn div(self, k: T) -> Point<T> {
+        Point(self.0 / k, self.1 / k, self.2 / k)
+    }
+}
+
+impl<T: Scalar> Sum for Point<T> {
+    fn sum<I: Iterator<Item = Point<T>>>(points: I) -> Point<T> {
+        points.fold(Point::origin(), Add::add)
+    }
+}
+
+impl<'a, T: Scalar> Sum<&'a Point<T>> for Point<T> {
+    fn sum<I: Iterator<Item = &'a Point<T>>>(points: I) -> Point<T> {
+        points.copied().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+
+    const CASES: usize = 1000;
+
+    /// Small enough that a triple product can't overflow.
+    fn random_point(rng: &mut XorShift) -> Point<i64> {
+        Point(rng.range(-1000, 1000), rng.range(-1000, 1000), rng.range(-1000, 1000))
+    }
+
+    fn random_float_point(rng: &mut XorShift) -> Point<f64> {
+        let Point(x, y, z) = random_point(rng);
+        Point(x as f64 / 7.0, y as f64 / 7.0, z as f64 / 7.0)
+    }
+
+    fn triples(seed: u64) -> impl Iterator<Item = (Point<i64>, Point<i64>, Point<i64>)> {
+        let mut rng = XorShift::new(seed);
+        (0..CASES).map(move |_| (random_point(&mut rng), random_point(&mut rng), random_point(&mut rng)))
+    }
+
+    #[test]
+    fn addition_is_a_group() {
+        let zero = Point::origin();
+        for (a, b, c) in triples(1) {
+            assert_eq!(a + b, b + a);
+            assert_eq!((a + b) + c, a + (b + c));
+            assert_eq!(a + zero, a);
+            assert_eq!(a + -a, zero);
+            assert_eq!(a - b, a + -b);
+            assert_eq!(-(-a), a);
+
+            let mut sum = a;
+            sum += b;
+            assert_eq!(sum, a + b);
+        }
+    }
+
+    #[test]
+    fn scaling_distributes() {
+        let mut rng = XorShift::new(2);
+        for (a, b, _) in triples(2) {
+            let (j, k) = (rng.range(-50, 50), rng.range(-50, 50));
+            assert_eq!((a + b) * k, a * k + b * k);
+            assert_eq!(a * (j + k), a * j + a * k);
+            assert_eq!(a * (j * k), (a * j) * k);
+            assert_eq!(k * a, a * k);
+            assert_eq!(a * 1, a);
+            if k != 0 {
+                assert_eq!((a * k) / k, a);
+            }
+        }
+        assert_eq!(Point(7, -7, 8) / 2, Point(3, -3, 4));
+    }
+
+    #[test]
+    fn dot_and_cross_products() {
+        let zero = Point::origin();
+        for (a, b, c) in triples(3) {
+            assert_eq!(a.dot(b), b.dot(a));
+            assert_eq!(a.dot(b + c), a.dot(b) + a.dot(c));
+            assert_eq!(a.cross(b), -b.cross(a));
+            assert_eq!(a.cross(a), zero);
+            assert_eq!(a.cross(b + c), a.cross(b) + a.cross(c));
+            // the cross product is perpendicular to both
+            assert_eq!(a.dot(a.cross(b)), 0);
+            assert_eq!(b.dot(a.cross(b)), 0);
+            // scalar triple product is the same under rotation
+            assert_eq!(a.dot(b.cross(c)), b.dot(c.cross(a)));
+            // Lagrange's identity
+            assert_eq!(a.cross(b).length_squared(), a.length_squared() * b.length_squared() - a.dot(b) * a.dot(b));
+        }
+        assert_eq!(Point(1, 0, 0).cross(Point(0, 1, 0)), Point(0, 0, 1));
+    }
+
+    #[test]
+    fn lengths_and_distances() {
+        assert_eq!(Point(3, 4, 12).length(), 13.0);
+        assert_eq!(Point(1.0f32, 2.0, 2.0).length(), 3.0);
+        assert_eq!(Point(1, 1, 1).distance(Point(4, 5, 1)), 5.0);
+
+        let mut rng = XorShift::new(4);
+        for _ in 0..CASES {
+            let (a, b, c) = (random_float_point(&mut rng), random_float_point(&mut rng), random_float_point(&mut rng));
+            assert_eq!(a.distance(b), b.distance(a));
+            assert!(a.distance(c) <= a.distance(b) + b.distance(c) + 1e-9);
+            assert!(((a * 3.0).length() - a.length() * 3.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn normalizes_floats() {
+        assert_eq!(Point(0.0, 0.0, 0.0).normalize(), None);
+        assert_eq!(Point(0.0, -5.0, 0.0).normalize(), Some(Point(0.0, -1.0, 0.0)));
+
+        let mut rng = XorShift::new(5);
+        for _ in 0..CASES {
+            let a = random_float_point(&mut rng);
+            if let Some(unit) = a.normalize() {
+                assert!((unit.length() - 1.0).abs() < 1e-12);
+                // same direction
+                assert!((unit.dot(a) - a.length()).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn sums_iterators() {
+        let points: Vec<Point<i32>> = (0..10).map(|i| Point(i, 2 * i, -i)).collect();
+        assert_eq!(points.iter().sum::<Point<i32>>(), Point(45, 90, -45));
+        assert_eq!(points.into_iter().sum::<Point<i32>>(), Point(45, 90, -45));
+        assert_eq!(Vec::<Point<f64>>::new().into_iter().sum::<Point<f64>>(), Point::origin());
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use crate::rectangle::Rectangle;
+
+/// How many rectangles a node keeps before it splits.
+const NODE_CAPACITY: usize = 8;
+
+/// Handed out by `QuadTree::insert`, to name a rectangle later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Id(u64);
+
+/// A spatial index over rectangles anywhere on the `i32` grid.
+///
+/// Each rectangle lives in the smallest node that holds all of it, so a big
+/// rectangle sits near the root and small ones sink to the leaves. Empty
+/// rectangles are stored but cover nothing, so no query ever finds them.
+pub struct QuadTree {
+    root: Node,
+    rectangles: HashMap<Id, Rectangle>,
+    next_id: u64,
+}
+
+/// Edges of a node, wider than `i32` since rectangles reach past `i32::MAX` on the right.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    left: i64,
+    top: i64,
+    right: i64,
+    bottom: i64,
+}
+
+struct Node {
+    bounds: Bounds,
+    items: Vec<(Id, Rectangle)>,
+    children: Option<Box<[Node; 4]>>,
+}
+
+impl Default for QuadTree {
+    fn default() -> QuadTree {
+        QuadTree::new()
+    }
+}
+
+impl QuadTree {
+    pub fn new() -> QuadTree {
+        // a power-of-two square holding every possible rectangle,
+        // from i32::MIN to i32::MAX plus the widest u32
+        let left = i64::from(i32::MIN);
+        let side = 1i64 << 33;
+        let bounds = Bounds { left, top: left, right: left + side, bottom: left + side };
+        QuadTree { root: Node::new(bounds), rectangles: HashMap::new(), next_id: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rectangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rectangles.is_empty()
+    }
+
+    pub fn insert(&mut self, rectangle: Rectangle) -> Id {
+        let id = Id(self.next_id);
+        self.next_id += 1;
+        self.rectangles.insert(id, rectangle);
+        self.root.insert(id, rectangle);
+        id
+    }
+
+    pub fn get(&self, id: Id) -> Option<&Rectangle> {
+        self.rectangles.get(&id)
+    }
+
+    /// Takes a rectangle out of the index, handing it back if it was there.
+    pub fn remove(&mut self, id: Id) -> Option<Rectangle> {
+        let rectangle = self.rectangles.remove(&id)?;
+        self.root.remove(id, &rectangle);
+        Some(rectangle)
+    }
+
+    /// Every rectangle sharing at least one cell with `region`, in insertion order.
+    pub fn query(&self, region: &Rectangle) -> Vec<Id> {
+        let mut found = Vec::new();
+        self.root.query(&Bounds::of(region), &mut |id, rectangle| {
+            if rectangle.intersects(region) {
+                found.push(id);
+            }
+        });
+        found.sort();
+        found
+    }
+
+    /// Every rectangle covering the cell at `(x, y)`, in insertion order.
+    pub fn query_point(&self, x: i32, y: i32) -> Vec<Id> {
+        self.query(&Rectangle::new(x, y, 1, 1))
+    }
+
+    /// The rectangle with a cell closest to `(x, y)`, measured in straight
+    /// lines; the earliest inserted one wins a tie.
+    pub fn nearest(&self, x: i32, y: i32) -> Option<Id> {
+        let mut best = None;
+        self.root.nearest(i64::from(x), i64::from(y), &mut best);
+        best.map(|(_, id)| id)
+    }
+}
+
+impl Bounds {
+    fn of(rectangle: &Rectangle) -> Bounds {
+        Bounds { left: rectangle.left(), top: rectangle.top(), right: rectangle.right(), bottom: rectangle.bottom() }
+    }
+
+    fn holds(&self, rectangle: &Rectangle) -> bool {
+        self.left <= rectangle.left()
+            && rectangle.right() <= self.right
+            && self.top <= rectangle.top()
+            && rectangle.bottom() <= self.bottom
+    }
+
+    fn overlaps(&self, other: &Bounds) -> bool {
+        self.left < other.right && other.left < self.right && self.top < other.bottom && other.top < self.bottom
+    }
+
+    /// Squared distance from `(x, y)` to the nearest cell inside.
+    fn distance_squared(&self, x: i64, y: i64) -> u128 {
+        let dx = (self.left - x).max(x - (self.right - 1)).max(0) as u128;
+        let dy = (self.top - y).max(y - (self.bottom - 1)).max(0) as u128;
+        dx * dx + dy * dy
+    }
+
+    fn quarters(&self) -> [Bounds; 4] {
+        let middle_x = self.left + (self.right - self.left) / 2;
+        let middle_y = self.top + (self.bottom - self.top) / 2;
+        [
+            Bounds { right: middle_x, bottom: middle_y, ..*self },
+            Bounds { left: middle_x, bottom: middle_y, ..*self },
+            Bounds { right: middle_x, top: middle_y, ..*self },
+            Bounds { left: middle_x, top: middle_y, ..*self },
+        ]
+    }
+}
+
+impl Node {
+    fn new(bounds: Bounds) -> Node {
+        Node { bounds, items: Vec::new(), children: None }
+    }
+
+    /// The child that holds all of `rectangle`, if any does.
+    fn child_for(&mut self, rectangle: &Rectangle) -> Option<&mut Node> {
+        self.children.as_mut()?.iter_mut().find(|child| child.bounds.holds(rectangle))
+    }
+
+    fn insert(&mut self, id: Id, rectangle: Rectangle) {
+        if let Some(child) = self.child_for(&rectangle) {
+            return child.insert(id, rectangle);
+        }
+        self.items.push((id, rectangle));
+        if self.children.is_none() && self.items.len() > NODE_CAPACITY && self.bounds.right - self.bounds.left > 1 {
+            self.split();
+        }
+    }
+
+    fn split(&mut self) {
+        let [a, b, c, d] = self.bounds.quarters();
+        self.children = Some(Box::new([Node::new(a), Node::new(b), Node::new(c), Node::new(d)]));
+        for (id, rectangle) in std::mem::take(&mut self.items) {
+            self.insert(id, rectangle);
+        }
+    }
+
+    fn remove(&mut self, id: Id, rectangle: &Rectangle) {
+        match self.child_for(rectangle) {
+            Some(child) => child.remove(id, rectangle),
+            None => self.items.retain(|(item, _)| *item != id),
+        }
+        // fold leaves back in once they hold too little to be worth the descent
+        if self.children.as_ref().is_some_and(|children| children.iter().all(|child| child.children.is_none())) {
+            let held: usize = self.children.as_ref().unwrap().iter().map(|child| child.items.len()).sum();
+            if self.items.len() + held <= NODE_CAPACITY {
+                for child in self.children.take().unwrap().iter_mut() {
+                    self.items.append(&mut child.items);
+                }
+            }
+        }
+    }
+
+    fn query<F: FnMut(Id, &Rectangle)>(&self, region: &Bounds, visit: &mut F) {
+        for (id, rectangle) in &self.items {
+            visit(*id, rectangle);
+        }
+        for child in self.children.iter().flat_map(|children| children.iter()) {
+            if child.bounds.overlaps(region) {
+                child.query(region, visit);
+            }
+        }
+    }
+
+    fn nearest(&self, x: i64, y: i64, best: &mut Option<(u128, Id)>) {
+        for (id, rectangle) in &self.items {
+            if rectangle.is_empty() {
+                continue;
+            }
+            let candidate = (Bounds::of(rectangle).distance_squared(x, y), *id);
+            if best.is_none_or(|best| candidate < best) {
+                *best = Some(candidate);
+            }
+        }
+
+        let children = match &self.children {
+            Some(children) => children,
+            None => return,
+        };
+        let mut order: Vec<(u128, &Node)> =
+            children.iter().map(|child| (child.bounds.distance_squared(x, y), child)).collect();
+        order.sort_by_key(|(distance, _)| *distance);
+        for (distance, child) in order {
+            // equal distances still get a look, a tie might go to an older rectangle
+            if best.is_some_and(|(best, _)| distance > best) {
+                break;
+            }
+            child.nearest(x, y, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+    use std::time::Instant;
+
+    fn random_rectangle(rng: &mut XorShift, spread: i64, size: i64) -> Rectangle {
+        Rectangle::new(
+            rng.range(-spread, spread) as i32,
+            rng.range(-spread, spread) as i32,
+            rng.range(0, size) as u32,
+            rng.range(0, size) as u32,
+        )
+    }
+
+    fn linear_query(all: &[(Id, Rectangle)], region: &Rectangle) -> Vec<Id> {
+        all.iter().filter(|(_, rectangle)| rectangle.intersects(region)).map(|(id, _)| *id).collect()
+    }
+
+    fn linear_nearest(all: &[(Id, Rectangle)], x: i32, y: i32) -> Option<Id> {
+        all.iter()
+            .filter(|(_, rectangle)| !rectangle.is_empty())
+            .map(|(id, rectangle)| (Bounds::of(rectangle).distance_squared(i64::from(x), i64::from(y)), *id))
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    #[test]
+    fn finds_what_was_inserted() {
+        let mut tree = QuadTree::new();
+        let a = tree.insert(Rectangle::new(0, 0, 10, 10));
+        let b = tree.insert(Rectangle::new(5, 5, 10, 10));
+        let c = tree.insert(Rectangle::new(100, 100, 1, 1));
+
+        assert_eq!(tree.query(&Rectangle::new(8, 8, 1, 1)), [a, b]);
+        assert_eq!(tree.query_point(12, 12), [b]);
+        assert_eq!(tree.query_point(10, 0), []);
+        assert_eq!(tree.nearest(90, 90), Some(c));
+        assert_eq!(tree.nearest(-3, 4), Some(a));
+
+        assert_eq!(tree.remove(a), Some(Rectangle::new(0, 0, 10, 10)));
+        assert_eq!(tree.remove(a), None);
+        assert_eq!(tree.query_point(1, 1), []);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn empty_rectangles_are_never_found() {
+        let mut tree = QuadTree::new();
+        let empty = tree.insert(Rectangle::new(0, 0, 0, 5));
+        assert_eq!(tree.query(&Rectangle::new(-10, -10, 20, 20)), []);
+        assert_eq!(tree.nearest(0, 0), None);
+        assert_eq!(tree.get(empty), Some(&Rectangle::new(0, 0, 0, 5)));
+    }
+
+    #[test]
+    fn handles_the_edges_of_the_grid() {
+        let mut tree = QuadTree::new();
+        let far = tree.insert(Rectangle::new(i32::MAX, i32::MAX, u32::MAX, u32::MAX));
+        let low = tree.insert(Rectangle::new(i32::MIN, i32::MIN, 1, 1));
+        assert_eq!(tree.query_point(i32::MAX, i32::MAX), [far]);
+        assert_eq!(tree.query_point(i32::MIN, i32::MIN), [low]);
+        // i32::MAX is one closer to zero than i32::MIN
+        assert_eq!(tree.nearest(0, 0), Some(far));
+    }
+
+    #[test]
+    fn agrees_with_a_linear_scan() {
+        let mut rng = XorShift::new(16);
+        let mut tree = QuadTree::new();
+        let mut all = Vec::new();
+
+        for round in 0..2000 {
+            if !all.is_empty() && rng.range(0, 4) == 0 {
+                let (id, rectangle) = all.remove(rng.range(0, all.len() as i64) as usize);
+                assert_eq!(tree.remove(id), Some(rectangle));
+            } else {
+                // mostly small ones, now and then one spanning many nodes
+                let size = if round % 50 == 0 { 400 } else { 20 };
+                let rectangle = random_rectangle(&mut rng, 200, size);
+                all.push((tree.insert(rectangle), rectangle));
+            }
+
+            let region = random_rectangle(&mut rng, 220, 60);
+            assert_eq!(tree.query(&region), linear_query(&all, &region), "round {}", round);
+            let (x, y) = (rng.range(-250, 250) as i32, rng.range(-250, 250) as i32);
+            assert_eq!(tree.query_point(x, y), linear_query(&all, &Rectangle::new(x, y, 1, 1)));
+            assert_eq!(tree.nearest(x, y), linear_nearest(&all, x, y), "round {} at ({}, {})", round, x, y);
+        }
+        assert_eq!(tree.len(), all.len());
+    }
+
+    #[test]
+    fn shrinks_back_after_removals() {
+        let mut tree = QuadTree::new();
+        let ids: Vec<_> = (0..100).map(|i| tree.insert(Rectangle::new(i * 3, i * 7, 2, 2))).collect();
+        assert!(tree.root.children.is_some());
+        for id in ids {
+            tree.remove(id);
+        }
+        assert!(tree.root.children.is_none());
+        assert!(tree.is_empty());
+    }
+
+    /// `cargo test --release -- --ignored --nocapture benchmark`
+    #[test]
+    #[ignore]
+    fn benchmark_100k() {
+        let mut rng = XorShift::new(100_000);
+        let all: Vec<Rectangle> = (0..100_000).map(|_| random_rectangle(&mut rng, 100_000, 200)).collect();
+        let regions: Vec<Rectangle> = (0..1000).map(|_| random_rectangle(&mut rng, 100_000, 2000)).collect();
+
+        let started = Instant::now();
+        let mut tree = QuadTree::new();
+        let ids: Vec<(Id, Rectangle)> = all.iter().map(|rectangle| (tree.insert(*rectangle), *rectangle)).collect();
+        println!("insert 100k: {:?}", started.elapsed());
+
+        let started = Instant::now();
+        let indexed: Vec<_> = regions.iter().map(|region| tree.query(region)).collect();
+        println!("1000 region queries, quadtree: {:?}", started.elapsed());
+        let started = Instant::now();
+        let scanned: Vec<_> = regions.iter().map(|region| linear_query(&ids, region)).collect();
+        println!("1000 region queries, linear scan: {:?}", started.elapsed());
+        assert_eq!(indexed, scanned);
+
+        let started = Instant::now();
+        let indexed: Vec<_> = regions.iter().map(|region| tree.nearest(region.x, region.y)).collect();
+        println!("1000 nearest, quadtree: {:?}", started.elapsed());
+        let started = Instant::now();
+        let scanned: Vec<_> = regions.iter().map(|region| linear_nearest(&ids, region.x, region.y)).collect();
+        println!("1000 nearest, linear scan: {:?}", started.elapsed());
+        assert_eq!(indexed, scanned);
+
+        let started = Instant::now();
+        for (id, _) in ids {
+            tree.remove(id);
+        }
+        println!("remove 100k: {:?}", started.elapsed());
+        assert!(tree.is_empty());
+    }
+}
@@ -0,0 +1,336 @@
+use std::convert::TryFrom;
+
+/// An axis-aligned rectangle on an integer grid.
+/// It covers the cells from `(x, y)` up to but not including
+/// `(x + width, y + height)`, so a rectangle with no width or
+/// height covers nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rectangle {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Rectangle {
+        Rectangle { x, y, width, height }
+    }
+
+    /// A square with its corner at the origin.
+    pub fn square(size: u32) -> Rectangle {
+        Rectangle::new(0, 0, size, size)
+    }
+
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+
+    pub fn cycle(&self) -> u64 {
+        (u64::from(self.width) + u64::from(self.height)) * 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn left(&self) -> i64 {
+        i64::from(self.x)
+    }
+
+    pub fn top(&self) -> i64 {
+        i64::from(self.y)
+    }
+
+    /// First column past the rectangle. Wider than `i32` so it can't overflow.
+    pub fn right(&self) -> i64 {
+        self.left() + i64::from(self.width)
+    }
+
+    /// First row past the rectangle.
+    pub fn bottom(&self) -> i64 {
+        self.top() + i64::from(self.height)
+    }
+
+    /// Whether `other` would fit inside, wherever either of them is.
+    /// Equal sizes fit.
+    pub fn can_hold(&self, other: &Rectangle) -> bool {
+        self.width >= other.width && self.height >= other.height
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        let (x, y) = (i64::from(x), i64::from(y));
+        self.left() <= x && x < self.right() && self.top() <= y && y < self.bottom()
+    }
+
+    /// Whether `other` lies within this rectangle, edges allowed to touch.
+    /// An empty `other` is contained wherever its corners are inside or on the edge.
+    pub fn contains_rect(&self, other: &Rectangle) -> bool {
+        self.left() <= other.left()
+            && other.right() <= self.right()
+            && self.top() <= other.top()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Whether the two share at least one cell. Touching edges don't count.
+    pub fn intersects(&self, other: &Rectangle) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.left() < other.right()
+            && other.left() < self.right()
+            && self.top() < other.bottom()
+            && other.top() < self.bottom()
+    }
+
+    /// The cells both cover, or `None` if there are none.
+    pub fn intersection(&self, other: &Rectangle) -> Option<Rectangle> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let left = self.left().max(other.left());
+        let top = self.top().max(other.top());
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Some(Rectangle::from_edges(left, top, right, bottom))
+    }
+
+    /// Cells covered by both, which is 0 when they don't overlap.
+    pub fn overlap_area(&self, other: &Rectangle) -> u64 {
+        self.intersection(other).map_or(0, |overlap| overlap.area())
+    }
+
+    /// The smallest rectangle covering both.
+    /// An empty rectangle covers nothing, so it doesn't stretch the result.
+    ///
+    /// # Panics
+    ///
+    /// If the result is more than `u32::MAX` wide or high.
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        if other.is_empty() {
+            return *self;
+        }
+        if self.is_empty() {
+            return *other;
+        }
+        let left = self.left().min(other.left());
+        let top = self.top().min(other.top());
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rectangle::from_edges(left, top, right, bottom)
+    }
+
+    /// The smallest rectangle covering all of `rectangles`, or `None` if they cover nothing.
+    pub fn bounding_box<'a, I>(rectangles: I) -> Option<Rectangle>
+    where
+        I: IntoIterator<Item = &'a Rectangle>,
+    {
+        rectangles
+            .into_iter()
+            .filter(|rectangle| !rectangle.is_empty())
+            .fold(None, |bounds: Option<Rectangle>, rectangle| {
+                Some(bounds.map_or(*rectangle, |bounds| bounds.union(rectangle)))
+            })
+    }
+
+    /// The same rectangle moved by `(dx, dy)`.
+    ///
+    /// # Panics
+    ///
+    /// If the corner would leave the `i32` range.
+    pub fn translate(&self, dx: i32, dy: i32) -> Rectangle {
+        let x = self.x.checked_add(dx).expect("translated out of range");
+        let y = self.y.checked_add(dy).expect("translated out of range");
+        Rectangle { x, y, ..*self }
+    }
+
+    /// Scales position and size by `factor` around the origin, so every
+    /// cell becomes a `factor` by `factor` block.
+    ///
+    /// # Panics
+    ///
+    /// If the result is out of range.
+    pub fn scale(&self, factor: u32) -> Rectangle {
+        let factor = i64::from(factor);
+        Rectangle::from_edges(
+            self.left() * factor,
+            self.top() * factor,
+            self.right() * factor,
+            self.bottom() * factor,
+        )
+    }
+
+    fn from_edges(left: i64, top: i64, right: i64, bottom: i64) -> Rectangle {
+        Rectangle {
+            x: i32::try_from(left).expect("rectangle out of range"),
+            y: i32::try_from(top).expect("rectangle out of range"),
+            width: u32::try_from(right - left).expect("rectangle too large"),
+            height: u32::try_from(bottom - top).expect("rectangle too large"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+    use std::collections::BTreeSet;
+
+    const CASES: usize = 500;
+
+    /// Small rectangles near the origin, empty ones included, so they
+    /// overlap, touch and miss each other often.
+    fn random_rectangle(rng: &mut XorShift) -> Rectangle {
+        Rectangle::new(
+            rng.range(-6, 6) as i32,
+            rng.range(-6, 6) as i32,
+            rng.range(0, 8) as u32,
+            rng.range(0, 8) as u32,
+        )
+    }
+
+    /// Every cell the rectangle covers, the slow way.
+    fn cells(rectangle: &Rectangle) -> BTreeSet<(i32, i32)> {
+        let mut cells = BTreeSet::new();
+        for x in rectangle.x..rectangle.x + rectangle.width as i32 {
+            for y in rectangle.y..rectangle.y + rectangle.height as i32 {
+                cells.insert((x, y));
+            }
+        }
+        cells
+    }
+
+    fn bounds_of(cells: &BTreeSet<(i32, i32)>) -> Option<Rectangle> {
+        let left = cells.iter().map(|cell| cell.0).min()?;
+        let right = cells.iter().map(|cell| cell.0).max()?;
+        let top = cells.iter().map(|cell| cell.1).min()?;
+        let bottom = cells.iter().map(|cell| cell.1).max()?;
+        Some(Rectangle::new(left, top, (right - left + 1) as u32, (bottom - top + 1) as u32))
+    }
+
+    fn pairs(seed: u64) -> impl Iterator<Item = (Rectangle, Rectangle)> {
+        let mut rng = XorShift::new(seed);
+        (0..CASES).map(move |_| (random_rectangle(&mut rng), random_rectangle(&mut rng)))
+    }
+
+    #[test]
+    fn larger_can_hold_small() {
+        let rect1 = Rectangle::new(0, 0, 30, 50);
+        let rect2 = Rectangle::new(0, 0, 40, 51);
+
+        assert!(rect2.can_hold(&rect1));
+        assert!(!rect1.can_hold(&rect2));
+    }
+
+    #[test]
+    fn equal_sizes_fit() {
+        let rect = Rectangle::new(3, 4, 30, 50);
+        assert!(rect.can_hold(&Rectangle::new(-10, 7, 30, 50)));
+        assert!(rect.contains_rect(&rect));
+    }
+
+    #[test]
+    fn area_counts_cells() {
+        for (a, _) in pairs(1) {
+            assert_eq!(a.area(), cells(&a).len() as u64, "{:?}", a);
+            assert_eq!(a.is_empty(), cells(&a).is_empty(), "{:?}", a);
+        }
+    }
+
+    #[test]
+    fn contains_point_matches_cells() {
+        for (a, _) in pairs(2) {
+            let covered = cells(&a);
+            for x in -10..16 {
+                for y in -10..16 {
+                    assert_eq!(a.contains_point(x, y), covered.contains(&(x, y)), "{:?} at ({}, {})", a, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersection_matches_cells() {
+        for (a, b) in pairs(3) {
+            let shared: BTreeSet<_> = cells(&a).intersection(&cells(&b)).copied().collect();
+            assert_eq!(a.intersects(&b), !shared.is_empty(), "{:?} {:?}", a, b);
+            assert_eq!(a.intersection(&b).map(|overlap| cells(&overlap)).unwrap_or_default(), shared);
+            assert_eq!(a.overlap_area(&b), shared.len() as u64, "{:?} {:?}", a, b);
+            assert_eq!(a.intersection(&b), b.intersection(&a));
+        }
+    }
+
+    #[test]
+    fn union_is_the_bounding_box_of_the_cells() {
+        for (a, b) in pairs(4) {
+            let all: BTreeSet<_> = cells(&a).union(&cells(&b)).copied().collect();
+            match bounds_of(&all) {
+                Some(bounds) => {
+                    assert_eq!(a.union(&b), bounds, "{:?} {:?}", a, b);
+                    assert_eq!(Rectangle::bounding_box(&[a, b]), Some(bounds));
+                },
+                None => assert_eq!(Rectangle::bounding_box(&[a, b]), None),
+            }
+        }
+    }
+
+    #[test]
+    fn bounding_box_covers_many() {
+        let mut rng = XorShift::new(5);
+        for _ in 0..50 {
+            let rectangles: Vec<_> = (0..rng.range(0, 10)).map(|_| random_rectangle(&mut rng)).collect();
+            let all = rectangles.iter().flat_map(cells).collect();
+            assert_eq!(Rectangle::bounding_box(&rectangles), bounds_of(&all));
+        }
+    }
+
+    #[test]
+    fn contains_rect_matches_cells() {
+        for (a, b) in pairs(6) {
+            if !b.is_empty() {
+                assert_eq!(a.contains_rect(&b), cells(&b).is_subset(&cells(&a)), "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn can_hold_means_some_position_fits() {
+        for (a, b) in pairs(7) {
+            let fits = (a.x - 8..=a.x + 8)
+                .any(|x| (a.y - 8..=a.y + 8).any(|y| a.contains_rect(&Rectangle { x, y, ..b })));
+            assert_eq!(a.can_hold(&b), fits, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn translate_moves_every_cell() {
+        let mut rng = XorShift::new(8);
+        for (a, _) in pairs(8) {
+            let (dx, dy) = (rng.range(-20, 20) as i32, rng.range(-20, 20) as i32);
+            let moved: BTreeSet<_> = cells(&a).iter().map(|&(x, y)| (x + dx, y + dy)).collect();
+            assert_eq!(cells(&a.translate(dx, dy)), moved);
+        }
+    }
+
+    #[test]
+    fn scale_blows_up_every_cell() {
+        for (a, _) in pairs(9) {
+            for factor in 0..4 {
+                let scaled = a.scale(factor);
+                assert_eq!(scaled.area(), a.area() * u64::from(factor * factor));
+                if factor == 0 {
+                    continue;
+                }
+                let factor = factor as i32;
+                for &(x, y) in &cells(&scaled) {
+                    assert!(a.contains_point(x.div_euclid(factor), y.div_euclid(factor)), "{:?} x{}", a, factor);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "translated out of range")]
+    fn translate_refuses_to_wrap() {
+        Rectangle::new(i32::MAX, 0, 1, 1).translate(1, 0);
+    }
+}
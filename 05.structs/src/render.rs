@@ -0,0 +1,302 @@
+use std::fmt::Write;
+
+use crate::color::Color;
+use crate::rectangle::Rectangle;
+
+/// How dark the shading over an overlap is, on top of the shapes' own colors.
+const OVERLAP_SHADE: Color = Color::rgba(0, 0, 0, 64);
+
+/// Height of a label in the SVG, in pixels.
+const FONT_SIZE: u32 = 12;
+
+/// A rectangle as it should be drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shape {
+    pub rectangle: Rectangle,
+    pub label: String,
+    pub color: Color,
+}
+
+/// Shapes drawn in order, later ones on top.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scene {
+    pub shapes: Vec<Shape>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    pub fn add(&mut self, rectangle: Rectangle, label: &str, color: Color) -> &mut Scene {
+        self.shapes.push(Shape { rectangle, label: label.to_string(), color });
+        self
+    }
+
+    /// What gets drawn: `clip` if given, else everything in the scene.
+    fn frame(&self, clip: Option<Rectangle>) -> Option<Rectangle> {
+        let frame = clip.or_else(|| Rectangle::bounding_box(self.shapes.iter().map(|shape| &shape.rectangle)))?;
+        Some(frame).filter(|frame| !frame.is_empty())
+    }
+
+    /// The scene as a standalone SVG document showing `clip`, or the whole
+    /// scene, at `scale` pixels per unit. Overlaps are shaded darker, and the
+    /// labels go on top of everything.
+    ///
+    /// # Panics
+    ///
+    /// If there's no `clip` and the scene is more than `u32::MAX` wide or high.
+    pub fn to_svg(&self, clip: Option<Rectangle>, scale: u32) -> String {
+        let frame = self.frame(clip).unwrap_or_default();
+        let place = |rectangle: &Rectangle| rectangle.intersection(&frame).map(|visible| Pixels::new(&visible, &frame, scale));
+        let size = Pixels::new(&frame, &frame, scale);
+
+        let mut svg = String::new();
+        svg.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+            w = size.width,
+            h = size.height
+        );
+        for shape in &self.shapes {
+            if let Some(visible) = place(&shape.rectangle) {
+                svg_rect(&mut svg, &visible, shape.color);
+            }
+        }
+        for overlap in self.overlaps() {
+            if let Some(visible) = place(&overlap) {
+                svg_rect(&mut svg, &visible, OVERLAP_SHADE);
+            }
+        }
+        for shape in &self.shapes {
+            if let (Some(visible), false) = (place(&shape.rectangle), shape.label.is_empty()) {
+                let _ = writeln!(
+                    svg,
+                    "  <text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"{}\">{}</text>",
+                    visible.x + 2,
+                    visible.y + u64::from(FONT_SIZE),
+                    FONT_SIZE,
+                    escape_xml(&shape.label)
+                );
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// The scene as text, one character per `cell` by `cell` block of `clip`,
+    /// or of the whole scene. A block touched by one shape shows the first
+    /// letter of its label, by two `%` and by more `@`; a legend follows.
+    ///
+    /// # Panics
+    ///
+    /// If `cell` is zero, or if there's no `clip` and the scene is more than
+    /// `u32::MAX` wide or high.
+    pub fn to_ascii(&self, clip: Option<Rectangle>, cell: u32) -> String {
+        assert!(cell > 0, "cells must be at least one unit wide");
+        let frame = match self.frame(clip) {
+            Some(frame) => frame,
+            None => return String::new(),
+        };
+
+        let columns = frame.width.div_ceil(cell);
+        let rows = frame.height.div_ceil(cell);
+        let mut art = String::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                // in i64, as a frame reaching past i32::MAX has blocks no Rectangle can hold
+                let left = frame.left() + i64::from(column * cell);
+                let top = frame.top() + i64::from(row * cell);
+                let block = (left, top, (left + i64::from(cell)).min(frame.right()), (top + i64::from(cell)).min(frame.bottom()));
+                let mut touching = self.shapes.iter().filter(|shape| touches(&shape.rectangle, block));
+                art.push(match (touching.next(), touching.count()) {
+                    (None, _) => '.',
+                    (Some(shape), 0) => symbol(shape),
+                    (Some(_), 1) => '%',
+                    (Some(_), _) => '@',
+                });
+            }
+            art.push('\n');
+        }
+
+        for shape in &self.shapes {
+            let r = &shape.rectangle;
+            let _ = writeln!(art, "{} {} {}x{} at ({}, {}) {}", symbol(shape), shape.label, r.width, r.height, r.x, r.y, shape.color);
+        }
+        art
+    }
+
+    /// Where each pair of shapes overlaps.
+    fn overlaps(&self) -> Vec<Rectangle> {
+        let mut overlaps = Vec::new();
+        for (i, a) in self.shapes.iter().enumerate() {
+            for b in &self.shapes[i + 1..] {
+                overlaps.extend(a.rectangle.intersection(&b.rectangle));
+            }
+        }
+        overlaps
+    }
+}
+
+/// Whether `rectangle` shares a cell with the one between the `(left, top,
+/// right, bottom)` edges.
+fn touches(rectangle: &Rectangle, (left, top, right, bottom): (i64, i64, i64, i64)) -> bool {
+    !rectangle.is_empty() && rectangle.left() < right && left < rectangle.right() && rectangle.top() < bottom && top < rectangle.bottom()
+}
+
+/// A rectangle in pixels, relative to the corner of the frame it's in.
+/// Any rectangle at any scale fits in a `u64`.
+struct Pixels {
+    x: u64,
+    y: u64,
+    width: u64,
+    height: u64,
+}
+
+impl Pixels {
+    /// `rectangle`, which lies within `frame`, at `scale` pixels per unit.
+    fn new(rectangle: &Rectangle, frame: &Rectangle, scale: u32) -> Pixels {
+        let scale = u64::from(scale);
+        Pixels {
+            x: (rectangle.left() - frame.left()) as u64 * scale,
+            y: (rectangle.top() - frame.top()) as u64 * scale,
+            width: u64::from(rectangle.width) * scale,
+            height: u64::from(rectangle.height) * scale,
+        }
+    }
+}
+
+/// What marks a shape in ASCII art.
+fn symbol(shape: &Shape) -> char {
+    shape.label.chars().find(|c| c.is_ascii_graphic()).unwrap_or('#')
+}
+
+fn svg_rect(svg: &mut String, rectangle: &Pixels, color: Color) {
+    let _ = write!(
+        svg,
+        "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"",
+        rectangle.x,
+        rectangle.y,
+        rectangle.width,
+        rectangle.height,
+        Color { a: 255, ..color }
+    );
+    if color.a != 255 {
+        let _ = write!(svg, " fill-opacity=\"{}\"", opacity(color.a));
+    }
+    svg.push_str("/>\n");
+}
+
+/// `alpha` as a fraction, with no more digits than it takes.
+fn opacity(alpha: u8) -> String {
+    let text = format!("{:.3}", f64::from(alpha) / 255.0);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Compares `actual` with `testdata/render/<name>`.
+    /// Run with `UPDATE_GOLDEN=1` to write the current output there instead.
+    fn golden(name: &str, actual: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "testdata", "render", name].iter().collect();
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        assert_eq!(actual, expected, "{} changed, rerun with UPDATE_GOLDEN=1 if that's intended", name);
+    }
+
+    fn layout() -> Scene {
+        let mut scene = Scene::new();
+        scene
+            .add(Rectangle::new(0, 0, 30, 50), "rect1", Color::named("steelblue").unwrap())
+            .add(Rectangle::new(10, 10, 40, 51), "rect2", Color::rgba(255, 99, 71, 128))
+            .add(Rectangle::new(20, 30, 20, 10), "<footer & \"co\">", Color::named("gold").unwrap())
+            .add(Rectangle::new(60, 0, 10, 10), "", Color::BLACK);
+        scene
+    }
+
+    #[test]
+    fn renders_svg() {
+        golden("layout.svg", &layout().to_svg(None, 4));
+    }
+
+    #[test]
+    fn renders_clipped_svg() {
+        golden("layout-clipped.svg", &layout().to_svg(Some(Rectangle::new(15, 5, 30, 30)), 2));
+    }
+
+    #[test]
+    fn renders_ascii() {
+        golden("layout.txt", &layout().to_ascii(None, 5));
+    }
+
+    #[test]
+    fn renders_clipped_ascii() {
+        golden("layout-clipped.txt", &layout().to_ascii(Some(Rectangle::new(15, 5, 30, 30)), 2));
+    }
+
+    #[test]
+    fn renders_nothing_for_an_empty_scene() {
+        let scene = Scene::new();
+        assert_eq!(scene.to_ascii(None, 1), "");
+        assert!(scene.to_svg(None, 1).contains("width=\"0\" height=\"0\""));
+    }
+
+    #[test]
+    fn shades_by_overlap_count() {
+        let mut scene = Scene::new();
+        scene
+            .add(Rectangle::new(0, 0, 3, 1), "a", Color::BLACK)
+            .add(Rectangle::new(1, 0, 2, 1), "b", Color::BLACK)
+            .add(Rectangle::new(2, 0, 1, 1), "c", Color::BLACK);
+        assert!(scene.to_ascii(None, 1).starts_with("a%@\n"));
+    }
+
+    #[test]
+    fn renders_at_the_edges_of_the_grid() {
+        let mut scene = Scene::new();
+        scene
+            .add(Rectangle::new(i32::MIN, i32::MIN, 2, 2), "low", Color::BLACK)
+            .add(Rectangle::new(i32::MAX, 0, u32::MAX, 1), "wide", Color::BLACK);
+        let low = Some(Rectangle::new(i32::MIN, i32::MIN, 4, 4));
+        assert!(scene.to_svg(low, u32::MAX).contains("<rect x=\"0\" y=\"0\" width=\"8589934590\" height=\"8589934590\""));
+        assert!(scene.to_ascii(low, 2).starts_with("l.\n..\n"));
+
+        // blocks past i32::MAX still see the shapes reaching into them
+        let far = Some(Rectangle::new(i32::MAX - 1, 0, 6, 1));
+        assert!(scene.to_ascii(far, 2).starts_with("www\n"));
+        assert!(scene.to_svg(far, 3).contains("<rect x=\"3\" y=\"0\" width=\"15\" height=\"3\""));
+    }
+
+    #[test]
+    fn writes_short_opacities() {
+        assert_eq!(opacity(255), "1");
+        assert_eq!(opacity(0), "0");
+        assert_eq!(opacity(128), "0.502");
+        assert_eq!(opacity(51), "0.2");
+    }
+}
@@ -0,0 +1,22 @@
+/// xorshift64, so randomised tests are the same on every run and need no crates.
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Any seed works; zero, the one state xorshift can't leave, is swapped for another.
+    pub fn new(seed: u64) -> XorShift {
+        XorShift(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `low..high`.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        assert!(low < high, "empty range {}..{}", low, high);
+        low + (self.next_u64() % (high - low) as u64) as i64
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Longest name a user may have, in characters.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// First line of a saved registry, so an old file is never misread by a newer format.
+const HEADER: &str = "users v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    name: String,
+    email: String,
+    account: u64,
+    active: bool,
+}
+
+impl User {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Always lowercase.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn account(&self) -> u64 {
+        self.account
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// Why the registry refused a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserError {
+    InvalidName(String),
+    InvalidEmail(String),
+    /// Someone already signed up with this address.
+    DuplicateEmail(String),
+    /// Two saved users share an account number.
+    DuplicateAccount(u64),
+    UnknownAccount(u64),
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserError::InvalidName(reason) => write!(f, "invalid name: {}", reason),
+            UserError::InvalidEmail(reason) => write!(f, "invalid email: {}", reason),
+            UserError::DuplicateEmail(email) => write!(f, "{} is already registered", email),
+            UserError::DuplicateAccount(account) => write!(f, "account {} appears twice", account),
+            UserError::UnknownAccount(account) => write!(f, "no account {}", account),
+        }
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// Why a saved registry couldn't be read back.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// A line isn't in the format `save` writes. Lines count from 1.
+    Malformed { line: usize, reason: String },
+    /// A line reads fine but holds a user the registry wouldn't have created.
+    Invalid { line: usize, error: UserError },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "i/o error: {}", err),
+            LoadError::Malformed { line, reason } => write!(f, "line {}: {}", line, reason),
+            LoadError::Invalid { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Invalid { error, .. } => Some(error),
+            LoadError::Malformed { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+/// Every user, with unique emails and account numbers handed out in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserRegistry {
+    users: BTreeMap<u64, User>,
+    emails: HashMap<String, u64>,
+    next_account: u64,
+}
+
+impl UserRegistry {
+    pub fn new() -> UserRegistry {
+        UserRegistry { next_account: 1, ..UserRegistry::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Signs up a new, active user and returns their account number.
+    /// Account numbers are never reused, even after a save and load.
+    pub fn create(&mut self, name: &str, email: &str) -> Result<u64, UserError> {
+        let account = self.next_account;
+        self.add(User { name: check_name(name)?, email: check_email(email)?, account, active: true })?;
+        Ok(account)
+    }
+
+    pub fn by_account(&self, account: u64) -> Option<&User> {
+        self.users.get(&account)
+    }
+
+    /// Emails are matched without regard to case.
+    pub fn by_email(&self, email: &str) -> Option<&User> {
+        self.emails.get(&email.to_lowercase()).and_then(|account| self.users.get(account))
+    }
+
+    pub fn activate(&mut self, account: u64) -> Result<(), UserError> {
+        self.set_active(account, true)
+    }
+
+    pub fn deactivate(&mut self, account: u64) -> Result<(), UserError> {
+        self.set_active(account, false)
+    }
+
+    /// Users in account order.
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Writes one user per line: account, `active` or `inactive`, name and
+    /// email, separated by tabs. Backslashes, tabs and line breaks inside
+    /// a field are escaped, so any name survives the trip.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        for user in self.iter() {
+            let state = if user.active { "active" } else { "inactive" };
+            writeln!(writer, "{}\t{}\t{}\t{}", user.account, state, escape(&user.name), escape(&user.email))?;
+        }
+        writer.flush()
+    }
+
+    /// Reads back what `save` wrote, checking every user as `create` would.
+    pub fn load<R: BufRead>(reader: R) -> Result<UserRegistry, LoadError> {
+        let mut registry = UserRegistry::new();
+        let mut lines = reader.lines();
+        match lines.next().transpose()? {
+            Some(ref header) if header == HEADER => {},
+            _ => return Err(LoadError::Malformed { line: 1, reason: format!("expected {:?}", HEADER) }),
+        }
+
+        for (index, line) in lines.enumerate() {
+            let number = index + 2;
+            let malformed = |reason: String| LoadError::Malformed { line: number, reason };
+            let invalid = |error: UserError| LoadError::Invalid { line: number, error };
+
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 4 {
+                return Err(malformed(format!("expected 4 fields, found {}", fields.len())));
+            }
+            let account = fields[0].parse().map_err(|_| malformed(format!("bad account number {:?}", fields[0])))?;
+            let active = match fields[1] {
+                "active" => true,
+                "inactive" => false,
+                other => return Err(malformed(format!("bad state {:?}", other))),
+            };
+            let name = unescape(fields[2]).map_err(malformed)?;
+            let email = unescape(fields[3]).map_err(malformed)?;
+
+            let user = User {
+                name: check_name(&name).map_err(invalid)?,
+                email: check_email(&email).map_err(invalid)?,
+                account,
+                active,
+            };
+            registry.add(user).map_err(invalid)?;
+        }
+        Ok(registry)
+    }
+
+    fn add(&mut self, user: User) -> Result<(), UserError> {
+        if self.emails.contains_key(&user.email) {
+            return Err(UserError::DuplicateEmail(user.email));
+        }
+        if self.users.contains_key(&user.account) {
+            return Err(UserError::DuplicateAccount(user.account));
+        }
+        self.next_account = self.next_account.max(user.account.saturating_add(1));
+        self.emails.insert(user.email.clone(), user.account);
+        self.users.insert(user.account, user);
+        Ok(())
+    }
+
+    fn set_active(&mut self, account: u64, active: bool) -> Result<(), UserError> {
+        let user = self.users.get_mut(&account).ok_or(UserError::UnknownAccount(account))?;
+        user.active = active;
+        Ok(())
+    }
+}
+
+/// A name is up to `MAX_NAME_LEN` printable characters, not blank and not padded.
+fn check_name(name: &str) -> Result<String, UserError> {
+    let invalid = |reason: &str| Err(UserError::InvalidName(String::from(reason)));
+    if name.trim().is_empty() {
+        return invalid("empty");
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return invalid("too long");
+    }
+    if name.trim() != name {
+        return invalid("starts or ends with whitespace");
+    }
+    if name.chars().any(char::is_control) {
+        return invalid("contains control characters");
+    }
+    Ok(name.to_string())
+}
+
+/// A practical subset of RFC 5321: a dot-atom local part and a domain of
+/// at least two letters-digits-hyphens labels. Comes back lowercased.
+fn check_email(email: &str) -> Result<String, UserError> {
+    let invalid = |reason: &str| Err(UserError::InvalidEmail(format!("{:?} {}", email, reason)));
+    if email.len() > 254 {
+        return invalid("is too long");
+    }
+    let (local, domain) = match email.rfind('@') {
+        Some(at) => (&email[..at], &email[at + 1..]),
+        None => return invalid("has no @"),
+    };
+
+    if local.is_empty() || local.len() > 64 {
+        return invalid("needs 1 to 64 characters before the @");
+    }
+    let atom_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c);
+    if local.split('.').any(|atom| atom.is_empty() || !atom.chars().all(atom_char)) {
+        return invalid("has an unusable character or dot before the @");
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return invalid("needs a domain with a dot in it");
+    }
+    let good_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if !labels.iter().all(good_label) {
+        return invalid("has a malformed domain");
+    }
+    Ok(email.to_lowercase())
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => return Err(format!("unknown escape \\{}", other)),
+            None => return Err(String::from("dangling backslash")),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShift;
+
+    fn load(text: &str) -> Result<UserRegistry, LoadError> {
+        UserRegistry::load(text.as_bytes())
+    }
+
+    #[test]
+    fn hands_out_unique_accounts() {
+        let mut registry = UserRegistry::new();
+        let alex = registry.create("Alex", "alex@example.com").unwrap();
+        let hex = registry.create("Hex", "hex@example.com").unwrap();
+        assert_eq!((alex, hex), (1, 2));
+
+        assert_eq!(
+            registry.create("Alex again", "ALEX@Example.com"),
+            Err(UserError::DuplicateEmail(String::from("alex@example.com")))
+        );
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.create("Dee", "dee@example.com"), Ok(3));
+    }
+
+    #[test]
+    fn looks_users_up() {
+        let mut registry = UserRegistry::new();
+        let account = registry.create("Alex", "Alex@Example.COM").unwrap();
+        let user = registry.by_email("alex@EXAMPLE.com").unwrap();
+        assert_eq!((user.name(), user.email(), user.account()), ("Alex", "alex@example.com", account));
+        assert_eq!(registry.by_account(account), Some(user));
+        assert_eq!(registry.by_account(99), None);
+        assert_eq!(registry.by_email("nobody@example.com"), None);
+    }
+
+    #[test]
+    fn activates_and_deactivates() {
+        let mut registry = UserRegistry::new();
+        let account = registry.create("Alex", "alex@example.com").unwrap();
+        assert!(registry.by_account(account).unwrap().is_active());
+
+        registry.deactivate(account).unwrap();
+        assert!(!registry.by_account(account).unwrap().is_active());
+        registry.activate(account).unwrap();
+        assert!(registry.by_account(account).unwrap().is_active());
+        assert_eq!(registry.deactivate(7), Err(UserError::UnknownAccount(7)));
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let mut registry = UserRegistry::new();
+        for name in &["", "   ", " Alex", "Alex ", "line\nbreak", "tab\there", &"x".repeat(MAX_NAME_LEN + 1)] {
+            assert!(
+                matches!(registry.create(name, "a@example.com"), Err(UserError::InvalidName(_))),
+                "{:?}",
+                name
+            );
+        }
+        assert!(registry.create(&"é".repeat(MAX_NAME_LEN), "a@example.com").is_ok());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_emails() {
+        let mut registry = UserRegistry::new();
+        let bad = [
+            "",
+            "plain",
+            "@example.com",
+            "alex@",
+            "alex@localhost",
+            "alex@@example.com",
+            "alex@exa mple.com",
+            ".alex@example.com",
+            "al..ex@example.com",
+            "alex@-example.com",
+            "alex@example..com",
+            "al ex@example.com",
+        ];
+        for email in &bad {
+            let result = registry.create("Alex", email);
+            assert!(matches!(result, Err(UserError::InvalidEmail(_))), "{:?} gave {:?}", email, result);
+        }
+        for email in &["alex@example.com", "a.b+tag@sub.example.co", "o'brien@x-y.org"] {
+            assert!(registry.create("Alex", email).is_ok(), "{:?}", email);
+        }
+    }
+
+    #[test]
+    fn errors_describe_themselves() {
+        let error = UserRegistry::new().create("Alex", "nope").unwrap_err();
+        assert_eq!(error.to_string(), "invalid email: \"nope\" has no @");
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let mut registry = UserRegistry::new();
+        registry.create("Alex", "alex@example.com").unwrap();
+        let tricky = registry.create("C:\\Users\\tab \\t not a tab", "hex@example.com").unwrap();
+        registry.create("Zoë \"Z\" O'Neil", "zoe@example.com").unwrap();
+        registry.deactivate(tricky).unwrap();
+
+        let mut saved = Vec::new();
+        registry.save(&mut saved).unwrap();
+        let text = String::from_utf8(saved.clone()).unwrap();
+        assert!(text.contains("2\tinactive\tC:\\\\Users\\\\tab \\\\t not a tab\thex@example.com\n"), "{}", text);
+
+        let loaded = UserRegistry::load(&saved[..]).unwrap();
+        assert_eq!(loaded, registry);
+        let mut loaded = loaded;
+        assert_eq!(loaded.create("Next", "next@example.com"), Ok(4));
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        let mut rng = XorShift::new(18);
+        let alphabet = ['a', '\\', 't', 'n', '\t', '\n', '\r', ' ', 'é'];
+        for _ in 0..1000 {
+            let field: String = (0..rng.range(0, 12)).map(|_| alphabet[rng.range(0, 9) as usize]).collect();
+            let escaped = escape(&field);
+            assert!(!escaped.contains(['\t', '\n', '\r']), "{:?}", escaped);
+            assert_eq!(unescape(&escaped), Ok(field));
+        }
+    }
+
+    #[test]
+    fn refuses_broken_files() {
+        assert!(matches!(load(""), Err(LoadError::Malformed { line: 1, .. })));
+        assert!(matches!(load("users v2\n"), Err(LoadError::Malformed { line: 1, .. })));
+        assert!(load("users v1\n").unwrap().is_empty());
+
+        let broken = [
+            "1\tactive\tAlex",
+            "x\tactive\tAlex\talex@example.com",
+            "1\tasleep\tAlex\talex@example.com",
+            "1\tactive\tAl\\ex\talex@example.com",
+            "1\tactive\tAlex\\\talex@example.com",
+        ];
+        for line in &broken {
+            let result = load(&format!("users v1\n{}\n", line));
+            assert!(matches!(result, Err(LoadError::Malformed { line: 2, .. })), "{:?} gave {:?}", line, result);
+        }
+
+        let result = load("users v1\n1\tactive\tAlex\tnot-an-email\n");
+        assert!(matches!(result, Err(LoadError::Invalid { line: 2, error: UserError::InvalidEmail(_) })));
+        let result = load("users v1\n1\tactive\tAlex\ta@example.com\n1\tactive\tBo\tb@example.com\n");
+        assert!(matches!(result, Err(LoadError::Invalid { line: 3, error: UserError::DuplicateAccount(1) })));
+        let result = load("users v1\n1\tactive\tAlex\ta@example.com\n2\tactive\tBo\tA@example.com\n");
+        assert!(matches!(result, Err(LoadError::Invalid { line: 3, error: UserError::DuplicateEmail(_) })));
+    }
+}
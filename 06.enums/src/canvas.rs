@@ -0,0 +1,470 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::message::Message;
+
+/// A color, one byte per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+  pub const BLACK: Rgb = Rgb(0, 0, 0);
+  pub const WHITE: Rgb = Rgb(255, 255, 255);
+}
+
+impl fmt::Display for Rgb {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "rgb({}, {}, {})", self.0, self.1, self.2)
+  }
+}
+
+/// Why a message was turned down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanvasError {
+  /// A channel outside `0..=255`.
+  BadColor(i32, i32, i32),
+  /// Nothing is accepted after `Quit`.
+  Finished,
+}
+
+impl fmt::Display for CanvasError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CanvasError::BadColor(r, g, b) => write!(f, "({}, {}, {}) is not a color", r, g, b),
+      CanvasError::Finished => write!(f, "the canvas is finished"),
+    }
+  }
+}
+
+impl std::error::Error for CanvasError {}
+
+/// What a message did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+  Moved { from: (i32, i32), to: (i32, i32) },
+  Recolored(Rgb),
+  Wrote { at: (i32, i32), text: String },
+  Finished,
+  Rejected(CanvasError),
+}
+
+/// One line of the execution log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+  /// Counts from 1.
+  pub step: usize,
+  pub message: Message,
+  pub outcome: Outcome,
+}
+
+impl fmt::Display for Entry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: ", self.step)?;
+    match &self.outcome {
+      Outcome::Moved { from, to } => write!(f, "move {:?} -> {:?}", from, to),
+      Outcome::Recolored(color) => write!(f, "stroke {}", color),
+      Outcome::Wrote { at, text } => write!(f, "write {:?} at {:?}", text, at),
+      Outcome::Finished => write!(f, "quit"),
+      Outcome::Rejected(err) => write!(f, "rejected {:?}: {}", self.message, err),
+    }
+  }
+}
+
+/// Something left on the canvas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mark {
+  Line { from: (i32, i32), to: (i32, i32), color: Rgb },
+  Text { at: (i32, i32), text: String, color: Rgb },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Drawing,
+  Finished,
+}
+
+/// Side of the block standing in for each character of text in a PPM.
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+
+/// A pen on a white canvas, driven by `Message`s.
+///
+/// The pen starts at the top left corner in black. `Move` draws a line to
+/// a point relative to the pen, `ChangeColor` sets the stroke, `Write`
+/// puts text at the pen without moving it and `Quit` finishes the drawing.
+/// Marks off the canvas are kept but clipped when rendered.
+#[derive(Debug, Clone)]
+pub struct Canvas {
+  width: u32,
+  height: u32,
+  pen: (i32, i32),
+  stroke: Rgb,
+  marks: Vec<Mark>,
+  state: State,
+  log: Vec<Entry>,
+}
+
+impl Canvas {
+  pub fn new(width: u32, height: u32) -> Canvas {
+    Canvas {
+      width,
+      height,
+      pen: (0, 0),
+      stroke: Rgb::BLACK,
+      marks: Vec::new(),
+      state: State::Drawing,
+      log: Vec::new(),
+    }
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn pen(&self) -> (i32, i32) {
+    self.pen
+  }
+
+  pub fn stroke(&self) -> Rgb {
+    self.stroke
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.state == State::Finished
+  }
+
+  /// Everything that has happened so far.
+  pub fn log(&self) -> &[Entry] {
+    &self.log
+  }
+
+  /// Carries out one message and logs it. Bad messages are logged as
+  /// rejected and leave the canvas as it was.
+  pub fn apply(&mut self, message: &Message) -> &Entry {
+    let outcome = match self.step(message) {
+      Ok(outcome) => outcome,
+      Err(err) => Outcome::Rejected(err),
+    };
+    self.log.push(Entry { step: self.log.len() + 1, message: message.clone(), outcome });
+    self.log.last().unwrap()
+  }
+
+  /// Carries out all of `messages`, returning the whole log.
+  pub fn run<'a, I>(&mut self, messages: I) -> &[Entry]
+  where
+    I: IntoIterator<Item = &'a Message>,
+  {
+    for message in messages {
+      self.apply(message);
+    }
+    &self.log
+  }
+
+  fn step(&mut self, message: &Message) -> Result<Outcome, CanvasError> {
+    if self.state == State::Finished {
+      return Err(CanvasError::Finished);
+    }
+    match message {
+      Message::Quit => {
+        self.state = State::Finished;
+        Ok(Outcome::Finished)
+      },
+      Message::Move { x, y } => {
+        let from = self.pen;
+        let to = (from.0.saturating_add(*x), from.1.saturating_add(*y));
+        self.marks.push(Mark::Line { from, to, color: self.stroke });
+        self.pen = to;
+        Ok(Outcome::Moved { from, to })
+      },
+      Message::Write(text) => {
+        let at = self.pen;
+        self.marks.push(Mark::Text { at, text: text.clone(), color: self.stroke });
+        Ok(Outcome::Wrote { at, text: text.clone() })
+      },
+      Message::ChangeColor(r, g, b) => {
+        let channel = |c: i32| u8::try_from(c).map_err(|_| CanvasError::BadColor(*r, *g, *b));
+        self.stroke = Rgb(channel(*r)?, channel(*g)?, channel(*b)?);
+        Ok(Outcome::Recolored(self.stroke))
+      },
+    }
+  }
+
+  /// The drawing as a plain (`P3`) PPM image, one pixel per unit. There's
+  /// no font, so each character of text is a solid block.
+  pub fn to_ppm(&self) -> String {
+    let mut pixels = vec![Rgb::WHITE; self.width as usize * self.height as usize];
+    let mut plot = |(x, y): (i32, i32), color: Rgb| {
+      if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+        pixels[y as usize * self.width as usize + x as usize] = color;
+      }
+    };
+    for mark in &self.marks {
+      match mark {
+        Mark::Line { from, to, color } => {
+          line(*from, *to, (self.width, self.height), |point| plot(point, *color));
+        },
+        Mark::Text { at, text, color } => {
+          for (i, c) in text.chars().enumerate() {
+            if c.is_whitespace() {
+              continue;
+            }
+            let left = at.0 as i64 + i as i64 * (GLYPH_WIDTH as i64 + 1);
+            for dy in 0..GLYPH_HEIGHT {
+              for dx in 0..GLYPH_WIDTH {
+                let (x, y) = (left + dx as i64, at.1 as i64 + dy as i64);
+                if let (Ok(x), Ok(y)) = (i32::try_from(x), i32::try_from(y)) {
+                  plot((x, y), *color);
+                }
+              }
+            }
+          }
+        },
+      }
+    }
+
+    let mut ppm = format!("P3\n{} {}\n255\n", self.width, self.height);
+    for row in pixels.chunks(self.width.max(1) as usize) {
+      let row: Vec<String> = row.iter().map(|Rgb(r, g, b)| format!("{} {} {}", r, g, b)).collect();
+      ppm.push_str(&row.join("  "));
+      ppm.push('\n');
+    }
+    ppm
+  }
+
+  /// The drawing as a standalone SVG document, one pixel per unit.
+  pub fn to_svg(&self) -> String {
+    let mut svg = String::new();
+    svg.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+      svg,
+      "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+      w = self.width,
+      h = self.height
+    );
+    svg.push_str("  <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    for mark in &self.marks {
+      let _ = match mark {
+        Mark::Line { from, to, color } => writeln!(
+          svg,
+          "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>",
+          from.0, from.1, to.0, to.1, color
+        ),
+        Mark::Text { at, text, color } => writeln!(
+          svg,
+          "  <text x=\"{}\" y=\"{}\" fill=\"{}\" dominant-baseline=\"hanging\">{}</text>",
+          at.0,
+          at.1,
+          color,
+          escape_xml(text)
+        ),
+      };
+    }
+    svg.push_str("</svg>\n");
+    svg
+  }
+}
+
+/// Visits the points from `from` to `to`, both included, that Bresenham's
+/// algorithm would draw, but only where they fall within `0..width` along
+/// the longer axis. Each point is worked out from its step instead of the
+/// one before, so a line costs the width or height it crosses, however far
+/// off the canvas its ends are.
+fn line(from: (i32, i32), to: (i32, i32), (width, height): (u32, u32), mut visit: impl FnMut((i32, i32))) {
+  // i64 so lines across the whole i32 range don't overflow
+  let (x0, y0) = (from.0 as i64, from.1 as i64);
+  let (dx, dy) = (to.0 as i64 - x0, to.1 as i64 - y0);
+  // the longer axis moves every step, the shorter one when Bresenham's error says so
+  let x_major = dx.abs() >= dy.abs();
+  let (long, short) = if x_major { (dx.abs(), dy.abs()) } else { (dy.abs(), dx.abs()) };
+  let (start, step, limit) = if x_major { (x0, dx.signum(), width as i64) } else { (y0, dy.signum(), height as i64) };
+
+  // the steps at which the longer axis is on the canvas
+  let (first, last) = match step {
+    1 => (-start, limit - 1 - start),
+    -1 => (start - (limit - 1), start),
+    _ if (0..limit).contains(&start) => (0, 0),
+    _ => (1, 0),
+  };
+  for i in first.max(0)..=last.min(long) {
+    // how far the shorter axis has moved by step `i`, in i128 as 2 * short * i can pass i64
+    let moved = if long == 0 { 0 } else { ((2 * short as i128 * i as i128 + long as i128) / (2 * long as i128)) as i64 };
+    let point = if x_major {
+      (x0 + dx.signum() * i, y0 + dy.signum() * moved)
+    } else {
+      (x0 + dx.signum() * moved, y0 + dy.signum() * i)
+    };
+    visit((point.0 as i32, point.1 as i32));
+  }
+}
+
+fn escape_xml(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn moves_the_pen_and_logs_it() {
+    let mut canvas = Canvas::new(10, 10);
+    let log = canvas.run(&[Message::Move { x: 3, y: 4 }, Message::Move { x: -1, y: 0 }]);
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].outcome, Outcome::Moved { from: (0, 0), to: (3, 4) });
+    assert_eq!(log[1].to_string(), "2: move (3, 4) -> (2, 4)");
+    assert_eq!(canvas.pen(), (2, 4));
+  }
+
+  #[test]
+  fn changes_color_within_range() {
+    let mut canvas = Canvas::new(1, 1);
+    assert_eq!(Message::ChangeColor(255, 0, 10).call(&mut canvas).outcome, Outcome::Recolored(Rgb(255, 0, 10)));
+    let entry = Message::ChangeColor(256, 0, -1).call(&mut canvas);
+    assert_eq!(entry.outcome, Outcome::Rejected(CanvasError::BadColor(256, 0, -1)));
+    assert_eq!(entry.to_string(), "2: rejected ChangeColor(256, 0, -1): (256, 0, -1) is not a color");
+    assert_eq!(canvas.stroke(), Rgb(255, 0, 10));
+  }
+
+  #[test]
+  fn writes_at_the_pen() {
+    let mut canvas = Canvas::new(10, 10);
+    canvas.run(&[Message::Move { x: 2, y: 1 }, Message::Write("hi".to_string())]);
+    assert_eq!(canvas.pen(), (2, 1));
+    assert_eq!(canvas.log()[1].to_string(), "2: write \"hi\" at (2, 1)");
+    assert!(canvas.to_svg().contains("<text x=\"2\" y=\"1\" fill=\"rgb(0, 0, 0)\" dominant-baseline=\"hanging\">hi</text>"));
+  }
+
+  #[test]
+  fn rejects_everything_after_quit() {
+    let mut canvas = Canvas::new(4, 4);
+    canvas.run(&[Message::Quit, Message::Move { x: 1, y: 1 }, Message::Quit]);
+    assert!(canvas.is_finished());
+    assert_eq!(canvas.pen(), (0, 0));
+    let outcomes: Vec<&Outcome> = canvas.log().iter().map(|entry| &entry.outcome).collect();
+    assert_eq!(
+      outcomes,
+      [&Outcome::Finished, &Outcome::Rejected(CanvasError::Finished), &Outcome::Rejected(CanvasError::Finished)]
+    );
+  }
+
+  #[test]
+  fn renders_ppm() {
+    let mut canvas = Canvas::new(3, 2);
+    canvas.run(&[
+      Message::ChangeColor(255, 0, 0),
+      Message::Move { x: 2, y: 0 },
+      Message::ChangeColor(0, 0, 255),
+      // off the canvas, clipped
+      Message::Move { x: 0, y: 5 },
+      Message::Quit,
+    ]);
+    assert_eq!(
+      canvas.to_ppm(),
+      "P3\n3 2\n255\n255 0 0  255 0 0  0 0 255\n255 255 255  255 255 255  0 0 255\n"
+    );
+  }
+
+  #[test]
+  fn renders_text_as_blocks_in_ppm() {
+    let mut canvas = Canvas::new(8, 5);
+    canvas.apply(&Message::Write("a b".to_string()));
+    let ppm = canvas.to_ppm();
+    let first_row = ppm.lines().nth(3).unwrap();
+    assert_eq!(first_row, "0 0 0  0 0 0  0 0 0  255 255 255  255 255 255  255 255 255  255 255 255  255 255 255");
+  }
+
+  #[test]
+  fn renders_svg() {
+    let mut canvas = Canvas::new(20, 10);
+    canvas.run(&[Message::Move { x: 5, y: 5 }, Message::ChangeColor(0, 128, 0), Message::Write("<&>".to_string())]);
+    let svg = canvas.to_svg();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains("width=\"20\" height=\"10\" viewBox=\"0 0 20 10\""));
+    assert!(svg.contains("<line x1=\"0\" y1=\"0\" x2=\"5\" y2=\"5\" stroke=\"rgb(0, 0, 0)\"/>"));
+    assert!(svg.contains("fill=\"rgb(0, 128, 0)\" dominant-baseline=\"hanging\">&lt;&amp;&gt;</text>"));
+    assert!(svg.ends_with("</svg>\n"));
+  }
+
+  /// Bresenham's algorithm step by step, which `line` must agree with.
+  fn bresenham(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    let mut points = Vec::new();
+    let (mut x, mut y) = (from.0 as i64, from.1 as i64);
+    let (x1, y1) = (to.0 as i64, to.1 as i64);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+    let mut err = dx + dy;
+    loop {
+      points.push((x as i32, y as i32));
+      if x == x1 && y == y1 {
+        return points;
+      }
+      let e2 = 2 * err;
+      if e2 >= dy {
+        err += dy;
+        x += sx;
+      }
+      if e2 <= dx {
+        err += dx;
+        y += sy;
+      }
+    }
+  }
+
+  fn points(from: (i32, i32), to: (i32, i32), size: (u32, u32)) -> Vec<(i32, i32)> {
+    let mut points = Vec::new();
+    line(from, to, size, |point| points.push(point));
+    points
+  }
+
+  #[test]
+  fn draws_every_point_of_a_line() {
+    let everywhere = (u32::MAX, u32::MAX);
+    assert_eq!(points((0, 0), (3, 1), everywhere), [(0, 0), (1, 0), (2, 1), (3, 1)]);
+    assert_eq!(points((2, 2), (2, 2), everywhere), [(2, 2)]);
+    assert_eq!(points((0, 0), (3, 6), everywhere), bresenham((0, 0), (3, 6)));
+    assert_eq!(points((0, 0), (-2, -2), (3, 3)), [(0, 0)]);
+    assert_eq!(points((5, 0), (-5, 2), (2, 9)), [(1, 1), (0, 1)]);
+  }
+
+  #[test]
+  fn clips_lines_to_the_points_bresenham_draws() {
+    let (width, height) = (5, 4);
+    for x0 in -6..9 {
+      for y0 in -5..8 {
+        for &(x1, y1) in &[(0, 0), (4, 3), (-3, 7), (8, -2), (2, -5), (7, 7), (-6, 1)] {
+          let expected: Vec<_> = bresenham((x0, y0), (x1, y1))
+            .into_iter()
+            .filter(|&(x, y)| if (x1 - x0).abs() >= (y1 - y0).abs() { (0..width).contains(&x) } else { (0..height).contains(&y) })
+            .collect();
+          assert_eq!(points((x0, y0), (x1, y1), (width as u32, height as u32)), expected, "{:?}", ((x0, y0), (x1, y1)));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn renders_lines_from_far_away_quickly() {
+    let mut canvas = Canvas::new(4, 4);
+    canvas.run(&[Message::Move { x: i32::MAX, y: 0 }, Message::Move { x: -i32::MAX, y: 1 }]);
+    let started = std::time::Instant::now();
+    let ppm = canvas.to_ppm();
+    assert!(started.elapsed() < std::time::Duration::from_millis(500), "{:?}", started.elapsed());
+    assert_eq!(ppm.lines().nth(3).unwrap(), "0 0 0  0 0 0  0 0 0  0 0 0");
+  }
+}
@@ -0,0 +1,173 @@
+//! Making change out of coins worth given amounts of cents.
+//!
+//! Denominations of zero are ignored. The work is proportional to the
+//! amount, times the number of denominations, or for a limited supply the
+//! log of each count, so these are meant for amounts you'd hand over in
+//! coins.
+
+/// The fewest coins adding up to `amount`, largest first, or `None` if it
+/// can't be made. Unlike always taking the biggest coin that fits, this
+/// also works for odd coin systems.
+pub fn min_coins(amount: u32, denominations: &[u32]) -> Option<Vec<u32>> {
+  let amount = amount as usize;
+  // fewest[a] is how many coins make a, last[a] the one added last
+  let mut fewest = vec![None; amount + 1];
+  let mut last = vec![0; amount + 1];
+  fewest[0] = Some(0u32);
+  for a in 1..=amount {
+    for &d in denominations {
+      let d_size = d as usize;
+      if d == 0 || d_size > a {
+        continue;
+      }
+      if let Some(n) = fewest[a - d_size] {
+        if fewest[a].is_none_or(|best| n + 1 < best) {
+          fewest[a] = Some(n + 1);
+          last[a] = d;
+        }
+      }
+    }
+  }
+  fewest[amount]?;
+
+  let mut coins = Vec::new();
+  let mut a = amount;
+  while a > 0 {
+    coins.push(last[a]);
+    a -= last[a] as usize;
+  }
+  coins.sort_unstable_by(|x, y| y.cmp(x));
+  Some(coins)
+}
+
+/// How many different sets of coins add up to `amount`, where order
+/// doesn't matter. Saturates at `u128::MAX`.
+pub fn ways(amount: u32, denominations: &[u32]) -> u128 {
+  let mut seen = Vec::new();
+  let mut ways = vec![0u128; amount as usize + 1];
+  ways[0] = 1;
+  for &d in denominations {
+    // the same denomination twice would count every set twice
+    if d == 0 || seen.contains(&d) {
+      continue;
+    }
+    seen.push(d);
+    for a in d as usize..ways.len() {
+      ways[a] = ways[a].saturating_add(ways[a - d as usize]);
+    }
+  }
+  ways[amount as usize]
+}
+
+/// Like `min_coins`, but only `count` of each `(denomination, count)` in
+/// `supply` can be used.
+pub fn min_coins_limited(amount: u32, supply: &[(u32, u32)]) -> Option<Vec<u32>> {
+  let amount = amount as usize;
+  // a count is split into bundles of 1, 2, 4, ... coins and what's left,
+  // which add up to any number up to it, and each bundle is used at most
+  // once, so a count of n costs about log n passes instead of n
+  let mut bundles = Vec::new();
+  for &(d, count) in supply {
+    if d == 0 {
+      continue;
+    }
+    // more than fit in the amount would never be used
+    let mut left = (count as usize).min(amount / d as usize);
+    let mut size = 1;
+    while left > 0 {
+      let k = size.min(left);
+      bundles.push((d, k));
+      left -= k;
+      size *= 2;
+    }
+  }
+
+  let mut fewest: Vec<Option<usize>> = vec![None; amount + 1];
+  fewest[0] = Some(0);
+  // used[i][a] is whether the best way to make a with the first i + 1
+  // bundles uses bundles[i]
+  let mut used = Vec::with_capacity(bundles.len());
+  for &(d, k) in &bundles {
+    let worth = d as usize * k;
+    let mut use_it = vec![false; amount + 1];
+    // downwards, so fewest[a - worth] doesn't already have this bundle in it
+    for a in (worth..=amount).rev() {
+      if let Some(n) = fewest[a - worth] {
+        if fewest[a].is_none_or(|best| n + k < best) {
+          fewest[a] = Some(n + k);
+          use_it[a] = true;
+        }
+      }
+    }
+    used.push(use_it);
+  }
+  fewest[amount]?;
+
+  let mut coins = Vec::new();
+  let mut a = amount;
+  for (use_it, &(d, k)) in used.iter().zip(&bundles).rev() {
+    if use_it[a] {
+      coins.extend(std::iter::repeat_n(d, k));
+      a -= d as usize * k;
+    }
+  }
+  coins.sort_unstable_by(|x, y| y.cmp(x));
+  Some(coins)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::coin::Denomination;
+
+  fn us() -> Vec<u32> {
+    Denomination::ALL.iter().map(|d| d.cents()).collect()
+  }
+
+  #[test]
+  fn makes_change_with_the_fewest_coins() {
+    assert_eq!(min_coins(0, &us()), Some(vec![]));
+    assert_eq!(min_coins(41, &us()), Some(vec![25, 10, 5, 1]));
+    assert_eq!(min_coins(190, &us()), Some(vec![100, 50, 25, 10, 5]));
+    // greedy would give 4 + 1 + 1
+    assert_eq!(min_coins(6, &[1, 3, 4]), Some(vec![3, 3]));
+    assert_eq!(min_coins(7, &[2, 4]), None);
+    assert_eq!(min_coins(3, &[0, 3]), Some(vec![3]));
+    assert_eq!(min_coins(3, &[]), None);
+  }
+
+  #[test]
+  fn counts_the_ways() {
+    assert_eq!(ways(0, &us()), 1);
+    assert_eq!(ways(10, &[1, 5, 10]), 4);
+    assert_eq!(ways(100, &[1, 5, 10, 25, 50]), 292);
+    assert_eq!(ways(100, &[1, 5, 10, 25, 50, 50, 0]), 292);
+    assert_eq!(ways(7, &[2, 4]), 0);
+    assert_eq!(ways(100_000, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]), u128::MAX);
+  }
+
+  #[test]
+  fn makes_change_from_a_limited_supply() {
+    let unlimited: Vec<(u32, u32)> = us().into_iter().map(|d| (d, u32::MAX)).collect();
+    for amount in 0..300 {
+      assert_eq!(min_coins_limited(amount, &unlimited), min_coins(amount, &us()), "{}", amount);
+    }
+    // no nickels, so 30 takes a quarter and five pennies or three dimes
+    assert_eq!(min_coins_limited(30, &[(1, 10), (10, 3), (25, 1)]), Some(vec![10, 10, 10]));
+    assert_eq!(min_coins_limited(30, &[(1, 5), (10, 2), (25, 1)]), Some(vec![25, 1, 1, 1, 1, 1]));
+    assert_eq!(min_coins_limited(30, &[(1, 4), (10, 2), (25, 1)]), None);
+    assert_eq!(min_coins_limited(6, &[(4, 1), (3, 1), (1, 2)]), Some(vec![4, 1, 1]));
+    assert_eq!(min_coins_limited(6, &[(4, 1), (3, 2), (1, 2)]), Some(vec![3, 3]));
+    assert_eq!(min_coins_limited(0, &[]), Some(vec![]));
+    assert_eq!(min_coins_limited(7, &[(0, 3), (2, 1), (5, 1)]), Some(vec![5, 2]));
+  }
+
+  #[test]
+  fn makes_change_from_a_large_supply_quickly() {
+    let started = std::time::Instant::now();
+    let coins = min_coins_limited(200_000, &[(1, 200_000), (5, 40_000)]).unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(5), "{:?}", started.elapsed());
+    assert_eq!(coins.len(), 40_000);
+    assert_eq!(min_coins_limited(200_000, &[(1, 3), (5, 39_999)]), None);
+  }
+}
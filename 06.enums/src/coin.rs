@@ -0,0 +1,178 @@
+use std::fmt;
+
+macro_rules! states {
+  ($($state:ident => $name:expr,)*) => {
+    /// The states, each with its own quarter.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum UsState {
+      $($state,)*
+    }
+
+    impl UsState {
+      /// Every state, in alphabetical order.
+      pub const ALL: [UsState; 50] = [$(UsState::$state,)*];
+
+      pub fn name(self) -> &'static str {
+        match self {
+          $(UsState::$state => $name,)*
+        }
+      }
+    }
+  };
+}
+
+states! {
+  Alabama => "Alabama",
+  Alaska => "Alaska",
+  Arizona => "Arizona",
+  Arkansas => "Arkansas",
+  California => "California",
+  Colorado => "Colorado",
+  Connecticut => "Connecticut",
+  Delaware => "Delaware",
+  Florida => "Florida",
+  Georgia => "Georgia",
+  Hawaii => "Hawaii",
+  Idaho => "Idaho",
+  Illinois => "Illinois",
+  Indiana => "Indiana",
+  Iowa => "Iowa",
+  Kansas => "Kansas",
+  Kentucky => "Kentucky",
+  Louisiana => "Louisiana",
+  Maine => "Maine",
+  Maryland => "Maryland",
+  Massachusetts => "Massachusetts",
+  Michigan => "Michigan",
+  Minnesota => "Minnesota",
+  Mississippi => "Mississippi",
+  Missouri => "Missouri",
+  Montana => "Montana",
+  Nebraska => "Nebraska",
+  Nevada => "Nevada",
+  NewHampshire => "New Hampshire",
+  NewJersey => "New Jersey",
+  NewMexico => "New Mexico",
+  NewYork => "New York",
+  NorthCarolina => "North Carolina",
+  NorthDakota => "North Dakota",
+  Ohio => "Ohio",
+  Oklahoma => "Oklahoma",
+  Oregon => "Oregon",
+  Pennsylvania => "Pennsylvania",
+  RhodeIsland => "Rhode Island",
+  SouthCarolina => "South Carolina",
+  SouthDakota => "South Dakota",
+  Tennessee => "Tennessee",
+  Texas => "Texas",
+  Utah => "Utah",
+  Vermont => "Vermont",
+  Virginia => "Virginia",
+  Washington => "Washington",
+  WestVirginia => "West Virginia",
+  Wisconsin => "Wisconsin",
+  Wyoming => "Wyoming",
+}
+
+impl fmt::Display for UsState {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// What a coin is worth, whatever is on its back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Denomination {
+  Penny,
+  Nickel,
+  Dime,
+  Quarter,
+  HalfDollar,
+  Dollar,
+}
+
+impl Denomination {
+  /// From smallest to largest.
+  pub const ALL: [Denomination; 6] = [
+    Denomination::Penny,
+    Denomination::Nickel,
+    Denomination::Dime,
+    Denomination::Quarter,
+    Denomination::HalfDollar,
+    Denomination::Dollar,
+  ];
+
+  pub fn cents(self) -> u32 {
+    match self {
+      Denomination::Penny => 1,
+      Denomination::Nickel => 5,
+      Denomination::Dime => 10,
+      Denomination::Quarter => 25,
+      Denomination::HalfDollar => 50,
+      Denomination::Dollar => 100,
+    }
+  }
+
+  /// The denomination worth exactly `cents`, if there is one.
+  pub fn from_cents(cents: u32) -> Option<Denomination> {
+    Denomination::ALL.iter().copied().find(|d| d.cents() == cents)
+  }
+}
+
+/// A coin in hand. Quarters say which state they're from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Coin {
+  Penny,
+  Nickel,
+  Dime,
+  Quarter(UsState),
+  HalfDollar,
+  Dollar,
+}
+
+impl Coin {
+  pub fn denomination(self) -> Denomination {
+    // match is exhaustive, adding a coin won't compile until it's handled here
+    match self {
+      Coin::Penny => Denomination::Penny,
+      Coin::Nickel => Denomination::Nickel,
+      Coin::Dime => Denomination::Dime,
+      // patterns can bind to the values inside a variant
+      Coin::Quarter(_state) => Denomination::Quarter,
+      Coin::HalfDollar => Denomination::HalfDollar,
+      Coin::Dollar => Denomination::Dollar,
+    }
+  }
+
+  pub fn value_in_cents(self) -> u32 {
+    self.denomination().cents()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashSet;
+
+  #[test]
+  fn coins_are_worth_their_face_value() {
+    assert_eq!(Coin::Penny.value_in_cents(), 1);
+    assert_eq!(Coin::Nickel.value_in_cents(), 5);
+    assert_eq!(Coin::Dime.value_in_cents(), 10);
+    assert_eq!(Coin::Quarter(UsState::Alaska).value_in_cents(), 25);
+    assert_eq!(Coin::HalfDollar.value_in_cents(), 50);
+    assert_eq!(Coin::Dollar.value_in_cents(), 100);
+    for d in &Denomination::ALL {
+      assert_eq!(Denomination::from_cents(d.cents()), Some(*d));
+    }
+    assert_eq!(Denomination::from_cents(3), None);
+  }
+
+  #[test]
+  fn has_every_state_once_in_order() {
+    let names: HashSet<&str> = UsState::ALL.iter().map(|state| state.name()).collect();
+    assert_eq!(names.len(), 50);
+    assert!(UsState::ALL.windows(2).all(|pair| pair[0] < pair[1] && pair[0].name() < pair[1].name()));
+    assert_eq!(UsState::NewHampshire.to_string(), "New Hampshire");
+  }
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::coin::{Coin, Denomination, UsState};
+
+/// A jar of coins, keeping track of which state quarters are in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Collection {
+  counts: HashMap<Denomination, u64>,
+  quarters: BTreeMap<UsState, u64>,
+}
+
+impl Collection {
+  pub fn new() -> Collection {
+    Collection::default()
+  }
+
+  pub fn add(&mut self, coin: Coin) {
+    *self.counts.entry(coin.denomination()).or_insert(0) += 1;
+    if let Coin::Quarter(state) = coin {
+      *self.quarters.entry(state).or_insert(0) += 1;
+    }
+  }
+
+  /// How many coins there are of `denomination`.
+  pub fn count(&self, denomination: Denomination) -> u64 {
+    self.counts.get(&denomination).copied().unwrap_or(0)
+  }
+
+  /// How many quarters there are from `state`.
+  pub fn quarters_from(&self, state: UsState) -> u64 {
+    self.quarters.get(&state).copied().unwrap_or(0)
+  }
+
+  pub fn len(&self) -> u64 {
+    self.counts.values().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn total_cents(&self) -> u64 {
+    self.counts.iter().map(|(d, n)| u64::from(d.cents()) * n).sum()
+  }
+
+  /// States whose quarter is still missing, in alphabetical order.
+  pub fn missing_states(&self) -> Vec<UsState> {
+    UsState::ALL.iter().copied().filter(|state| !self.quarters.contains_key(state)).collect()
+  }
+
+  /// States with more than one quarter, and how many spares there are.
+  pub fn duplicate_quarters(&self) -> Vec<(UsState, u64)> {
+    self.quarters.iter().filter(|(_, &n)| n > 1).map(|(&state, &n)| (state, n - 1)).collect()
+  }
+
+  /// Whether there's a quarter from every state.
+  pub fn is_complete(&self) -> bool {
+    self.quarters.len() == UsState::ALL.len()
+  }
+}
+
+impl Extend<Coin> for Collection {
+  fn extend<I: IntoIterator<Item = Coin>>(&mut self, coins: I) {
+    for coin in coins {
+      self.add(coin);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_coins_and_value() {
+    let mut jar = Collection::new();
+    assert!(jar.is_empty());
+    jar.extend(vec![Coin::Penny, Coin::Penny, Coin::Dime, Coin::Quarter(UsState::Ohio), Coin::Dollar]);
+    assert_eq!(jar.len(), 5);
+    assert_eq!(jar.count(Denomination::Penny), 2);
+    assert_eq!(jar.count(Denomination::Quarter), 1);
+    assert_eq!(jar.count(Denomination::Nickel), 0);
+    assert_eq!(jar.total_cents(), 137);
+  }
+
+  #[test]
+  fn tracks_missing_state_quarters() {
+    let mut jar = Collection::new();
+    assert_eq!(jar.missing_states().len(), 50);
+    jar.extend(UsState::ALL.iter().skip(2).map(|&state| Coin::Quarter(state)));
+    jar.add(Coin::Quarter(UsState::Texas));
+    jar.add(Coin::Quarter(UsState::Texas));
+    jar.add(Coin::Quarter(UsState::Utah));
+    assert_eq!(jar.missing_states(), [UsState::Alabama, UsState::Alaska]);
+    assert_eq!(jar.duplicate_quarters(), [(UsState::Texas, 2), (UsState::Utah, 1)]);
+    assert_eq!(jar.quarters_from(UsState::Texas), 3);
+    assert!(!jar.is_complete());
+
+    jar.add(Coin::Quarter(UsState::Alaska));
+    jar.add(Coin::Quarter(UsState::Alabama));
+    assert!(jar.missing_states().is_empty());
+    assert!(jar.is_complete());
+    assert_eq!(jar.total_cents(), 25 * 53);
+  }
+}
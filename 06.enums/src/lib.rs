@@ -0,0 +1,15 @@
+//! The enums from the chapter, grown into something other code can use.
+
+pub mod canvas;
+pub mod change;
+pub mod coin;
+pub mod collection;
+pub mod message;
+pub mod script;
+pub mod vending;
+
+pub use canvas::Canvas;
+pub use coin::{Coin, UsState};
+pub use collection::Collection;
+pub use message::Message;
+pub use vending::VendingMachine;
@@ -1,18 +1,8 @@
-#[allow(dead_code)]
-enum Message {
-  Quit,
-  Move { x: i32, y: i32 },
-  Write(String),
-  ChangeColor(i32, i32, i32),
-}
+use enums::{Canvas, Coin, Message, UsState};
 
-impl Message {
-  fn call(&self) {
-    println!("Call Message");
-  }
-}
+// enum with data in its variants, see message.rs
 
-/// options
+// options
 // enum Option<T> {
 //   // <T> means the Some variant of the Option 
 //   // enum can hold one piece of data of any type.
@@ -30,36 +20,10 @@ fn option_ex() {
   let _absent_number: Option<i32> = None;
 }
 
-/// the match control flow operator
-#[allow(dead_code)]
-#[derive(Debug)]
-enum UsState {
-  Alabama,
-  Alaska,
-}
-
-#[allow(dead_code)]
-enum Coin {
-  Penny,
-  Nickel,
-  Dime,
-  Quarter(UsState),
-}
-
-fn value_in_cents(coin: Coin) -> u32 {
-  match coin {
-    Coin::Nickel => 3,
-    Coin::Dime => 5,
-    Coin::Quarter(_state) => {
-      println!("{:?}", _state);
-      24
-    },
-    // The _ pattern will match any value
-    _ => 0,
-  }
-}
+// the match control flow operator, see coin.rs
 
 /// match with option<T>
+#[allow(clippy::manual_map)]
 fn plus_one(x: Option<i32>) -> Option<i32> {
   match x {
     Some(i) => Some(i + 1),
@@ -71,6 +35,7 @@ fn match_same(coin: Coin) {
   let mut _count = 0;
   match coin {
     Coin::Quarter(_state) => println!("It is quarter"),
+    // The _ pattern will match any value
     _ => _count += 1,
   }
 }
@@ -88,8 +53,16 @@ fn if_let(coin: Coin) {
 }
 
 fn main() {
+  let mut canvas = Canvas::new(32, 16);
   let m = Message::Write(String::from("Hello"));
-  m.call();
+  println!("{}", m.call(&mut canvas));
+  for message in enums::script::parse("move 8 4\ncolor 255 0 0\nmove 16 8\nquit").unwrap() {
+    println!("{}", message.call(&mut canvas));
+  }
+
+  let _dime = Coin::Dime.value_in_cents();
+  match_same(Coin::Penny);
+  if_let(Coin::Quarter(UsState::Alaska));
 
   option_ex();
   let five = Some(5);
@@ -0,0 +1,17 @@
+use crate::canvas::{Canvas, Entry};
+
+/// Each variant holds different kinds and amounts of data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+  Quit,
+  Move { x: i32, y: i32 },
+  Write(String),
+  ChangeColor(i32, i32, i32),
+}
+
+impl Message {
+  /// Carries the message out on `canvas`, returning what happened.
+  pub fn call<'a>(&self, canvas: &'a mut Canvas) -> &'a Entry {
+    canvas.apply(self)
+  }
+}
@@ -0,0 +1,312 @@
+//! `Message`s written down as text, one per line:
+//!
+//! ```text
+//! # a comment, like blank lines it's skipped
+//! move 3 -4
+//! write "hello \"world\""
+//! color 255 0 10
+//! quit
+//! ```
+//!
+//! Strings take the escapes `\\`, `\"`, `\n`, `\r`, `\t`, `\0` and
+//! `\u{...}`. Printing a message gives back this syntax, so `parse` and
+//! `print` undo each other.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::message::Message;
+
+/// What was wrong, and where. Lines and columns count from 1, and columns
+/// count characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+  pub line: usize,
+  pub column: usize,
+  pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+  UnknownCommand(String),
+  /// The name of the argument that's missing.
+  MissingArgument(&'static str),
+  BadNumber(String),
+  ExpectedString,
+  ExtraArgument,
+  UnterminatedString,
+  BadEscape(String),
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}: ", self.line, self.column)?;
+    match &self.kind {
+      ErrorKind::UnknownCommand(word) => write!(f, "unknown command `{}`", word),
+      ErrorKind::MissingArgument(name) => write!(f, "missing {}", name),
+      ErrorKind::BadNumber(word) => write!(f, "`{}` is not a number", word),
+      ErrorKind::ExpectedString => write!(f, "expected a quoted string"),
+      ErrorKind::ExtraArgument => write!(f, "too many arguments"),
+      ErrorKind::UnterminatedString => write!(f, "unterminated string"),
+      ErrorKind::BadEscape(escape) => write!(f, "bad escape `{}`", escape),
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a whole script.
+pub fn parse(text: &str) -> Result<Vec<Message>, ParseError> {
+  let mut messages = Vec::new();
+  for (i, line) in text.lines().enumerate() {
+    if let Some(message) = parse_line(line).map_err(|(column, kind)| ParseError { line: i + 1, column, kind })? {
+      messages.push(message);
+    }
+  }
+  Ok(messages)
+}
+
+/// One message per line, in the syntax `parse` reads.
+pub fn print(messages: &[Message]) -> String {
+  messages.iter().map(|message| format!("{}\n", message)).collect()
+}
+
+impl FromStr for Message {
+  type Err = ParseError;
+
+  /// Parses a single message; anything but one line holding one is an error.
+  fn from_str(text: &str) -> Result<Message, ParseError> {
+    let mut messages = parse(text)?.into_iter();
+    match (messages.next(), messages.next()) {
+      (Some(message), None) => Ok(message),
+      (None, _) => Err(ParseError { line: 1, column: 1, kind: ErrorKind::MissingArgument("command") }),
+      (Some(_), Some(_)) => {
+        let line = text.lines().enumerate().filter(|(_, line)| !is_blank(line)).nth(1).map_or(1, |(i, _)| i + 1);
+        Err(ParseError { line, column: 1, kind: ErrorKind::ExtraArgument })
+      },
+    }
+  }
+}
+
+impl fmt::Display for Message {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Message::Quit => write!(f, "quit"),
+      Message::Move { x, y } => write!(f, "move {} {}", x, y),
+      Message::Write(text) => {
+        f.write_str("write \"")?;
+        for c in text.chars() {
+          match c {
+            '\\' => f.write_str("\\\\")?,
+            '"' => f.write_str("\\\"")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\0' => f.write_str("\\0")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+          }
+        }
+        f.write_str("\"")
+      },
+      Message::ChangeColor(r, g, b) => write!(f, "color {} {} {}", r, g, b),
+    }
+  }
+}
+
+type LineError = (usize, ErrorKind);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+  Word(String),
+  Quoted(String),
+}
+
+fn is_blank(line: &str) -> bool {
+  matches!(tokenize(line), Ok(tokens) if tokens.is_empty())
+}
+
+fn parse_line(line: &str) -> Result<Option<Message>, LineError> {
+  let tokens = tokenize(line)?;
+  let end = line.chars().count() + 1;
+  let mut args = tokens.into_iter();
+  let (column, command) = match args.next() {
+    None => return Ok(None),
+    Some((column, Token::Word(word))) => (column, word),
+    Some((column, Token::Quoted(_))) => return Err((column, ErrorKind::UnknownCommand("\"...\"".to_string()))),
+  };
+
+  let mut number = |name| match args.next() {
+    Some((column, Token::Word(word))) => word.parse::<i32>().map_err(|_| (column, ErrorKind::BadNumber(word))),
+    Some((column, Token::Quoted(text))) => Err((column, ErrorKind::BadNumber(format!("\"{}\"", text)))),
+    None => Err((end, ErrorKind::MissingArgument(name))),
+  };
+  let message = match command.as_str() {
+    "quit" => Message::Quit,
+    "move" => Message::Move { x: number("x")?, y: number("y")? },
+    "color" => Message::ChangeColor(number("red")?, number("green")?, number("blue")?),
+    "write" => match args.next() {
+      Some((_, Token::Quoted(text))) => Message::Write(text),
+      Some((column, Token::Word(_))) => return Err((column, ErrorKind::ExpectedString)),
+      None => return Err((end, ErrorKind::MissingArgument("text"))),
+    },
+    _ => return Err((column, ErrorKind::UnknownCommand(command))),
+  };
+  match args.next() {
+    Some((column, _)) => Err((column, ErrorKind::ExtraArgument)),
+    None => Ok(Some(message)),
+  }
+}
+
+/// Splits a line into words and quoted strings, each with its column,
+/// stopping at a `#` outside quotes.
+fn tokenize(line: &str) -> Result<Vec<(usize, Token)>, LineError> {
+  let mut tokens = Vec::new();
+  let mut chars = line.chars().enumerate().map(|(i, c)| (i + 1, c)).peekable();
+  while let Some(&(column, c)) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+    } else if c == '#' {
+      break;
+    } else if c == '"' {
+      chars.next();
+      let mut text = String::new();
+      loop {
+        match chars.next() {
+          None => return Err((column, ErrorKind::UnterminatedString)),
+          Some((_, '"')) => break,
+          Some((at, '\\')) => text.push(unescape(at, &mut chars)?),
+          Some((_, c)) => text.push(c),
+        }
+      }
+      tokens.push((column, Token::Quoted(text)));
+    } else {
+      let mut word = String::new();
+      while let Some(&(_, c)) = chars.peek() {
+        if c.is_whitespace() || c == '"' || c == '#' {
+          break;
+        }
+        word.push(c);
+        chars.next();
+      }
+      tokens.push((column, Token::Word(word)));
+    }
+  }
+  Ok(tokens)
+}
+
+/// The character escaped by what follows a backslash at `column`.
+fn unescape(column: usize, chars: &mut impl Iterator<Item = (usize, char)>) -> Result<char, LineError> {
+  let bad = |escape: &str| (column, ErrorKind::BadEscape(format!("\\{}", escape)));
+  match chars.next() {
+    None => Err((column, ErrorKind::UnterminatedString)),
+    Some((_, '\\')) => Ok('\\'),
+    Some((_, '"')) => Ok('"'),
+    Some((_, 'n')) => Ok('\n'),
+    Some((_, 'r')) => Ok('\r'),
+    Some((_, 't')) => Ok('\t'),
+    Some((_, '0')) => Ok('\0'),
+    Some((_, 'u')) => {
+      let mut escape = "u".to_string();
+      if chars.next().map(|(_, c)| c) != Some('{') {
+        return Err(bad(&escape));
+      }
+      escape.push('{');
+      loop {
+        match chars.next() {
+          None => return Err((column, ErrorKind::UnterminatedString)),
+          Some((_, '}')) => break,
+          Some((_, c)) => escape.push(c),
+        }
+      }
+      let hex = escape[2..].to_string();
+      escape.push('}');
+      if hex.is_empty() || hex.len() > 6 {
+        return Err(bad(&escape));
+      }
+      u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32).ok_or_else(|| bad(&escape))
+    },
+    Some((_, c)) => Err(bad(&c.to_string())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error(text: &str) -> (usize, usize, ErrorKind) {
+    let err = parse(text).unwrap_err();
+    (err.line, err.column, err.kind)
+  }
+
+  #[test]
+  fn parses_every_command() {
+    let script = "# a drawing\nmove 3 -4\n\n  write \"hello world\"  # greet\ncolor 255 0 10\nquit\n";
+    assert_eq!(
+      parse(script).unwrap(),
+      [
+        Message::Move { x: 3, y: -4 },
+        Message::Write("hello world".to_string()),
+        Message::ChangeColor(255, 0, 10),
+        Message::Quit,
+      ]
+    );
+  }
+
+  #[test]
+  fn unescapes_strings() {
+    let parsed: Message = r#"write "a \"q\" \\ # not a comment\n\t\u{1F600}\0""#.parse().unwrap();
+    assert_eq!(parsed, Message::Write("a \"q\" \\ # not a comment\n\t\u{1F600}\0".to_string()));
+    assert_eq!("write \"\"".parse::<Message>().unwrap(), Message::Write(String::new()));
+  }
+
+  #[test]
+  fn reports_lines_and_columns() {
+    assert_eq!(error("quit\n  jump 1 2"), (2, 3, ErrorKind::UnknownCommand("jump".to_string())));
+    assert_eq!(error("move 1 two"), (1, 8, ErrorKind::BadNumber("two".to_string())));
+    assert_eq!(error("move 1"), (1, 7, ErrorKind::MissingArgument("y")));
+    assert_eq!(error("color 1 2 99999999999"), (1, 11, ErrorKind::BadNumber("99999999999".to_string())));
+    assert_eq!(error("quit now"), (1, 6, ErrorKind::ExtraArgument));
+    assert_eq!(error("write hello"), (1, 7, ErrorKind::ExpectedString));
+    assert_eq!(error("\n\nwrite \"hello"), (3, 7, ErrorKind::UnterminatedString));
+    assert_eq!(error("write \"a\\qb\""), (1, 9, ErrorKind::BadEscape("\\q".to_string())));
+    assert_eq!(error("write \"\\u{d800}\""), (1, 8, ErrorKind::BadEscape("\\u{d800}".to_string())));
+    assert_eq!(error("write \"é\\x\""), (1, 9, ErrorKind::BadEscape("\\x".to_string())));
+    assert_eq!(parse("move 1 x").unwrap_err().to_string(), "1:8: `x` is not a number");
+  }
+
+  #[test]
+  fn parses_one_message_from_a_string() {
+    assert_eq!("  quit ".parse::<Message>(), Ok(Message::Quit));
+    assert_eq!("# nothing".parse::<Message>().unwrap_err().kind, ErrorKind::MissingArgument("command"));
+    let err = "quit\n\nquit".parse::<Message>().unwrap_err();
+    assert_eq!((err.line, err.kind), (3, ErrorKind::ExtraArgument));
+  }
+
+  #[test]
+  fn round_trips() {
+    let all_ascii: String = (0..128u8).map(char::from).collect();
+    let messages = vec![
+      Message::Quit,
+      Message::Move { x: i32::MIN, y: i32::MAX },
+      Message::ChangeColor(-1, 256, 0),
+      Message::Write(all_ascii),
+      Message::Write("\u{7f}\u{85}ünïcødé \u{1F600}".to_string()),
+      Message::Write(String::new()),
+    ];
+    let text = print(&messages);
+    assert_eq!(parse(&text).unwrap(), messages);
+    assert_eq!(print(&parse(&text).unwrap()), text);
+    for message in &messages {
+      assert_eq!(&message.to_string().parse::<Message>().unwrap(), message);
+    }
+  }
+
+  #[test]
+  fn prints_the_same_syntax() {
+    assert_eq!(
+      print(&[Message::Move { x: 3, y: -4 }, Message::Write("say \"hi\"\n".to_string()), Message::Quit]),
+      "move 3 -4\nwrite \"say \\\"hi\\\"\\n\"\nquit\n"
+    );
+  }
+}
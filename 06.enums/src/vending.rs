@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+
+use crate::change;
+use crate::coin::{Coin, Denomination};
+
+/// Something for sale, and how many are left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+  pub product: String,
+  /// In cents.
+  pub price: u32,
+  pub count: u32,
+}
+
+impl Slot {
+  pub fn new(product: &str, price: u32, count: u32) -> Slot {
+    Slot { product: product.to_string(), price, count }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+  Idle,
+  /// Coins are in, nothing bought yet.
+  Credited { credit: u32 },
+}
+
+/// Everything that can happen to the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  Insert(Coin),
+  Select(usize),
+  /// Give back the coins put in so far.
+  Cancel,
+  /// Add `count` to a slot. Only while nobody's buying.
+  Restock { slot: usize, count: u32 },
+}
+
+/// Why the machine said no.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+  UnknownSlot(usize),
+  SoldOut(usize),
+  InsufficientFunds { price: u32, credit: u32 },
+  /// The float can't make this much change.
+  NoChange(u32),
+  /// Restocking in the middle of a sale.
+  Busy,
+}
+
+/// What the machine did about an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+  Credited(u32),
+  Vended { product: String, change: Vec<Coin> },
+  Refunded(Vec<Coin>),
+  Restocked { slot: usize, count: u32 },
+  Refused(Refusal),
+}
+
+/// One entry in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+  pub from: State,
+  pub event: Event,
+  pub to: State,
+  pub outcome: Outcome,
+}
+
+/// Sells from its slots and gives change from its own coins, which the
+/// coins paid in go into. It won't sell anything it can't give change for.
+#[derive(Debug, Clone)]
+pub struct VendingMachine {
+  slots: Vec<Slot>,
+  float: BTreeMap<Denomination, Vec<Coin>>,
+  /// What's been paid in since the last sale, to give back on cancel.
+  inserted: Vec<Coin>,
+  state: State,
+  log: Vec<Transition>,
+}
+
+impl VendingMachine {
+  pub fn new(slots: Vec<Slot>, float: Vec<Coin>) -> VendingMachine {
+    let mut machine = VendingMachine { slots, float: BTreeMap::new(), inserted: Vec::new(), state: State::Idle, log: Vec::new() };
+    for coin in float {
+      machine.deposit(coin);
+    }
+    machine
+  }
+
+  pub fn state(&self) -> State {
+    self.state
+  }
+
+  pub fn credit(&self) -> u32 {
+    match self.state {
+      State::Idle => 0,
+      State::Credited { credit } => credit,
+    }
+  }
+
+  pub fn slot(&self, slot: usize) -> Option<&Slot> {
+    self.slots.get(slot)
+  }
+
+  /// Everything in the coin box, paid in or not.
+  pub fn float_cents(&self) -> u64 {
+    self.float.iter().map(|(d, coins)| u64::from(d.cents()) * coins.len() as u64).sum()
+  }
+
+  /// Every transition so far, oldest first.
+  pub fn log(&self) -> &[Transition] {
+    &self.log
+  }
+
+  /// Handles one event and logs it.
+  pub fn handle(&mut self, event: Event) -> &Transition {
+    let from = self.state;
+    let outcome = match self.step(&event) {
+      Ok(outcome) => outcome,
+      Err(refusal) => Outcome::Refused(refusal),
+    };
+    self.log.push(Transition { from, event, to: self.state, outcome });
+    self.log.last().unwrap()
+  }
+
+  fn step(&mut self, event: &Event) -> Result<Outcome, Refusal> {
+    match (self.state, event) {
+      (State::Idle, Event::Insert(coin)) => Ok(self.insert(*coin, 0)),
+      (State::Credited { credit }, Event::Insert(coin)) => Ok(self.insert(*coin, credit)),
+      // with nothing paid in only a free product can be had, but the
+      // refusal still says why
+      (State::Idle, Event::Select(slot)) => self.sell(*slot, 0),
+      (State::Credited { credit }, Event::Select(slot)) => self.sell(*slot, credit),
+      (State::Idle, Event::Cancel) => Ok(Outcome::Refunded(Vec::new())),
+      (State::Credited { .. }, Event::Cancel) => {
+        let refund = std::mem::take(&mut self.inserted);
+        for coin in &refund {
+          let coins = self.float.get_mut(&coin.denomination()).unwrap();
+          let at = coins.iter().rposition(|c| c == coin).unwrap();
+          coins.remove(at);
+        }
+        self.state = State::Idle;
+        Ok(Outcome::Refunded(refund))
+      },
+      (State::Idle, Event::Restock { slot, count }) => {
+        let restocked = self.slots.get_mut(*slot).ok_or(Refusal::UnknownSlot(*slot))?;
+        restocked.count = restocked.count.saturating_add(*count);
+        Ok(Outcome::Restocked { slot: *slot, count: restocked.count })
+      },
+      (State::Credited { .. }, Event::Restock { .. }) => Err(Refusal::Busy),
+    }
+  }
+
+  fn insert(&mut self, coin: Coin, credit: u32) -> Outcome {
+    let credit = credit + coin.value_in_cents();
+    self.deposit(coin);
+    self.inserted.push(coin);
+    self.state = State::Credited { credit };
+    Outcome::Credited(credit)
+  }
+
+  fn sell(&mut self, slot: usize, credit: u32) -> Result<Outcome, Refusal> {
+    let wanted = self.slots.get(slot).ok_or(Refusal::UnknownSlot(slot))?;
+    if wanted.count == 0 {
+      return Err(Refusal::SoldOut(slot));
+    }
+    if credit < wanted.price {
+      return Err(Refusal::InsufficientFunds { price: wanted.price, credit });
+    }
+    let change = self.withdraw(credit - wanted.price)?;
+    let wanted = &mut self.slots[slot];
+    wanted.count -= 1;
+    self.inserted.clear();
+    self.state = State::Idle;
+    Ok(Outcome::Vended { product: wanted.product.clone(), change })
+  }
+
+  fn deposit(&mut self, coin: Coin) {
+    self.float.entry(coin.denomination()).or_default().push(coin);
+  }
+
+  /// Takes the fewest coins worth `cents` out of the float, or leaves it
+  /// alone if it can't.
+  fn withdraw(&mut self, cents: u32) -> Result<Vec<Coin>, Refusal> {
+    let supply: Vec<(u32, u32)> = self.float.iter().map(|(d, coins)| (d.cents(), coins.len() as u32)).collect();
+    let amounts = change::min_coins_limited(cents, &supply).ok_or(Refusal::NoChange(cents))?;
+    let mut coins = Vec::with_capacity(amounts.len());
+    for amount in amounts {
+      let denomination = Denomination::from_cents(amount).unwrap();
+      coins.push(self.float.get_mut(&denomination).unwrap().pop().unwrap());
+    }
+    Ok(coins)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::coin::UsState;
+
+  const QUARTER: Coin = Coin::Quarter(UsState::Ohio);
+
+  fn stocked(float: Vec<Coin>) -> VendingMachine {
+    VendingMachine::new(vec![Slot::new("cola", 75, 2), Slot::new("chips", 50, 0)], float)
+  }
+
+  fn pay(machine: &mut VendingMachine, coins: &[Coin]) {
+    for coin in coins {
+      machine.handle(Event::Insert(*coin));
+    }
+  }
+
+  #[test]
+  fn sells_for_exact_change() {
+    let mut machine = stocked(vec![]);
+    pay(&mut machine, &[QUARTER, QUARTER, Coin::Dime, Coin::Dime, Coin::Nickel]);
+    assert_eq!(machine.state(), State::Credited { credit: 75 });
+    let sale = machine.handle(Event::Select(0));
+    assert_eq!(sale.outcome, Outcome::Vended { product: "cola".to_string(), change: vec![] });
+    assert_eq!((sale.from, sale.to), (State::Credited { credit: 75 }, State::Idle));
+    assert_eq!(machine.slot(0).unwrap().count, 1);
+    assert_eq!(machine.float_cents(), 75);
+  }
+
+  #[test]
+  fn gives_change_from_the_float() {
+    let mut machine = stocked(vec![Coin::Dime, Coin::Dime, Coin::Nickel, Coin::Penny]);
+    pay(&mut machine, &[Coin::Dollar]);
+    let sale = machine.handle(Event::Select(0));
+    assert_eq!(sale.outcome, Outcome::Vended { product: "cola".to_string(), change: vec![Coin::Dime, Coin::Dime, Coin::Nickel] });
+    assert_eq!(machine.float_cents(), 101);
+    assert_eq!(machine.credit(), 0);
+  }
+
+  #[test]
+  fn refuses_insufficient_funds() {
+    let mut machine = stocked(vec![]);
+    let refused = machine.handle(Event::Select(0));
+    assert_eq!(refused.outcome, Outcome::Refused(Refusal::InsufficientFunds { price: 75, credit: 0 }));
+    assert_eq!(refused.to, State::Idle);
+    pay(&mut machine, &[QUARTER, QUARTER]);
+    let refused = machine.handle(Event::Select(0));
+    assert_eq!(refused.outcome, Outcome::Refused(Refusal::InsufficientFunds { price: 75, credit: 50 }));
+    assert_eq!(refused.to, State::Credited { credit: 50 });
+    pay(&mut machine, &[QUARTER]);
+    assert!(matches!(machine.handle(Event::Select(0)).outcome, Outcome::Vended { .. }));
+  }
+
+  #[test]
+  fn refuses_sold_out_and_unknown_slots() {
+    let mut machine = stocked(vec![]);
+    pay(&mut machine, &[Coin::Dollar]);
+    assert_eq!(machine.handle(Event::Select(1)).outcome, Outcome::Refused(Refusal::SoldOut(1)));
+    assert_eq!(machine.handle(Event::Select(7)).outcome, Outcome::Refused(Refusal::UnknownSlot(7)));
+    assert_eq!(machine.handle(Event::Restock { slot: 1, count: 3 }).outcome, Outcome::Refused(Refusal::Busy));
+    machine.handle(Event::Cancel);
+    assert_eq!(machine.handle(Event::Restock { slot: 1, count: 3 }).outcome, Outcome::Restocked { slot: 1, count: 3 });
+
+    // the last cola sells out
+    let mut machine = VendingMachine::new(vec![Slot::new("cola", 75, 1)], vec![]);
+    pay(&mut machine, &[QUARTER, QUARTER, QUARTER]);
+    machine.handle(Event::Select(0));
+    pay(&mut machine, &[QUARTER, QUARTER, QUARTER]);
+    assert_eq!(machine.handle(Event::Select(0)).outcome, Outcome::Refused(Refusal::SoldOut(0)));
+  }
+
+  #[test]
+  fn refuses_sales_it_cant_make_change_for() {
+    let mut machine = stocked(vec![Coin::Dime, Coin::Dime]);
+    pay(&mut machine, &[Coin::Dollar]);
+    assert_eq!(machine.handle(Event::Select(0)).outcome, Outcome::Refused(Refusal::NoChange(25)));
+    assert_eq!(machine.slot(0).unwrap().count, 2);
+    assert_eq!(machine.float_cents(), 120);
+
+    // paid-in coins count toward change
+    let mut machine = stocked(vec![]);
+    pay(&mut machine, &[Coin::Dime, QUARTER, QUARTER, QUARTER]);
+    let sale = machine.handle(Event::Select(0));
+    assert_eq!(sale.outcome, Outcome::Vended { product: "cola".to_string(), change: vec![Coin::Dime] });
+  }
+
+  #[test]
+  fn cancel_gives_back_the_same_coins() {
+    let texas = Coin::Quarter(UsState::Texas);
+    let mut machine = stocked(vec![QUARTER, QUARTER]);
+    pay(&mut machine, &[texas, Coin::Dime]);
+    let refund = machine.handle(Event::Cancel);
+    assert_eq!(refund.outcome, Outcome::Refunded(vec![texas, Coin::Dime]));
+    assert_eq!(refund.to, State::Idle);
+    assert_eq!(machine.float_cents(), 50);
+    assert_eq!(machine.handle(Event::Cancel).outcome, Outcome::Refunded(vec![]));
+  }
+
+  #[test]
+  fn logs_every_transition() {
+    let mut machine = stocked(vec![]);
+    pay(&mut machine, &[QUARTER]);
+    machine.handle(Event::Select(0));
+    machine.handle(Event::Cancel);
+    let log: Vec<(State, &Event, State)> = machine.log().iter().map(|t| (t.from, &t.event, t.to)).collect();
+    assert_eq!(
+      log,
+      [
+        (State::Idle, &Event::Insert(QUARTER), State::Credited { credit: 25 }),
+        (State::Credited { credit: 25 }, &Event::Select(0), State::Credited { credit: 25 }),
+        (State::Credited { credit: 25 }, &Event::Cancel, State::Idle),
+      ]
+    );
+  }
+}
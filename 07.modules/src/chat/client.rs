@@ -0,0 +1,95 @@
+use std::io::{BufRead, Write};
+use std::net::ToSocketAddrs;
+use std::thread;
+
+use communicator::{Error, Event, Result, Transport};
+
+use super::Command;
+
+/// Joins the chat at `addr` as `nick`, sends every line of `input` and
+/// writes whatever the server says to `output`.
+/// Returns once the server hangs up; running out of input counts as `/quit`.
+pub fn run<A, R, W>(addr: A, nick: &str, input: R, output: &mut W) -> Result<()>
+where
+    A: ToSocketAddrs,
+    R: BufRead,
+    W: Write + Send,
+{
+    let connection = communicator::client::connect(addr)?;
+    connection.send(format!("/nick {}", nick).as_bytes())?;
+
+    thread::scope(|scope| {
+        let printer = scope.spawn(|| loop {
+            match connection.recv_event() {
+                // only the server speaks in the chat, other clients can't message us directly
+                Ok(Event::Routed { .. }) => {},
+                Ok(event) => {
+                    output.write_all(event.message())?;
+                    output.write_all(b"\n")?;
+                    output.flush()?;
+                },
+                Err(Error::Closed) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        });
+
+        let sent = (|| {
+            for line in input.lines() {
+                let line = line?;
+                connection.send(line.as_bytes())?;
+                if Command::parse(&line) == Command::Quit {
+                    return Ok(());
+                }
+            }
+            connection.send(b"/quit")
+        })();
+        if sent.is_err() {
+            // don't leave the printer waiting on a server that won't hang up
+            let _ = connection.close();
+        }
+        let printed = printer.join().unwrap();
+        sent.and(printed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::server;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn scripted_clients_chat() {
+        let server = server::serve("127.0.0.1:0").unwrap();
+        let listener = communicator::client::connect(server.local_addr()).unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        listener.send(b"/nick bob").unwrap();
+        listener.send(b"/join rust").unwrap();
+        assert_eq!(listener.recv().unwrap(), b"* you are bob, in #lobby");
+        assert_eq!(listener.recv().unwrap(), b"* you joined #rust");
+
+        let mut output = Vec::new();
+        let script = Cursor::new("/join rust\n/who\nhello from a script\n");
+        run(server.local_addr(), "alice", script, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "* you are alice, in #lobby\n* you joined #rust\n* in #rust: alice, bob\n* bye\n"
+        );
+        assert_eq!(listener.recv().unwrap(), b"* alice joined #rust");
+        assert_eq!(listener.recv().unwrap(), b"<alice> hello from a script");
+        assert_eq!(listener.recv().unwrap(), b"* alice left #rust");
+    }
+
+    #[test]
+    fn quits_on_a_padded_command() {
+        let server = server::serve("127.0.0.1:0").unwrap();
+        let mut output = Vec::new();
+        // both ends have to read this as /quit, or the client waits for a hang-up that never comes
+        let script = Cursor::new("  /quit \n/who\n");
+        run(server.local_addr(), "alice", script, &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "* you are alice, in #lobby\n* bye\n");
+    }
+}
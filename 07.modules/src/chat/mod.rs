@@ -0,0 +1,77 @@
+//! A small multi-user chat built on the communicator crate.
+//!
+//! Every line a client types goes to the server as one `DATA` message.
+//! Lines starting with `/` are commands, anything else is said to the
+//! room the client is in. Everything the server sends back is one line
+//! of text ready to be printed.
+//!
+//! The server is the communicator's broker with the chat as its `Service`,
+//! and each room is one of its topics.
+
+pub mod client;
+pub mod server;
+
+/// Where everyone starts out.
+pub const LOBBY: &str = "lobby";
+
+/// Longest nickname or room name.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Nicknames and room names are short and plain, so they can't be
+/// mistaken for each other or for part of a message.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// What a line typed into the chat asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Anything that isn't a command is said to the room.
+    Say(&'a str),
+    Nick(&'a str),
+    Join(&'a str),
+    Who,
+    Quit,
+    Unknown(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Reads a line the way both ends must agree on: a command is a `/` and
+    /// a name, maybe followed by an argument, with whitespace around either
+    /// ignored.
+    pub fn parse(line: &'a str) -> Command<'a> {
+        let command = match line.trim().strip_prefix('/') {
+            Some(command) => command,
+            None => return Command::Say(line),
+        };
+        let mut parts = command.trim_start().splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next().unwrap_or("").trim();
+        match name {
+            "nick" => Command::Nick(argument),
+            "join" => Command::Join(argument),
+            "who" => Command::Who,
+            "quit" => Command::Quit,
+            other => Command::Unknown(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("hello there"), Command::Say("hello there"));
+        assert_eq!(Command::parse("/nick  alice "), Command::Nick("alice"));
+        assert_eq!(Command::parse("/join #rust"), Command::Join("#rust"));
+        assert_eq!(Command::parse("/who"), Command::Who);
+        // surrounding whitespace doesn't turn a command into a message, on either end
+        assert_eq!(Command::parse("  /quit "), Command::Quit);
+        assert_eq!(Command::parse("/ quit"), Command::Quit);
+        assert_eq!(Command::parse("/dance now"), Command::Unknown("dance"));
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex};
+
+use communicator::network::server::{self, Peers, Server, Service};
+use communicator::network::stream::Addr;
+use communicator::{Error, Result};
+
+use super::{valid_name, Command, LOBBY, MAX_NAME_LEN};
+
+/// A running chat server. Stops when dropped.
+/// Each room is a topic on the broker, so a member that stops reading only
+/// loses its own messages once its queue fills up, and holds up nobody.
+pub struct ChatServer {
+    server: Server,
+}
+
+/// Starts accepting chat clients on `addr`.
+pub fn serve<A: ToSocketAddrs>(addr: A) -> Result<ChatServer> {
+    let server = server::connect(addr)?;
+    server.set_service(Arc::new(Chat::default()));
+    Ok(ChatServer { server })
+}
+
+impl ChatServer {
+    pub fn local_addr(&self) -> Addr {
+        self.server.local_addr()
+    }
+
+    /// Blocks for as long as the server runs.
+    pub fn wait(self) {
+        self.server.wait();
+    }
+}
+
+struct Member {
+    /// None until the client has picked one.
+    nick: Option<String>,
+    room: String,
+}
+
+/// Who is chatting, keyed by connection id. Clients show up here once
+/// they pick a nickname, which is also when they start hearing their room.
+#[derive(Default)]
+struct Chat {
+    members: Mutex<HashMap<u64, Member>>,
+}
+
+/// The broker topic a room's messages go to.
+fn topic(room: &str) -> String {
+    format!("#{}", room)
+}
+
+/// Sends `line` to everyone in `room` but `except`.
+fn broadcast(peers: &Peers, room: &str, except: u64, line: &str) {
+    let _ = peers.publish(&topic(room), line.as_bytes(), Some(except));
+}
+
+impl Service for Chat {
+    /// Answers one line from a client.
+    fn message(&self, peers: &Peers, id: u64, message: &[u8]) -> Result<()> {
+        let line = String::from_utf8_lossy(message);
+        let line = line.trim_end_matches(['\r', '\n']);
+        let reply = match Command::parse(line) {
+            Command::Say(text) => self.say(peers, id, text),
+            Command::Nick(name) => self.nick(peers, id, name),
+            Command::Join(room) => self.join(peers, id, room),
+            Command::Who => self.who(id),
+            Command::Quit => {
+                peers.send(id, b"* bye");
+                // hangs up once the goodbye is on its way
+                return Err(Error::Closed);
+            },
+            Command::Unknown(name) => Some(format!("! unknown command: /{}", name)),
+        };
+        if let Some(reply) = reply {
+            peers.send(id, reply.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Forgets a client, telling the room it was in.
+    fn left(&self, peers: &Peers, id: u64) {
+        let gone = self.members.lock().unwrap().remove(&id);
+        if let Some(Member { nick: Some(nick), room: current }) = gone {
+            broadcast(peers, &current, id, &format!("* {} left #{}", nick, current));
+        }
+    }
+
+    /// Only the server speaks in rooms, so nobody can put words in someone else's mouth.
+    fn owns(&self, topic: &str) -> bool {
+        topic.starts_with('#')
+    }
+}
+
+impl Chat {
+    /// The sender's nickname and room, or the reply telling them to pick a nickname.
+    fn whoami(&self, id: u64) -> std::result::Result<(String, String), String> {
+        match self.members.lock().unwrap().get(&id) {
+            Some(Member { nick: Some(nick), room: current }) => Ok((nick.clone(), current.clone())),
+            _ => Err(String::from("! pick a nickname with /nick <name> first")),
+        }
+    }
+
+    fn say(&self, peers: &Peers, id: u64, text: &str) -> Option<String> {
+        let (nick, current) = match self.whoami(id) {
+            Ok(me) => me,
+            Err(reply) => return Some(reply),
+        };
+        if !text.trim().is_empty() {
+            broadcast(peers, &current, id, &format!("<{}> {}", nick, text));
+        }
+        None
+    }
+
+    fn nick(&self, peers: &Peers, id: u64, name: &str) -> Option<String> {
+        if !valid_name(name) {
+            return Some(format!("! nicknames are 1 to {} letters, digits, - or _", MAX_NAME_LEN));
+        }
+
+        let (old, current) = {
+            let mut members = self.members.lock().unwrap();
+            if members.iter().any(|(other, member)| *other != id && member.nick.as_deref() == Some(name)) {
+                return Some(format!("! {} is taken", name));
+            }
+            let member = members.entry(id).or_insert_with(|| Member { nick: None, room: String::from(LOBBY) });
+            (member.nick.replace(name.to_string()), member.room.clone())
+        };
+        match old {
+            None => {
+                let _ = peers.subscribe(id, &topic(&current));
+                broadcast(peers, &current, id, &format!("* {} joined #{}", name, current));
+                Some(format!("* you are {}, in #{}", name, current))
+            },
+            Some(old) if old == name => None,
+            Some(old) => {
+                broadcast(peers, &current, id, &format!("* {} is now known as {}", old, name));
+                Some(format!("* you are now {}", name))
+            },
+        }
+    }
+
+    fn join(&self, peers: &Peers, id: u64, name: &str) -> Option<String> {
+        let name = name.strip_prefix('#').unwrap_or(name);
+        if !valid_name(name) {
+            return Some(String::from("! usage: /join <room>"));
+        }
+        let (nick, current) = match self.whoami(id) {
+            Ok(me) => me,
+            Err(reply) => return Some(reply),
+        };
+        if current == name {
+            return Some(format!("* already in #{}", name));
+        }
+
+        broadcast(peers, &current, id, &format!("* {} left #{}", nick, current));
+        let _ = peers.unsubscribe(id, &topic(&current));
+        if let Some(member) = self.members.lock().unwrap().get_mut(&id) {
+            member.room = name.to_string();
+        }
+        let _ = peers.subscribe(id, &topic(name));
+        broadcast(peers, name, id, &format!("* {} joined #{}", nick, name));
+        Some(format!("* you joined #{}", name))
+    }
+
+    fn who(&self, id: u64) -> Option<String> {
+        let current = match self.whoami(id) {
+            Ok((_, current)) => current,
+            Err(reply) => return Some(reply),
+        };
+        let mut nicks: Vec<String> = self
+            .members
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|member| member.room == current)
+            .filter_map(|member| member.nick.clone())
+            .collect();
+        nicks.sort();
+        Some(format!("* in #{}: {}", current, nicks.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use communicator::Connection;
+    use communicator::Transport;
+    use std::thread;
+    use std::time::Duration;
+
+    fn client(server: &ChatServer) -> Connection {
+        let connection = communicator::client::connect(server.local_addr()).unwrap();
+        connection.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        connection
+    }
+
+    fn say(connection: &Connection, line: &str) {
+        connection.send(line.as_bytes()).unwrap();
+    }
+
+    fn hear(connection: &Connection) -> String {
+        String::from_utf8(connection.recv().unwrap()).unwrap()
+    }
+
+    fn named(server: &ChatServer, nick: &str) -> Connection {
+        let connection = client(server);
+        say(&connection, &format!("/nick {}", nick));
+        assert_eq!(hear(&connection), format!("* you are {}, in #lobby", nick));
+        connection
+    }
+
+    #[test]
+    fn chats_within_a_room() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        let bob = named(&server, "bob");
+        assert_eq!(hear(&alice), "* bob joined #lobby");
+
+        say(&alice, "hi bob");
+        assert_eq!(hear(&bob), "<alice> hi bob");
+        say(&bob, "/who");
+        assert_eq!(hear(&bob), "* in #lobby: alice, bob");
+    }
+
+    #[test]
+    fn rooms_are_separate() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        let bob = named(&server, "bob");
+        let carol = named(&server, "carol");
+        assert_eq!(hear(&alice), "* bob joined #lobby");
+        assert_eq!(hear(&alice), "* carol joined #lobby");
+        assert_eq!(hear(&bob), "* carol joined #lobby");
+
+        say(&alice, "/join #rust");
+        assert_eq!(hear(&alice), "* you joined #rust");
+        assert_eq!(hear(&bob), "* alice left #lobby");
+        say(&bob, "/join rust");
+        assert_eq!(hear(&bob), "* you joined #rust");
+        assert_eq!(hear(&alice), "* bob joined #rust");
+        assert_eq!(hear(&carol), "* alice left #lobby");
+        assert_eq!(hear(&carol), "* bob left #lobby");
+
+        say(&carol, "anyone here?");
+        say(&alice, "just us");
+        assert_eq!(hear(&bob), "<alice> just us");
+        say(&carol, "/who");
+        assert_eq!(hear(&carol), "* in #lobby: carol");
+        say(&alice, "/join rust");
+        assert_eq!(hear(&alice), "* already in #rust");
+        say(&alice, "/who");
+        assert_eq!(hear(&alice), "* in #rust: alice, bob");
+    }
+
+    #[test]
+    fn nicknames_are_unique() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        let other = client(&server);
+
+        say(&other, "hello?");
+        assert_eq!(hear(&other), "! pick a nickname with /nick <name> first");
+        say(&other, "/nick alice");
+        assert_eq!(hear(&other), "! alice is taken");
+        say(&other, "/nick al ice");
+        assert_eq!(hear(&other), format!("! nicknames are 1 to {} letters, digits, - or _", MAX_NAME_LEN));
+        say(&other, "/nick bob");
+        assert_eq!(hear(&other), "* you are bob, in #lobby");
+        assert_eq!(hear(&alice), "* bob joined #lobby");
+
+        say(&other, "/nick robert");
+        assert_eq!(hear(&other), "* you are now robert");
+        assert_eq!(hear(&alice), "* bob is now known as robert");
+        say(&alice, "/dance");
+        assert_eq!(hear(&alice), "! unknown command: /dance");
+    }
+
+    #[test]
+    fn announces_departures() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        let bob = named(&server, "bob");
+        let carol = named(&server, "carol");
+        assert_eq!(hear(&alice), "* bob joined #lobby");
+        assert_eq!(hear(&alice), "* carol joined #lobby");
+        assert_eq!(hear(&bob), "* carol joined #lobby");
+
+        say(&bob, "/quit");
+        assert_eq!(hear(&bob), "* bye");
+        assert!(matches!(bob.recv(), Err(Error::Closed)));
+        assert_eq!(hear(&alice), "* bob left #lobby");
+
+        // hanging up without a word counts as leaving too
+        drop(carol);
+        assert_eq!(hear(&alice), "* carol left #lobby");
+        say(&alice, "/who");
+        assert_eq!(hear(&alice), "* in #lobby: alice");
+    }
+
+    #[test]
+    fn a_stalled_member_holds_up_nobody() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        let bob = named(&server, "bob");
+        let stalled = named(&server, "stalled");
+        assert_eq!(hear(&alice), "* bob joined #lobby");
+        assert_eq!(hear(&alice), "* stalled joined #lobby");
+        assert_eq!(hear(&bob), "* stalled joined #lobby");
+
+        // far more than the queue and socket buffers of `stalled` hold, and it never reads
+        let line = "x".repeat(32 * 1024);
+        let expected = format!("<alice> {}", line);
+        let listener = thread::spawn(move || {
+            for _ in 0..2000 {
+                assert!(hear(&bob) == expected);
+            }
+        });
+        for _ in 0..2000 {
+            say(&alice, &line);
+        }
+        listener.join().unwrap();
+        drop(stalled);
+    }
+
+    #[test]
+    fn stopping_hangs_up_on_everyone() {
+        let server = serve("127.0.0.1:0").unwrap();
+        let alice = named(&server, "alice");
+        drop(server);
+        assert!(matches!(alice.recv(), Err(Error::Closed)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::hash_map::RandomState;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of a challenge nonce and of the answer to it.
+pub const NONCE_LEN: usize = 32;
+pub const MAC_LEN: usize = 32;
+
+/// Mixed into every answer, so a MAC computed for some other purpose under
+/// the same key can't be passed off as one.
+const CONTEXT: &[u8] = b"communicator-auth-v1";
+
+/// A pre-shared secret. Kept out of `Debug` output so it doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+  pub fn new<K: Into<Vec<u8>>>(key: K) -> Key {
+    Key(key.into())
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl fmt::Debug for Key {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Key(..)")
+  }
+}
+
+/// The client's answer to `nonce`: HMAC-SHA256 over it under the shared `key`.
+pub fn answer(key: &[u8], nonce: &[u8]) -> [u8; MAC_LEN] {
+  hmac_sha256(key, &[CONTEXT, nonce].concat())
+}
+
+/// Checks an answer without giving away through timing how much of it was right.
+pub fn verify(key: &[u8], nonce: &[u8], mac: &[u8]) -> bool {
+  let expected = answer(key, nonce);
+  mac.len() == MAC_LEN && expected.iter().zip(mac).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// A fresh challenge. Every nonce is different, which is what makes an
+/// answer overheard on one connection useless on the next.
+///
+/// The standard library has no CSPRNG, so this hashes a process-wide secret
+/// drawn from `RandomState` together with a counter and the clock.
+pub fn nonce() -> [u8; NONCE_LEN] {
+  static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+  let secret = SECRET.get_or_init(|| {
+    (0..4u64)
+      .flat_map(|i| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(i);
+        hasher.finish().to_be_bytes()
+      })
+      .collect()
+  });
+  let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0);
+  hmac_sha256(secret, &[&count.to_be_bytes()[..], &now.to_be_bytes()[..]].concat())
+}
+
+/// HMAC as in RFC 2104, over SHA-256.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+  const BLOCK: usize = 64;
+  let mut block = [0u8; BLOCK];
+  if key.len() > BLOCK {
+    block[..32].copy_from_slice(&sha256(key));
+  } else {
+    block[..key.len()].copy_from_slice(key);
+  }
+
+  let inner_pad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+  let outer_pad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+  let inner = sha256(&[&inner_pad[..], message].concat());
+  sha256(&[&outer_pad[..], &inner[..]].concat())
+}
+
+/// SHA-256 as in FIPS 180-4.
+pub fn sha256(message: &[u8]) -> [u8; 32] {
+  const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+    0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+    0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+    0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+  ];
+  let mut state: [u32; 8] =
+    [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+  // pad with a one bit, zeros, and the length in bits to a multiple of 64 bytes
+  let mut data = message.to_vec();
+  data.push(0x80);
+  while data.len() % 64 != 56 {
+    data.push(0);
+  }
+  data.extend_from_slice(&((message.len() as u64).wrapping_mul(8)).to_be_bytes());
+
+  for chunk in data.chunks(64) {
+    let mut w = [0u32; 64];
+    for (i, word) in chunk.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let choice = (e & f) ^ (!e & g);
+      let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(K[i]).wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let majority = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(majority);
+      h = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (word, add) in state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+      *word = word.wrapping_add(*add);
+    }
+  }
+
+  let mut digest = [0u8; 32];
+  for (bytes, word) in digest.chunks_mut(4).zip(&state) {
+    bytes.copy_from_slice(&word.to_be_bytes());
+  }
+  digest
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[test]
+  fn hashes_known_vectors() {
+    assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    // two blocks once padded
+    assert_eq!(
+      hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+      "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+  }
+
+  /// RFC 4231 test cases 1, 2 and 6.
+  #[test]
+  fn macs_known_vectors() {
+    assert_eq!(
+      hex(&hmac_sha256(&[0x0b; 20], b"Hi There")),
+      "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+    );
+    assert_eq!(
+      hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+      "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert_eq!(
+      hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+      "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+  }
+
+  #[test]
+  fn hides_keys_from_debug_output() {
+    assert_eq!(format!("{:?}", Some(Key::new("hunter2"))), "Some(Key(..))");
+  }
+
+  #[test]
+  fn verifies_answers() {
+    let nonce = nonce();
+    let mac = answer(b"secret", &nonce);
+    assert!(verify(b"secret", &nonce, &mac));
+    assert!(!verify(b"guess", &nonce, &mac));
+    assert!(!verify(b"secret", &super::nonce(), &mac));
+    assert!(!verify(b"secret", &nonce, &mac[..31]));
+  }
+
+  #[test]
+  fn never_repeats_a_nonce() {
+    let nonces: std::collections::HashSet<_> = (0..1000).map(|_| nonce()).collect();
+    assert_eq!(nonces.len(), 1000);
+  }
+}
@@ -1,3 +0,0 @@
-fn connect() {
-  println!("This is the client side");
-}
\ No newline at end of file
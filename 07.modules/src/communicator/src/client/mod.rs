@@ -0,0 +1,264 @@
+use std::convert::TryInto;
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::path::Path;
+
+use crate::auth::{self, Key};
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::network;
+use crate::network::frame::{tag, Frame};
+
+pub mod reconnect;
+
+pub use self::reconnect::{Options, ReconnectingClient, State};
+
+/// Connects to the server at `addr` and waits for its greeting.
+pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Connection> {
+  let stream = network::connect(addr)?;
+  let mut connection = Connection::new(stream, 0)?;
+  handshake(&mut connection, None)?;
+  Ok(connection)
+}
+
+/// Like `connect`, but answers the server's challenge with `key` if it sends one.
+pub fn connect_with_key<A: ToSocketAddrs>(addr: A, key: &Key) -> Result<Connection> {
+  let stream = network::connect(addr)?;
+  let mut connection = Connection::new(stream, 0)?;
+  handshake(&mut connection, Some(key))?;
+  Ok(connection)
+}
+
+/// Connects to a server listening on the Unix socket file at `path`.
+#[cfg(unix)]
+pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Connection> {
+  let stream = network::unix::connect(path)?;
+  let mut connection = Connection::new(stream, 0)?;
+  handshake(&mut connection, None)?;
+  Ok(connection)
+}
+
+/// Reads the server's greeting and takes the id it assigns, proving
+/// first that we hold `key` if the server asks.
+pub(crate) fn handshake(connection: &mut Connection, key: Option<&Key>) -> Result<()> {
+  let mut hello = connection.recv_frame()?;
+  if hello.tag == tag::CHALLENGE {
+    let key = key.ok_or_else(|| Error::Rejected(String::from("the server requires a key")))?;
+    connection.send_frame(&Frame::new(tag::AUTH, auth::answer(key.as_bytes(), &hello.payload).to_vec()))?;
+    hello = connection.recv_frame()?;
+  }
+  if hello.tag == tag::ERROR {
+    return Err(Error::Rejected(String::from_utf8_lossy(&hello.payload).into_owned()));
+  }
+  if hello.tag != tag::HELLO || hello.payload.len() != 8 {
+    return Err(Error::Protocol(format!("expected a greeting, got frame tag {:#04x}", hello.tag)));
+  }
+  connection.set_id(u64::from_be_bytes(hello.payload[..].try_into().unwrap()));
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::connection::Event;
+  use crate::message::Message;
+  use crate::network::server;
+  use crate::transport::Transport;
+  use std::time::Duration;
+
+  #[test]
+  fn send_and_receive() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    assert!(client.id() > 0);
+    client.send(b"hello world").unwrap();
+    assert_eq!(client.recv().unwrap(), b"hello world");
+  }
+
+  #[test]
+  fn carries_arbitrary_bytes() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    let payload: Vec<u8> = (0..=255).cycle().take(70_000).collect();
+    client.send(&payload).unwrap();
+    assert_eq!(client.recv().unwrap(), payload);
+  }
+
+  #[test]
+  fn echoes_messages() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    let message = Message::ChangeColor(1, -2, 3);
+    client.send_frame(&message.to_frame()).unwrap();
+    assert_eq!(Message::from_frame(&client.recv_frame().unwrap()).unwrap(), message);
+  }
+
+  #[test]
+  fn talk_to_each_other() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let alice = connect(server.local_addr()).unwrap();
+    let bob = connect(server.local_addr()).unwrap();
+
+    alice.send_to(bob.id(), b"hi bob").unwrap();
+    assert_eq!(bob.recv_from().unwrap(), (Some(alice.id()), b"hi bob".to_vec()));
+  }
+
+  #[test]
+  fn publish_and_subscribe() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let publisher = connect(server.local_addr()).unwrap();
+    let subscriber = connect(server.local_addr()).unwrap();
+
+    subscriber.subscribe("weather").unwrap();
+    // the echo comes back once the subscription is in place
+    subscriber.send(b"ready").unwrap();
+    assert_eq!(subscriber.recv().unwrap(), b"ready");
+
+    publisher.publish("weather", b"sunny").unwrap();
+    let event = subscriber.recv_event().unwrap();
+    assert_eq!(event, Event::Published { topic: String::from("weather"), message: b"sunny".to_vec() });
+  }
+
+  #[test]
+  fn validates_topics() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    assert!(matches!(client.subscribe(""), Err(Error::InvalidMessage(_))));
+    assert!(matches!(client.publish(&"t".repeat(256), b""), Err(Error::InvalidMessage(_))));
+  }
+
+  #[test]
+  fn reports_unknown_peer() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    client.send_to(999, b"anyone?").unwrap();
+    assert!(matches!(client.recv(), Err(Error::Rejected(_))));
+  }
+
+  #[test]
+  fn reports_closed_server() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    server.shutdown();
+    assert!(matches!(client.recv(), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn times_out_waiting() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    client.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+    assert!(matches!(client.recv(), Err(Error::Timeout)));
+  }
+
+  #[test]
+  fn close_is_idempotent() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect(server.local_addr()).unwrap();
+
+    client.close().unwrap();
+    client.close().unwrap();
+    assert!(matches!(client.recv(), Err(Error::Closed)));
+  }
+
+  #[cfg(unix)]
+  fn socket_path(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("communicator-client-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("server.sock")
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn talks_over_unix_sockets() {
+    let path = socket_path("talk");
+    let server = server::connect_unix(&path).unwrap();
+    server.register("upper", |input| Ok(input.to_ascii_uppercase()));
+    let alice = connect_unix(&path).unwrap();
+    let bob = connect_unix(&path).unwrap();
+
+    alice.send(b"echo").unwrap();
+    assert_eq!(alice.recv().unwrap(), b"echo");
+
+    alice.send_to(bob.id(), b"hi bob").unwrap();
+    assert_eq!(bob.recv_from().unwrap(), (Some(alice.id()), b"hi bob".to_vec()));
+
+    bob.subscribe("news").unwrap();
+    bob.send(b"ready").unwrap();
+    assert_eq!(bob.recv().unwrap(), b"ready");
+    alice.publish("news", b"extra").unwrap();
+    assert_eq!(bob.recv_event().unwrap(), Event::Published { topic: String::from("news"), message: b"extra".to_vec() });
+
+    let rpc = crate::rpc::RpcClient::new(alice);
+    assert_eq!(rpc.call("upper", b"shout").unwrap(), b"SHOUT");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_server_cleans_up_its_socket() {
+    let path = socket_path("cleanup");
+    // a server that crashed leaves its socket file behind
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let server = server::connect_unix(&path).unwrap();
+    let client = connect_unix(&path).unwrap();
+    assert!(server::connect_unix(&path).is_err());
+
+    server.shutdown();
+    assert!(matches!(client.recv(), Err(Error::Closed)));
+    assert!(!path.exists());
+    assert!(connect_unix(&path).is_err());
+  }
+
+  fn locked_server(key: &str) -> server::Server {
+    let config = server::Config { auth_key: Some(Key::new(key)), ..server::Config::default() };
+    server::connect_with("127.0.0.1:0", config).unwrap()
+  }
+
+  #[test]
+  fn authenticates_with_the_right_key() {
+    let server = locked_server("open sesame");
+    let client = connect_with_key(server.local_addr(), &Key::new("open sesame")).unwrap();
+
+    assert!(client.id() > 0);
+    client.send(b"let me in").unwrap();
+    assert_eq!(client.recv().unwrap(), b"let me in");
+    assert_eq!(server.stats().auth_failures, 0);
+  }
+
+  #[test]
+  fn rejects_the_wrong_key() {
+    let server = locked_server("open sesame");
+
+    let wrong = connect_with_key(server.local_addr(), &Key::new("open barley"));
+    assert!(matches!(wrong, Err(Error::Rejected(ref reason)) if reason == "authentication failed"));
+    assert_eq!(server.stats().auth_failures, 1);
+    assert!(matches!(connect(server.local_addr()), Err(Error::Rejected(_))));
+    assert_eq!(server.connection_count(), 0);
+  }
+
+  #[test]
+  fn keyed_client_talks_to_an_open_server() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = connect_with_key(server.local_addr(), &Key::new("unused")).unwrap();
+    client.send(b"hi").unwrap();
+    assert_eq!(client.recv().unwrap(), b"hi");
+  }
+
+  #[test]
+  fn works_as_trait_object() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let transport: Box<dyn Transport> = Box::new(connect(server.local_addr()).unwrap());
+
+    transport.send(b"boxed").unwrap();
+    assert_eq!(transport.recv().unwrap(), b"boxed");
+  }
+}
@@ -0,0 +1,605 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::auth::Key;
+use crate::connection::{check_topic, publish_payload, Connection, Event};
+use crate::error::{Error, Result};
+use crate::network;
+use crate::network::frame::{tag, Frame};
+use crate::rng::XorShift;
+use crate::transport::Transport;
+
+/// Tuning for a `ReconnectingClient`.
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// How often to probe the server when nothing else is going on.
+  pub heartbeat_interval: Duration,
+  /// Silence from the server for this long means the connection is dead.
+  pub heartbeat_timeout: Duration,
+  /// Wait before the first retry; doubles (see `backoff_multiplier`) with every failure.
+  pub initial_backoff: Duration,
+  pub max_backoff: Duration,
+  pub backoff_multiplier: f64,
+  /// Fraction of each delay that is randomised, so a crowd of clients
+  /// doesn't come back all at once. 0 disables it, 1 is full jitter;
+  /// anything outside that is refused by `ReconnectingClient::new`.
+  pub jitter: f64,
+  /// Messages kept for replay while disconnected.
+  pub max_queued: usize,
+  /// Answers the server's challenge, if it sends one.
+  pub key: Option<Key>,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options {
+      heartbeat_interval: Duration::from_secs(1),
+      heartbeat_timeout: Duration::from_secs(3),
+      initial_backoff: Duration::from_millis(100),
+      max_backoff: Duration::from_secs(30),
+      backoff_multiplier: 2.0,
+      jitter: 0.2,
+      max_queued: 1024,
+      key: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+  Connecting,
+  Connected { id: u64 },
+  /// The last attempt failed or the connection died; `attempt` counts
+  /// failures in a row and the next try starts after `retry_in`.
+  Disconnected { attempt: u32, retry_in: Duration },
+  /// `close` was called, nothing more will happen.
+  Closed,
+}
+
+/// A client that keeps itself connected.
+/// A background thread watches the connection with heartbeats and
+/// reconnects with exponential backoff when it dies. While it's down,
+/// outgoing messages are queued and replayed in order once it's back,
+/// and subscriptions are restored. Delivery is at most once: a message
+/// written just before the connection broke may be lost.
+pub struct ReconnectingClient {
+  inner: Arc<Inner>,
+  events: Mutex<Receiver<Result<Event>>>,
+  worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+type Callback = Box<dyn Fn(&State) + Send + Sync>;
+
+struct Inner {
+  addrs: Vec<SocketAddr>,
+  options: Options,
+  link: Mutex<Link>,
+  state: Mutex<State>,
+  changed: Condvar,
+  callbacks: Mutex<Vec<Callback>>,
+  closed: AtomicBool,
+  sender: Mutex<Option<Sender<Result<Event>>>>,
+}
+
+/// The live connection, if any, and what must be replayed on the next one.
+#[derive(Default)]
+struct Link {
+  connection: Option<Arc<Connection>>,
+  queue: VecDeque<Frame>,
+  subscriptions: BTreeSet<String>,
+}
+
+impl ReconnectingClient {
+  /// Prepares a client for `addr`; nothing happens until `start`,
+  /// which leaves room to register callbacks first.
+  pub fn new<A: ToSocketAddrs>(addr: A, options: Options) -> Result<ReconnectingClient> {
+    if !(0.0..=1.0).contains(&options.jitter) {
+      return Err(Error::InvalidMessage(String::from("jitter must be between 0 and 1")));
+    }
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let (sender, events) = mpsc::channel();
+    let inner = Inner {
+      addrs,
+      options,
+      link: Mutex::new(Link::default()),
+      state: Mutex::new(State::Disconnected { attempt: 0, retry_in: Duration::from_secs(0) }),
+      changed: Condvar::new(),
+      callbacks: Mutex::new(Vec::new()),
+      closed: AtomicBool::new(false),
+      sender: Mutex::new(Some(sender)),
+    };
+    Ok(ReconnectingClient {
+      inner: Arc::new(inner),
+      events: Mutex::new(events),
+      worker: Mutex::new(None),
+    })
+  }
+
+  /// Calls `callback` on the client's thread after every state change.
+  pub fn on_state_change<F: Fn(&State) + Send + Sync + 'static>(&self, callback: F) {
+    self.inner.callbacks.lock().unwrap().push(Box::new(callback));
+  }
+
+  /// Starts connecting in the background. Calling it again does nothing.
+  pub fn start(&self) {
+    let mut worker = self.worker.lock().unwrap();
+    if worker.is_some() || self.inner.is_closed() {
+      return;
+    }
+    let sender = match self.inner.sender.lock().unwrap().take() {
+      Some(sender) => sender,
+      None => return,
+    };
+    let inner = Arc::clone(&self.inner);
+    *worker = Some(thread::spawn(move || inner.run(sender)));
+  }
+
+  pub fn state(&self) -> State {
+    self.inner.state.lock().unwrap().clone()
+  }
+
+  /// Blocks until the state satisfies `condition`, or `timeout` runs out.
+  pub fn wait_for<F: Fn(&State) -> bool>(&self, condition: F, timeout: Duration) -> bool {
+    let state = self.inner.state.lock().unwrap();
+    let (state, _) = self.inner.changed.wait_timeout_while(state, timeout, |state| !condition(state)).unwrap();
+    condition(&state)
+  }
+
+  /// Messages waiting for the next connection.
+  pub fn queued(&self) -> usize {
+    self.inner.link.lock().unwrap().queue.len()
+  }
+
+  /// Subscribes now if connected, and again after every reconnect.
+  pub fn subscribe(&self, topic: &str) -> Result<()> {
+    self.inner.set_subscription(topic, true)
+  }
+
+  pub fn unsubscribe(&self, topic: &str) -> Result<()> {
+    self.inner.set_subscription(topic, false)
+  }
+
+  pub fn publish(&self, topic: &str, message: &[u8]) -> Result<()> {
+    self.inner.send_frame(Frame::new(tag::PUBLISH, publish_payload(topic, message)?))
+  }
+
+  pub fn recv_event(&self) -> Result<Event> {
+    self.events.lock().unwrap().recv().unwrap_or(Err(Error::Closed))
+  }
+
+  pub fn recv_event_timeout(&self, timeout: Duration) -> Result<Event> {
+    match self.events.lock().unwrap().recv_timeout(timeout) {
+      Ok(event) => event,
+      Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+      Err(RecvTimeoutError::Disconnected) => Err(Error::Closed),
+    }
+  }
+}
+
+impl Transport for ReconnectingClient {
+  /// Sends right away when connected, otherwise queues for replay.
+  /// Fails with `Error::QueueFull` once `max_queued` messages are waiting.
+  fn send(&self, message: &[u8]) -> Result<()> {
+    self.inner.send_frame(Frame::new(tag::DATA, message))
+  }
+
+  fn recv(&self) -> Result<Vec<u8>> {
+    self.recv_event().map(Event::into_message)
+  }
+
+  /// Drops the connection and stops reconnecting. Queued messages are discarded.
+  fn close(&self) -> Result<()> {
+    self.inner.closed.store(true, Ordering::SeqCst);
+    if let Some(connection) = self.inner.link.lock().unwrap().connection.take() {
+      let _ = connection.close();
+    }
+    // wake up a backoff sleep; taking the lock first means the sleeper
+    // is either before its check of `closed` or already waiting
+    drop(self.inner.state.lock().unwrap());
+    self.inner.changed.notify_all();
+
+    match self.worker.lock().unwrap().take() {
+      Some(worker) => {
+        let _ = worker.join();
+      },
+      None => {
+        self.inner.sender.lock().unwrap().take();
+        self.inner.set_state(State::Closed);
+      },
+    }
+    Ok(())
+  }
+}
+
+impl Drop for ReconnectingClient {
+  fn drop(&mut self) {
+    let _ = self.close();
+  }
+}
+
+impl Inner {
+  fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  fn set_state(&self, state: State) {
+    *self.state.lock().unwrap() = state.clone();
+    self.changed.notify_all();
+    for callback in self.callbacks.lock().unwrap().iter() {
+      callback(&state);
+    }
+  }
+
+  fn run(&self, sender: Sender<Result<Event>>) {
+    let mut backoff = Backoff::new(&self.options);
+    while !self.is_closed() {
+      self.set_state(State::Connecting);
+      if let Ok(connection) = self.establish() {
+        backoff.reset();
+        self.set_state(State::Connected { id: connection.id() });
+        self.pump(&connection, &sender);
+        self.link.lock().unwrap().connection = None;
+        let _ = connection.close();
+      }
+      if self.is_closed() {
+        break;
+      }
+      let retry_in = backoff.next_delay();
+      self.set_state(State::Disconnected { attempt: backoff.attempt(), retry_in });
+      self.sleep(retry_in);
+    }
+    self.set_state(State::Closed);
+  }
+
+  /// Connects, restores subscriptions and flushes the queue.
+  /// The link stays locked throughout, so sends made meanwhile queue up behind the replay.
+  fn establish(&self) -> Result<Arc<Connection>> {
+    let stream = network::connect(&self.addrs[..])?;
+    let mut connection = Connection::new(stream, 0)?;
+    connection.set_read_timeout(Some(self.options.heartbeat_timeout))?;
+    super::handshake(&mut connection, self.options.key.as_ref())?;
+    connection.set_read_timeout(Some(self.options.heartbeat_interval))?;
+
+    let mut link = self.link.lock().unwrap();
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+    for topic in &link.subscriptions {
+      connection.subscribe(topic)?;
+    }
+    while let Some(frame) = link.queue.front() {
+      connection.send_frame(frame)?;
+      link.queue.pop_front();
+    }
+    let connection = Arc::new(connection);
+    link.connection = Some(Arc::clone(&connection));
+    Ok(connection)
+  }
+
+  /// Reads until the connection dies, sending heartbeats whenever the interval passes.
+  fn pump(&self, connection: &Connection, sender: &Sender<Result<Event>>) {
+    let mut last_heard = Instant::now();
+    let mut last_beat = Instant::now();
+    while !self.is_closed() {
+      match connection.recv_frame() {
+        Ok(frame) => {
+          last_heard = Instant::now();
+          if frame.tag != tag::HEARTBEAT {
+            let _ = sender.send(Event::from_frame(frame));
+          }
+        },
+        Err(Error::Timeout) => {},
+        Err(_) => return,
+      }
+      if last_heard.elapsed() >= self.options.heartbeat_timeout {
+        return;
+      }
+      if last_beat.elapsed() >= self.options.heartbeat_interval {
+        if connection.send_frame(&Frame::new(tag::HEARTBEAT, Vec::new())).is_err() {
+          return;
+        }
+        last_beat = Instant::now();
+      }
+    }
+  }
+
+  fn sleep(&self, delay: Duration) {
+    let state = self.state.lock().unwrap();
+    let _ = self.changed.wait_timeout_while(state, delay, |_| !self.is_closed()).unwrap();
+  }
+
+  fn send_frame(&self, frame: Frame) -> Result<()> {
+    if self.is_closed() {
+      return Err(Error::Closed);
+    }
+    let mut link = self.link.lock().unwrap();
+    if let Some(connection) = link.connection.clone() {
+      match connection.send_frame(&frame) {
+        Ok(()) => return Ok(()),
+        Err(err @ Error::FrameTooLarge { .. }) => return Err(err),
+        // let the reader notice as well, and keep the message for the next connection
+        Err(_) => {
+          let _ = connection.close();
+          link.connection = None;
+        },
+      }
+    }
+    if link.queue.len() >= self.options.max_queued {
+      return Err(Error::QueueFull);
+    }
+    link.queue.push_back(frame);
+    Ok(())
+  }
+
+  /// Records a subscription change for replay and passes it on if connected.
+  fn set_subscription(&self, topic: &str, subscribed: bool) -> Result<()> {
+    check_topic(topic)?;
+    let mut link = self.link.lock().unwrap();
+    let frame = if subscribed {
+      link.subscriptions.insert(topic.to_string());
+      Frame::new(tag::SUBSCRIBE, topic)
+    } else {
+      link.subscriptions.remove(topic);
+      Frame::new(tag::UNSUBSCRIBE, topic)
+    };
+    if let Some(connection) = link.connection.clone() {
+      if connection.send_frame(&frame).is_err() {
+        // replayed from `subscriptions` on the next connection anyway
+        let _ = connection.close();
+        link.connection = None;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Exponential backoff with jitter.
+struct Backoff {
+  initial: Duration,
+  max: Duration,
+  multiplier: f64,
+  jitter: f64,
+  attempt: u32,
+  rng: XorShift,
+}
+
+impl Backoff {
+  fn new(options: &Options) -> Backoff {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    Backoff {
+      initial: options.initial_backoff,
+      max: options.max_backoff,
+      multiplier: options.backoff_multiplier.max(1.0),
+      jitter: options.jitter,
+      attempt: 0,
+      rng: XorShift::new(hasher.finish()),
+    }
+  }
+
+  fn attempt(&self) -> u32 {
+    self.attempt
+  }
+
+  fn reset(&mut self) {
+    self.attempt = 0;
+  }
+
+  /// Delay before the next attempt: `initial * multiplier^failures`, capped
+  /// at `max`, with up to `jitter` of it shaved off at random.
+  fn next_delay(&mut self) -> Duration {
+    let base = self.initial.as_secs_f64() * self.multiplier.powi(self.attempt.min(64) as i32);
+    let base = base.min(self.max.as_secs_f64());
+    self.attempt += 1;
+    Duration::from_secs_f64(base * (1.0 - self.jitter * self.rng.next_f64()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::network::server;
+  use std::net::TcpListener;
+  use std::sync::atomic::AtomicUsize;
+
+  fn quick() -> Options {
+    Options {
+      heartbeat_interval: Duration::from_millis(20),
+      heartbeat_timeout: Duration::from_millis(150),
+      initial_backoff: Duration::from_millis(10),
+      max_backoff: Duration::from_millis(50),
+      ..Options::default()
+    }
+  }
+
+  fn connected(state: &State) -> bool {
+    matches!(state, State::Connected { .. })
+  }
+
+  fn disconnected(state: &State) -> bool {
+    matches!(state, State::Disconnected { .. })
+  }
+
+  const PATIENCE: Duration = Duration::from_secs(5);
+
+  #[test]
+  fn backoff_grows_and_caps() {
+    let options = Options {
+      initial_backoff: Duration::from_millis(100),
+      max_backoff: Duration::from_millis(1000),
+      jitter: 0.0,
+      ..Options::default()
+    };
+    let mut backoff = Backoff::new(&options);
+    let delays: Vec<u128> = (0..6).map(|_| backoff.next_delay().as_millis()).collect();
+    assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay().as_millis(), 100);
+  }
+
+  #[test]
+  fn jitter_stays_in_bounds() {
+    let options = Options { initial_backoff: Duration::from_millis(1000), jitter: 0.5, ..Options::default() };
+    let mut backoff = Backoff::new(&options);
+    let mut delays = Vec::new();
+    for _ in 0..100 {
+      backoff.reset();
+      let delay = backoff.next_delay();
+      assert!(delay > Duration::from_millis(500) && delay <= Duration::from_millis(1000), "{:?}", delay);
+      delays.push(delay);
+    }
+    delays.dedup();
+    assert!(delays.len() > 1, "jitter never varied");
+  }
+
+  #[test]
+  fn refuses_jitter_outside_zero_to_one() {
+    for &jitter in &[-0.1, 1.5, f64::NAN] {
+      let options = Options { jitter, ..Options::default() };
+      assert!(matches!(ReconnectingClient::new("127.0.0.1:1", options), Err(Error::InvalidMessage(_))), "{}", jitter);
+    }
+    assert!(ReconnectingClient::new("127.0.0.1:1", Options { jitter: 1.0, ..Options::default() }).is_ok());
+  }
+
+  #[test]
+  fn reconnects_and_replays_after_restart() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let addr = server.local_addr();
+    let client = ReconnectingClient::new(&addr, quick()).unwrap();
+    let history = Arc::new(Mutex::new(Vec::new()));
+    {
+      let history = Arc::clone(&history);
+      client.on_state_change(move |state| history.lock().unwrap().push(state.clone()));
+    }
+    client.start();
+    assert!(client.wait_for(connected, PATIENCE));
+    client.subscribe("news").unwrap();
+
+    server.shutdown();
+    assert!(client.wait_for(disconnected, PATIENCE));
+    client.send(b"first").unwrap();
+    client.send(b"second").unwrap();
+    assert_eq!(client.queued(), 2);
+
+    let server = server::connect(addr).unwrap();
+    assert!(client.wait_for(connected, PATIENCE));
+    assert_eq!(client.recv_event_timeout(PATIENCE).unwrap(), Event::Data(b"first".to_vec()));
+    assert_eq!(client.recv_event_timeout(PATIENCE).unwrap(), Event::Data(b"second".to_vec()));
+    assert_eq!(client.queued(), 0);
+
+    // the subscription came back with the connection
+    let publisher = crate::client::connect(server.local_addr()).unwrap();
+    publisher.publish("news", b"extra").unwrap();
+    let event = client.recv_event_timeout(PATIENCE).unwrap();
+    assert_eq!(event, Event::Published { topic: String::from("news"), message: b"extra".to_vec() });
+
+    let history = history.lock().unwrap();
+    let connects = history.iter().filter(|state| connected(state)).count();
+    assert!(connects >= 2, "{:?}", history);
+    assert!(history.iter().any(disconnected));
+  }
+
+  #[test]
+  fn detects_a_silent_server() {
+    // greets every connection and then never says another word
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    {
+      let accepted = Arc::clone(&accepted);
+      thread::spawn(move || {
+        let mut streams = Vec::new();
+        for stream in listener.incoming() {
+          let mut stream = stream.unwrap();
+          let id = accepted.fetch_add(1, Ordering::SeqCst) as u64 + 1;
+          let hello = Frame::new(tag::HELLO, id.to_be_bytes().to_vec());
+          network::frame::write_frame(&mut stream, &hello, network::frame::DEFAULT_MAX_LEN).unwrap();
+          streams.push(stream);
+        }
+      });
+    }
+
+    let client = ReconnectingClient::new(addr, quick()).unwrap();
+    client.start();
+    assert!(client.wait_for(connected, PATIENCE));
+    assert!(client.wait_for(disconnected, PATIENCE));
+    assert!(client.wait_for(|state| state == &State::Connected { id: 2 }, PATIENCE));
+    assert!(accepted.load(Ordering::SeqCst) >= 2);
+  }
+
+  #[test]
+  fn heartbeats_keep_a_quiet_connection_alive() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let client = ReconnectingClient::new(server.local_addr(), quick()).unwrap();
+    client.start();
+    assert!(client.wait_for(connected, PATIENCE));
+    let id = client.state();
+
+    // several heartbeat timeouts without any traffic from the application
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(client.state(), id);
+  }
+
+  #[test]
+  fn answers_heartbeats() {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    let connection = crate::client::connect(server.local_addr()).unwrap();
+
+    connection.send_frame(&Frame::new(tag::HEARTBEAT, Vec::new())).unwrap();
+    assert_eq!(connection.recv_frame().unwrap().tag, tag::HEARTBEAT);
+    // and plain receives never see them
+    connection.send_frame(&Frame::new(tag::HEARTBEAT, Vec::new())).unwrap();
+    connection.send(b"after").unwrap();
+    assert_eq!(connection.recv().unwrap(), b"after");
+  }
+
+  #[test]
+  fn queue_is_bounded() {
+    // nobody listens on a freshly closed port
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let client = ReconnectingClient::new(addr, Options { max_queued: 2, ..quick() }).unwrap();
+    client.start();
+
+    client.send(b"1").unwrap();
+    client.send(b"2").unwrap();
+    assert!(matches!(client.send(b"3"), Err(Error::QueueFull)));
+  }
+
+  #[test]
+  fn answers_challenges_on_every_connect() {
+    let key = Key::new("open sesame");
+    let config = server::Config { auth_key: Some(key.clone()), ..server::Config::default() };
+    let server = server::connect_with("127.0.0.1:0", config).unwrap();
+    let client = ReconnectingClient::new(server.local_addr(), Options { key: Some(key), ..quick() }).unwrap();
+    client.start();
+
+    client.send(b"hello").unwrap();
+    assert_eq!(client.recv_event_timeout(PATIENCE).unwrap(), Event::Data(b"hello".to_vec()));
+    assert_eq!(server.stats().auth_failures, 0);
+  }
+
+  #[test]
+  fn close_stops_reconnecting() {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let options = Options { initial_backoff: Duration::from_secs(60), ..quick() };
+    let client = ReconnectingClient::new(addr, options).unwrap();
+    client.start();
+    assert!(client.wait_for(disconnected, PATIENCE));
+
+    // sitting in a minute-long backoff, yet close returns right away
+    let started = Instant::now();
+    client.close().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(client.state(), State::Closed);
+    assert!(matches!(client.send(b"late"), Err(Error::Closed)));
+    assert!(matches!(client.recv(), Err(Error::Closed)));
+  }
+}
@@ -0,0 +1,222 @@
+use std::convert::TryInto;
+use std::net::Shutdown;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::network::frame::{self, tag, Frame, FrameReader};
+use crate::network::stream::{Addr, Stream};
+use crate::transport::{FrameTransport, Transport};
+
+/// Longest topic name the wire format can carry.
+pub const MAX_TOPIC_LEN: usize = 255;
+
+/// Something the server delivered to this connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// An echo, or anything else the server sent back directly.
+  Data(Vec<u8>),
+  /// A message another client addressed to this one.
+  Routed { from: u64, message: Vec<u8> },
+  /// A message published to a topic this connection subscribes to.
+  Published { topic: String, message: Vec<u8> },
+}
+
+impl Event {
+  pub fn message(&self) -> &[u8] {
+    match self {
+      Event::Data(message) => message,
+      Event::Routed { message, .. } => message,
+      Event::Published { message, .. } => message,
+    }
+  }
+
+  pub(crate) fn from_frame(frame: Frame) -> Result<Event> {
+    match frame.tag {
+      tag::DATA => Ok(Event::Data(frame.payload)),
+      tag::ROUTE => {
+        let (from, message) = split_route(&frame.payload)?;
+        Ok(Event::Routed { from, message: message.to_vec() })
+      },
+      tag::PUBLISH => {
+        let (topic, message) = split_publish(&frame.payload)?;
+        Ok(Event::Published { topic: topic.to_string(), message: message.to_vec() })
+      },
+      tag::ERROR => Err(Error::Rejected(String::from_utf8_lossy(&frame.payload).into_owned())),
+      other => Err(Error::Protocol(format!("unexpected frame tag {:#04x}", other))),
+    }
+  }
+
+  pub fn into_message(self) -> Vec<u8> {
+    match self {
+      Event::Data(message) => message,
+      Event::Routed { message, .. } => message,
+      Event::Published { message, .. } => message,
+    }
+  }
+}
+
+/// A TCP or Unix socket connection speaking the server's framed protocol.
+/// Reads and writes are locked separately, so a `Connection` can be
+/// shared between a receiving thread and any number of senders.
+pub struct Connection {
+  id: u64,
+  peer_addr: Addr,
+  reader: Mutex<FrameReader<Stream>>,
+  writer: Mutex<Stream>,
+}
+
+impl Connection {
+  /// Wraps an already connected stream.
+  /// `id` is whatever the server assigned, or 0 if it hasn't said yet.
+  pub fn new<S: Into<Stream>>(stream: S, id: u64) -> Result<Connection> {
+    let stream = stream.into();
+    let peer_addr = stream.peer_addr()?;
+    let writer = stream.try_clone()?;
+    Ok(Connection {
+      id,
+      peer_addr,
+      reader: Mutex::new(FrameReader::new(stream)),
+      writer: Mutex::new(writer),
+    })
+  }
+
+  /// The id the server assigned to this connection.
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub(crate) fn set_id(&mut self, id: u64) {
+    self.id = id;
+  }
+
+  pub fn peer_addr(&self) -> &Addr {
+    &self.peer_addr
+  }
+
+  pub fn send_frame(&self, frame: &Frame) -> Result<()> {
+    frame::write_frame(&mut *self.writer.lock().unwrap(), frame, frame::DEFAULT_MAX_LEN)
+  }
+
+  pub fn recv_frame(&self) -> Result<Frame> {
+    self.reader.lock().unwrap().read_frame()
+  }
+
+  /// Asks the server to route `message` to the client with id `peer`.
+  pub fn send_to(&self, peer: u64, message: &[u8]) -> Result<()> {
+    self.send_frame(&Frame::new(tag::ROUTE, route_payload(peer, message)))
+  }
+
+  /// Starts receiving everything published to `topic`.
+  pub fn subscribe(&self, topic: &str) -> Result<()> {
+    check_topic(topic)?;
+    self.send_frame(&Frame::new(tag::SUBSCRIBE, topic))
+  }
+
+  pub fn unsubscribe(&self, topic: &str) -> Result<()> {
+    check_topic(topic)?;
+    self.send_frame(&Frame::new(tag::UNSUBSCRIBE, topic))
+  }
+
+  /// Hands `message` to every current subscriber of `topic`, including this
+  /// connection if it subscribes too.
+  pub fn publish(&self, topic: &str, message: &[u8]) -> Result<()> {
+    self.send_frame(&Frame::new(tag::PUBLISH, publish_payload(topic, message)?))
+  }
+
+  /// Blocks until the server delivers something, skipping heartbeats.
+  /// A refusal from the server comes back as `Error::Rejected`.
+  pub fn recv_event(&self) -> Result<Event> {
+    loop {
+      let frame = self.recv_frame()?;
+      if frame.tag != tag::HEARTBEAT {
+        return Event::from_frame(frame);
+      }
+    }
+  }
+
+  /// Like `recv`, but also says which peer the message was routed from.
+  /// Anything that wasn't routed by a peer comes back with `None`.
+  pub fn recv_from(&self) -> Result<(Option<u64>, Vec<u8>)> {
+    match self.recv_event()? {
+      Event::Routed { from, message } => Ok((Some(from), message)),
+      event => Ok((None, event.into_message())),
+    }
+  }
+
+  /// Bounds how long a receive may block before failing with `Error::Timeout`;
+  /// `None` waits forever.
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+    self.writer.lock().unwrap().set_read_timeout(timeout)?;
+    Ok(())
+  }
+}
+
+impl Transport for Connection {
+  fn send(&self, message: &[u8]) -> Result<()> {
+    self.send_frame(&Frame::new(tag::DATA, message))
+  }
+
+  fn recv(&self) -> Result<Vec<u8>> {
+    self.recv_event().map(Event::into_message)
+  }
+
+  fn close(&self) -> Result<()> {
+    match self.writer.lock().unwrap().shutdown(Shutdown::Both) {
+      // already torn down by the peer
+      Err(ref err) if err.kind() == std::io::ErrorKind::NotConnected => Ok(()),
+      result => Ok(result?),
+    }
+  }
+}
+
+impl FrameTransport for Connection {
+  fn send_frame(&self, frame: &Frame) -> Result<()> {
+    Connection::send_frame(self, frame)
+  }
+
+  fn recv_frame(&self) -> Result<Frame> {
+    Connection::recv_frame(self)
+  }
+}
+
+/// Payload of a `ROUTE` frame: the peer id followed by the message.
+pub(crate) fn route_payload(peer: u64, message: &[u8]) -> Vec<u8> {
+  let mut payload = peer.to_be_bytes().to_vec();
+  payload.extend_from_slice(message);
+  payload
+}
+
+pub(crate) fn split_route(payload: &[u8]) -> Result<(u64, &[u8])> {
+  if payload.len() < 8 {
+    return Err(Error::Protocol(String::from("route frame is missing its peer id")));
+  }
+  let (id, message) = payload.split_at(8);
+  Ok((u64::from_be_bytes(id.try_into().unwrap()), message))
+}
+
+pub(crate) fn check_topic(topic: &str) -> Result<()> {
+  if topic.is_empty() || topic.len() > MAX_TOPIC_LEN {
+    return Err(Error::InvalidMessage(format!("topic must be 1 to {} bytes long", MAX_TOPIC_LEN)));
+  }
+  Ok(())
+}
+
+/// Payload of a `PUBLISH` frame: the topic length, the topic and the message.
+pub(crate) fn publish_payload(topic: &str, message: &[u8]) -> Result<Vec<u8>> {
+  check_topic(topic)?;
+  let mut payload = vec![topic.len() as u8];
+  payload.extend_from_slice(topic.as_bytes());
+  payload.extend_from_slice(message);
+  Ok(payload)
+}
+
+pub(crate) fn split_publish(payload: &[u8]) -> Result<(&str, &[u8])> {
+  let len = *payload.first().ok_or_else(|| Error::Protocol(String::from("publish frame is empty")))? as usize;
+  if len == 0 || payload.len() < 1 + len {
+    return Err(Error::Protocol(String::from("publish frame has a bad topic length")));
+  }
+  let topic = std::str::from_utf8(&payload[1..1 + len])
+    .map_err(|_| Error::Protocol(String::from("topic is not utf-8")))?;
+  Ok((topic, &payload[1 + len..]))
+}
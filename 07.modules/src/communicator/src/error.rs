@@ -0,0 +1,62 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while talking to a peer.
+#[derive(Debug)]
+pub enum Error {
+  /// The underlying socket failed.
+  Io(io::Error),
+  /// The peer closed the connection.
+  Closed,
+  /// Nothing arrived within the configured read timeout.
+  Timeout,
+  /// The message can't be put on the wire or read back as the requested type.
+  InvalidMessage(String),
+  /// The peer sent something that doesn't follow the protocol.
+  Protocol(String),
+  /// A frame is longer than the configured maximum.
+  FrameTooLarge { len: usize, max: usize },
+  /// The server understood the request but refused it.
+  Rejected(String),
+  /// Too many messages are already waiting for the connection to come back.
+  QueueFull,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::Io(err) => write!(f, "i/o error: {}", err),
+      Error::Closed => write!(f, "connection closed"),
+      Error::Timeout => write!(f, "timed out"),
+      Error::InvalidMessage(reason) => write!(f, "invalid message: {}", reason),
+      Error::Protocol(reason) => write!(f, "protocol error: {}", reason),
+      Error::FrameTooLarge { len, max } => write!(f, "frame of {} bytes exceeds the {} byte limit", len, max),
+      Error::Rejected(reason) => write!(f, "rejected by server: {}", reason),
+      Error::QueueFull => write!(f, "outbound queue is full"),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(err: io::Error) -> Error {
+    match err.kind() {
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+      io::ErrorKind::UnexpectedEof
+      | io::ErrorKind::ConnectionReset
+      | io::ErrorKind::ConnectionAborted
+      | io::ErrorKind::BrokenPipe => Error::Closed,
+      _ => Error::Io(err),
+    }
+  }
+}
@@ -1,10 +1,28 @@
-/// If a module named foo has no submodules, you should put the declarations for foo in a file named foo.rs.
-/// If a module named foo does have submodules, you should put the declarations for foo in a file named foo/mod.rs.
+// If a module named foo has no submodules, you should put the declarations for foo in a file named foo.rs.
+// If a module named foo does have submodules, you should put the declarations for foo in a file named foo/mod.rs.
 
-mod network;
-mod client;
+pub mod auth;
+pub mod client;
+pub mod connection;
+pub mod error;
+pub mod loopback;
+pub mod message;
+pub mod network;
+pub mod rpc;
+pub mod transport;
 
+mod rng;
 
+pub use crate::auth::Key;
+pub use crate::connection::{Connection, Event};
+pub use crate::error::{Error, Result};
+pub use crate::loopback::{Faults, Loopback};
+pub use crate::message::Message;
+pub use crate::rpc::{RpcClient, RpcError};
+pub use crate::transport::{FrameTransport, Transport};
+
+/// The crate version, for peers and applications that want to report it.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cfg(test)]
 mod tests {
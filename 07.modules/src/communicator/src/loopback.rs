@@ -0,0 +1,354 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::connection::Event;
+use crate::error::{Error, Result};
+use crate::network::frame::{tag, Frame};
+use crate::rng::Drops;
+use crate::transport::{FrameTransport, Transport};
+
+/// Trouble to inject into a loopback pair. The default is a perfect link.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+  /// Every message arrives this long after it was sent.
+  pub latency: Duration,
+  /// Chance in [0, 1] that a message silently disappears.
+  pub drop_rate: f64,
+  /// Seeds the drop decisions, so a test loses the same messages every run.
+  pub seed: u64,
+  /// Sever the link once this many messages have been sent, counting both directions.
+  pub disconnect_after: Option<usize>,
+}
+
+/// One end of an in-process connection.
+/// Behaves like a `Connection` without a socket: what one end sends the
+/// other receives, in order, subject to the configured `Faults`.
+/// Hand one end to `Server::attach` and the other to e.g. `RpcClient::new`
+/// to run the real server and client over it.
+pub struct Loopback {
+  side: usize,
+  link: Arc<Link>,
+  inbox: Mutex<Inbox>,
+  read_timeout: Mutex<Option<Duration>>,
+}
+
+struct Packet {
+  frame: Frame,
+  deliver_at: Instant,
+}
+
+struct Inbox {
+  receiver: Receiver<Packet>,
+  /// A packet taken off the channel whose latency hadn't passed when the read timed out.
+  early: Option<Packet>,
+}
+
+/// State shared by both ends.
+struct Link {
+  faults: Faults,
+  /// `senders[i]` delivers to side `i`; taking them is what closes the link.
+  senders: Mutex<[Option<Sender<Packet>>; 2]>,
+  severed: AtomicBool,
+  sent: AtomicUsize,
+  dropped: AtomicU64,
+  drops: Drops,
+}
+
+/// A connected pair with no faults.
+pub fn pair() -> (Loopback, Loopback) {
+  pair_with(Faults::default())
+}
+
+pub fn pair_with(faults: Faults) -> (Loopback, Loopback) {
+  let (to_first, first_inbox) = mpsc::channel();
+  let (to_second, second_inbox) = mpsc::channel();
+  let link = Arc::new(Link {
+    drops: Drops::new(faults.drop_rate, faults.seed),
+    faults,
+    senders: Mutex::new([Some(to_first), Some(to_second)]),
+    severed: AtomicBool::new(false),
+    sent: AtomicUsize::new(0),
+    dropped: AtomicU64::new(0),
+  });
+  let end = |side, receiver| Loopback {
+    side,
+    link: Arc::clone(&link),
+    inbox: Mutex::new(Inbox { receiver, early: None }),
+    read_timeout: Mutex::new(None),
+  };
+  (end(0, first_inbox), end(1, second_inbox))
+}
+
+impl Loopback {
+  pub fn send_frame(&self, frame: &Frame) -> Result<()> {
+    if self.link.severed.load(Ordering::SeqCst) {
+      return Err(Error::Closed);
+    }
+    let sent = self.link.sent.fetch_add(1, Ordering::SeqCst) + 1;
+    if self.link.faults.disconnect_after.is_some_and(|limit| sent > limit) {
+      self.disconnect();
+      return Err(Error::Closed);
+    }
+    let senders = self.link.senders.lock().unwrap();
+    let sender = senders[1 - self.side].as_ref().ok_or(Error::Closed)?;
+    if self.link.drops.should_drop() {
+      self.link.dropped.fetch_add(1, Ordering::SeqCst);
+      return Ok(());
+    }
+    let packet = Packet { frame: frame.clone(), deliver_at: Instant::now() + self.link.faults.latency };
+    sender.send(packet).map_err(|_| Error::Closed)
+  }
+
+  /// Blocks until the next frame's latency has passed.
+  /// Once the link is closed, frames already sent are still handed out
+  /// before `Error::Closed`, unless the link was severed by `disconnect`.
+  pub fn recv_frame(&self) -> Result<Frame> {
+    let deadline = self.read_timeout.lock().unwrap().map(|timeout| Instant::now() + timeout);
+    let mut inbox = self.inbox.lock().unwrap();
+    loop {
+      if self.link.severed.load(Ordering::SeqCst) {
+        return Err(Error::Closed);
+      }
+      let packet = match inbox.early.take() {
+        Some(packet) => packet,
+        None => match deadline {
+          None => inbox.receiver.recv().map_err(|_| Error::Closed)?,
+          Some(deadline) => match inbox.receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(packet) => packet,
+            Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::Closed),
+          },
+        },
+      };
+      let now = Instant::now();
+      if packet.deliver_at <= now {
+        return Ok(packet.frame);
+      }
+      match deadline {
+        Some(deadline) if deadline < packet.deliver_at => {
+          thread::sleep(deadline.saturating_duration_since(now));
+          inbox.early = Some(packet);
+          return Err(Error::Timeout);
+        },
+        _ => {
+          thread::sleep(packet.deliver_at - now);
+          inbox.early = Some(packet);
+        },
+      }
+    }
+  }
+
+  /// Bounds how long a receive may block before failing with `Error::Timeout`;
+  /// `None` waits forever.
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+    *self.read_timeout.lock().unwrap() = timeout;
+  }
+
+  /// Cuts the link the hard way: both ends fail with `Error::Closed` right
+  /// away and anything still in flight is lost.
+  pub fn disconnect(&self) {
+    self.link.severed.store(true, Ordering::SeqCst);
+    self.link.close();
+  }
+
+  /// Messages thrown away by `Faults::drop_rate` so far, in both directions.
+  pub fn dropped(&self) -> u64 {
+    self.link.dropped.load(Ordering::SeqCst)
+  }
+}
+
+impl Transport for Loopback {
+  fn send(&self, message: &[u8]) -> Result<()> {
+    self.send_frame(&Frame::new(tag::DATA, message))
+  }
+
+  /// Decodes like `Connection::recv`: heartbeats are skipped, routed and
+  /// published messages come back bare and an ERROR is `Error::Rejected`.
+  fn recv(&self) -> Result<Vec<u8>> {
+    loop {
+      let frame = self.recv_frame()?;
+      if frame.tag != tag::HEARTBEAT {
+        return Event::from_frame(frame).map(Event::into_message);
+      }
+    }
+  }
+
+  /// Closes both directions, like hanging up a socket: the other end
+  /// still reads what was already sent, then sees `Error::Closed`.
+  fn close(&self) -> Result<()> {
+    self.link.close();
+    Ok(())
+  }
+}
+
+impl FrameTransport for Loopback {
+  fn send_frame(&self, frame: &Frame) -> Result<()> {
+    Loopback::send_frame(self, frame)
+  }
+
+  fn recv_frame(&self) -> Result<Frame> {
+    Loopback::recv_frame(self)
+  }
+}
+
+impl Link {
+  fn close(&self) {
+    let mut senders = self.senders.lock().unwrap();
+    senders[0] = None;
+    senders[1] = None;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::connection::{publish_payload, Connection};
+  use std::net::{TcpListener, TcpStream};
+
+  /// Stands in for server logic: answers every message until the peer goes away.
+  fn echo<T: Transport>(transport: &T) -> usize {
+    let mut answered = 0;
+    while let Ok(message) = transport.recv() {
+      if transport.send(&message).is_err() {
+        break;
+      }
+      answered += 1;
+    }
+    answered
+  }
+
+  #[test]
+  fn delivers_in_order() {
+    let (a, b) = pair();
+    for i in 0..100u32 {
+      a.send(&i.to_be_bytes()).unwrap();
+    }
+    for i in 0..100u32 {
+      assert_eq!(b.recv().unwrap(), i.to_be_bytes());
+    }
+  }
+
+  #[test]
+  fn runs_generic_transport_logic() {
+    let (client, server) = pair();
+    let server = thread::spawn(move || echo(&server));
+
+    let client: Box<dyn Transport> = Box::new(client);
+    client.send(b"one").unwrap();
+    assert_eq!(client.recv().unwrap(), b"one");
+    client.send(b"two").unwrap();
+    assert_eq!(client.recv().unwrap(), b"two");
+    client.close().unwrap();
+    assert_eq!(server.join().unwrap(), 2);
+  }
+
+  /// What `recv` makes of the frames a server may send besides DATA.
+  fn decodes_like_a_connection<T: FrameTransport>(from: &T, to: &T) {
+    from.send_frame(&Frame::new(tag::HEARTBEAT, Vec::new())).unwrap();
+    from.send_frame(&Frame::new(tag::PUBLISH, publish_payload("news", b"extra").unwrap())).unwrap();
+    from.send_frame(&Frame::new(tag::ERROR, "go away")).unwrap();
+    assert_eq!(to.recv().unwrap(), b"extra");
+    assert!(matches!(to.recv(), Err(Error::Rejected(ref reason)) if reason == "go away"));
+  }
+
+  #[test]
+  fn recv_decodes_frames_the_same_over_both_transports() {
+    let (a, b) = pair();
+    decodes_like_a_connection(&a, &b);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = Connection::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap(), 0).unwrap();
+    let server = Connection::new(listener.accept().unwrap().0, 0).unwrap();
+    decodes_like_a_connection(&server, &client);
+  }
+
+  #[test]
+  fn carries_frames() {
+    let (a, b) = pair();
+    let frame = Frame::new(tag::MOVE, vec![1, 2, 3]);
+    a.send_frame(&frame).unwrap();
+    assert_eq!(b.recv_frame().unwrap(), frame);
+  }
+
+  #[test]
+  fn delays_by_latency() {
+    let (a, b) = pair_with(Faults { latency: Duration::from_millis(50), ..Faults::default() });
+    let sent = Instant::now();
+    a.send(b"late").unwrap();
+
+    b.set_read_timeout(Some(Duration::from_millis(10)));
+    assert!(matches!(b.recv(), Err(Error::Timeout)));
+    b.set_read_timeout(None);
+    assert_eq!(b.recv().unwrap(), b"late");
+    assert!(sent.elapsed() >= Duration::from_millis(50));
+  }
+
+  #[test]
+  fn drops_the_same_messages_for_the_same_seed() {
+    let survivors = |seed| {
+      let (a, b) = pair_with(Faults { drop_rate: 0.3, seed, ..Faults::default() });
+      for i in 0..200u8 {
+        a.send(&[i]).unwrap();
+      }
+      a.close().unwrap();
+      let mut received = Vec::new();
+      while let Ok(message) = b.recv() {
+        received.push(message[0]);
+      }
+      assert_eq!(received.len() as u64 + a.dropped(), 200);
+      received
+    };
+
+    let first = survivors(42);
+    assert_eq!(first, survivors(42));
+    assert_ne!(first, survivors(7));
+    assert!(first.len() > 100 && first.len() < 180, "kept {}", first.len());
+  }
+
+  #[test]
+  fn close_lets_the_peer_drain() {
+    let (a, b) = pair();
+    a.send(b"last words").unwrap();
+    a.close().unwrap();
+
+    assert!(matches!(a.send(b"too late"), Err(Error::Closed)));
+    assert_eq!(b.recv().unwrap(), b"last words");
+    assert!(matches!(b.recv(), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn disconnect_cuts_both_ends() {
+    let (a, b) = pair();
+    a.send(b"lost").unwrap();
+    b.disconnect();
+
+    assert!(matches!(b.recv(), Err(Error::Closed)));
+    assert!(matches!(a.recv(), Err(Error::Closed)));
+    assert!(matches!(a.send(b"x"), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn disconnect_wakes_a_blocked_reader() {
+    let (a, b) = pair();
+    let reader = thread::spawn(move || b.recv());
+    thread::sleep(Duration::from_millis(20));
+    a.disconnect();
+    assert!(matches!(reader.join().unwrap(), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn disconnects_after_a_message_budget() {
+    let (a, b) = pair_with(Faults { disconnect_after: Some(3), ..Faults::default() });
+    let server = thread::spawn(move || echo(&b));
+
+    a.send(b"1").unwrap();
+    assert_eq!(a.recv().unwrap(), b"1");
+    a.send(b"2").unwrap();
+    // the echo of "2" is the fourth message and never makes it
+    assert!(matches!(a.recv(), Err(Error::Closed)));
+    assert_eq!(server.join().unwrap(), 1);
+  }
+}
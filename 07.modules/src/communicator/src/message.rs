@@ -0,0 +1,73 @@
+use crate::error::{Error, Result};
+use crate::network::frame::{tag, Frame};
+
+/// The `Message` enum from the enums chapter, made sendable.
+/// Each variant travels as its own frame type, with integers in big-endian
+/// and text as raw utf-8, so nothing is lost on the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+  Quit,
+  Move { x: i32, y: i32 },
+  Write(String),
+  ChangeColor(i32, i32, i32),
+}
+
+impl Message {
+  pub fn to_frame(&self) -> Frame {
+    match self {
+      Message::Quit => Frame::new(tag::QUIT, Vec::new()),
+      Message::Move { x, y } => Frame::new(tag::MOVE, encode_ints(&[*x, *y])),
+      Message::Write(text) => Frame::new(tag::WRITE, text.as_bytes()),
+      Message::ChangeColor(r, g, b) => Frame::new(tag::CHANGE_COLOR, encode_ints(&[*r, *g, *b])),
+    }
+  }
+
+  pub fn from_frame(frame: &Frame) -> Result<Message> {
+    match frame.tag {
+      tag::QUIT => {
+        decode_ints::<0>(&frame.payload)?;
+        Ok(Message::Quit)
+      },
+      tag::MOVE => {
+        let [x, y] = decode_ints(&frame.payload)?;
+        Ok(Message::Move { x, y })
+      },
+      tag::WRITE => String::from_utf8(frame.payload.clone())
+        .map(Message::Write)
+        .map_err(|_| Error::Protocol(String::from("Write message is not utf-8"))),
+      tag::CHANGE_COLOR => {
+        let [r, g, b] = decode_ints(&frame.payload)?;
+        Ok(Message::ChangeColor(r, g, b))
+      },
+      other => Err(Error::InvalidMessage(format!("frame tag {:#04x} is not a Message", other))),
+    }
+  }
+}
+
+fn encode_ints(ints: &[i32]) -> Vec<u8> {
+  ints.iter().flat_map(|i| i.to_be_bytes()).collect()
+}
+
+fn decode_ints<const N: usize>(payload: &[u8]) -> Result<[i32; N]> {
+  if payload.len() != N * 4 {
+    return Err(Error::Protocol(format!("expected {} bytes, got {}", N * 4, payload.len())));
+  }
+  let mut ints = [0; N];
+  for (int, bytes) in ints.iter_mut().zip(payload.chunks_exact(4)) {
+    *int = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+  }
+  Ok(ints)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_malformed_payloads() {
+    assert!(matches!(Message::from_frame(&Frame::new(tag::MOVE, vec![0; 7])), Err(Error::Protocol(_))));
+    assert!(matches!(Message::from_frame(&Frame::new(tag::QUIT, vec![0])), Err(Error::Protocol(_))));
+    assert!(matches!(Message::from_frame(&Frame::new(tag::WRITE, vec![0xff])), Err(Error::Protocol(_))));
+    assert!(matches!(Message::from_frame(&Frame::new(tag::DATA, vec![])), Err(Error::InvalidMessage(_))));
+  }
+}
@@ -0,0 +1,270 @@
+use std::io::{self, Read, Write};
+
+use crate::error::{Error, Result};
+
+/// Every frame starts with a 4 byte big-endian payload length followed by a 1 byte type tag.
+pub const HEADER_LEN: usize = 5;
+
+/// Frames bigger than this are refused unless a reader or decoder is told otherwise.
+pub const DEFAULT_MAX_LEN: usize = 1 << 20;
+
+/// Type tags understood by the server and client.
+/// All tags live here so the different layers can't hand out the same number twice.
+pub mod tag {
+  /// Application payload, echoed back by the server.
+  pub const DATA: u8 = 0x01;
+  /// Server greeting carrying the connection id as a big-endian u64.
+  pub const HELLO: u8 = 0x02;
+  /// A big-endian u64 peer id followed by the payload to deliver to that peer.
+  /// The server rewrites the id to the sender's before forwarding.
+  pub const ROUTE: u8 = 0x03;
+  /// The server refused a request; the payload is a utf-8 reason.
+  pub const ERROR: u8 = 0x04;
+  /// Start receiving everything published to the utf-8 topic in the payload.
+  pub const SUBSCRIBE: u8 = 0x05;
+  /// Stop receiving a topic.
+  pub const UNSUBSCRIBE: u8 = 0x06;
+  /// A one byte topic length, the topic, then the message.
+  /// Subscribers receive the frame exactly as it was published.
+  pub const PUBLISH: u8 = 0x07;
+  /// Liveness probe with an empty payload. The server echoes it like any
+  /// other frame it has no special meaning for.
+  pub const HEARTBEAT: u8 = 0x08;
+  /// A big-endian u64 request id, a one byte method length, the method and the request body.
+  pub const RPC_REQUEST: u8 = 0x09;
+  /// The request id, a one byte status (see `crate::rpc`) and the result or error text.
+  pub const RPC_RESPONSE: u8 = 0x0a;
+  /// Sent instead of `HELLO` by a server that wants a key: a random nonce
+  /// (see `crate::auth`). The client has to answer before it is greeted.
+  pub const CHALLENGE: u8 = 0x0b;
+  /// The client's answer to a `CHALLENGE`, an HMAC-SHA256 of the nonce.
+  pub const AUTH: u8 = 0x0c;
+
+  /// `Message` variants, see `crate::message`.
+  pub const QUIT: u8 = 0x10;
+  pub const MOVE: u8 = 0x11;
+  pub const WRITE: u8 = 0x12;
+  pub const CHANGE_COLOR: u8 = 0x13;
+}
+
+/// One unit on the wire: a type tag and an opaque payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  pub tag: u8,
+  pub payload: Vec<u8>,
+}
+
+impl Frame {
+  pub fn new<P: Into<Vec<u8>>>(tag: u8, payload: P) -> Frame {
+    Frame { tag, payload: payload.into() }
+  }
+
+  /// Header and payload as they go on the wire.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+    bytes.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+    bytes.push(self.tag);
+    bytes.extend_from_slice(&self.payload);
+    bytes
+  }
+}
+
+/// Reassembles frames from bytes that arrive in arbitrary pieces.
+/// After an error the byte stream can't be trusted any more and the
+/// connection it came from should be dropped.
+pub struct Decoder {
+  buf: Vec<u8>,
+  max_len: usize,
+}
+
+impl Decoder {
+  pub fn new() -> Decoder {
+    Decoder::with_max_len(DEFAULT_MAX_LEN)
+  }
+
+  pub fn with_max_len(max_len: usize) -> Decoder {
+    Decoder { buf: Vec::new(), max_len }
+  }
+
+  /// Appends freshly read bytes.
+  pub fn extend(&mut self, bytes: &[u8]) {
+    self.buf.extend_from_slice(bytes);
+  }
+
+  /// Bytes received that aren't part of a returned frame yet.
+  pub fn buffered(&self) -> usize {
+    self.buf.len()
+  }
+
+  /// Takes the next complete frame out of the buffer, or `None` if more bytes are needed.
+  /// An oversized length is rejected as soon as the header is in,
+  /// without waiting for (or buffering) the payload.
+  pub fn decode(&mut self) -> Result<Option<Frame>> {
+    if self.buf.len() < HEADER_LEN {
+      return Ok(None);
+    }
+    let len = u32::from_be_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]) as usize;
+    if len > self.max_len {
+      return Err(Error::FrameTooLarge { len, max: self.max_len });
+    }
+    if self.buf.len() < HEADER_LEN + len {
+      return Ok(None);
+    }
+    let tag = self.buf[4];
+    let payload = self.buf[HEADER_LEN..HEADER_LEN + len].to_vec();
+    self.buf.drain(..HEADER_LEN + len);
+    Ok(Some(Frame { tag, payload }))
+  }
+}
+
+impl Default for Decoder {
+  fn default() -> Decoder {
+    Decoder::new()
+  }
+}
+
+/// Reads whole frames from a byte stream.
+/// Partial frames survive a read timeout, so the caller can simply try again.
+pub struct FrameReader<R> {
+  inner: R,
+  decoder: Decoder,
+}
+
+impl<R: Read> FrameReader<R> {
+  pub fn new(inner: R) -> FrameReader<R> {
+    FrameReader::with_max_len(inner, DEFAULT_MAX_LEN)
+  }
+
+  pub fn with_max_len(inner: R, max_len: usize) -> FrameReader<R> {
+    FrameReader { inner, decoder: Decoder::with_max_len(max_len) }
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  /// Blocks until a whole frame is available.
+  /// A stream that ends between frames is `Error::Closed`, one that ends inside a frame is a protocol error.
+  pub fn read_frame(&mut self) -> Result<Frame> {
+    let mut chunk = [0; 4096];
+    loop {
+      if let Some(frame) = self.decoder.decode()? {
+        return Ok(frame);
+      }
+      let n = match self.inner.read(&mut chunk) {
+        Ok(n) => n,
+        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err.into()),
+      };
+      if n == 0 {
+        return match self.decoder.buffered() {
+          0 => Err(Error::Closed),
+          _ => Err(Error::Protocol(String::from("stream ended inside a frame"))),
+        };
+      }
+      self.decoder.extend(&chunk[..n]);
+    }
+  }
+}
+
+/// Writes `frame` in a single call so concurrent writers holding the same lock never interleave.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame, max_len: usize) -> Result<()> {
+  if frame.payload.len() > max_len {
+    return Err(Error::FrameTooLarge { len: frame.payload.len(), max: max_len });
+  }
+  writer.write_all(&frame.encode())?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::Message;
+
+  /// Hands out at most `step` bytes per read, like a congested socket.
+  struct Trickle {
+    bytes: Vec<u8>,
+    pos: usize,
+    step: usize,
+  }
+
+  impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let n = self.step.min(buf.len()).min(self.bytes.len() - self.pos);
+      buf[..n].copy_from_slice(&self.bytes[self.pos..self.pos + n]);
+      self.pos += n;
+      Ok(n)
+    }
+  }
+
+  fn messages() -> Vec<Message> {
+    vec![
+      Message::Quit,
+      Message::Move { x: -3, y: i32::MAX },
+      Message::Write(String::from("hello\nworld \u{1f980}")),
+      Message::Write(String::new()),
+      Message::ChangeColor(255, 0, i32::MIN),
+    ]
+  }
+
+  #[test]
+  fn round_trips_every_message() {
+    for message in messages() {
+      let mut decoder = Decoder::new();
+      decoder.extend(&message.to_frame().encode());
+      let frame = decoder.decode().unwrap().unwrap();
+      assert_eq!(Message::from_frame(&frame).unwrap(), message);
+      assert_eq!(decoder.buffered(), 0);
+    }
+  }
+
+  #[test]
+  fn reassembles_frames_split_across_reads() {
+    let frames: Vec<Frame> = messages().iter().map(Message::to_frame).collect();
+    let bytes: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+
+    for step in 1..=bytes.len() {
+      let mut reader = FrameReader::new(Trickle { bytes: bytes.clone(), pos: 0, step });
+      for frame in &frames {
+        assert_eq!(&reader.read_frame().unwrap(), frame, "step {}", step);
+      }
+      assert!(matches!(reader.read_frame(), Err(Error::Closed)));
+    }
+  }
+
+  #[test]
+  fn decodes_several_frames_from_one_chunk() {
+    let mut decoder = Decoder::new();
+    decoder.extend(&Frame::new(tag::DATA, "a").encode());
+    decoder.extend(&Frame::new(tag::DATA, "").encode());
+    decoder.extend(&Frame::new(tag::DATA, "b").encode()[..3]);
+
+    assert_eq!(decoder.decode().unwrap(), Some(Frame::new(tag::DATA, "a")));
+    assert_eq!(decoder.decode().unwrap(), Some(Frame::new(tag::DATA, "")));
+    assert_eq!(decoder.decode().unwrap(), None);
+    assert_eq!(decoder.buffered(), 3);
+  }
+
+  #[test]
+  fn rejects_oversized_frames() {
+    let frame = Frame::new(tag::DATA, vec![0; 17]);
+    let mut sink = Vec::new();
+    assert!(matches!(
+      write_frame(&mut sink, &frame, 16),
+      Err(Error::FrameTooLarge { len: 17, max: 16 })
+    ));
+    assert!(sink.is_empty());
+
+    // only the header has arrived, but that's already enough to refuse it
+    let mut decoder = Decoder::with_max_len(16);
+    decoder.extend(&frame.encode()[..HEADER_LEN]);
+    assert!(matches!(decoder.decode(), Err(Error::FrameTooLarge { len: 17, max: 16 })));
+  }
+
+  #[test]
+  fn reports_truncated_stream() {
+    let bytes = Frame::new(tag::DATA, "truncated").encode();
+    let mut reader = FrameReader::new(&bytes[..bytes.len() - 1]);
+    assert!(matches!(reader.read_frame(), Err(Error::Protocol(_))));
+  }
+}
@@ -0,0 +1,408 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::network::stream::Stream;
+
+/// Longest request or header line we are willing to buffer.
+pub const MAX_LINE_LEN: usize = 8 * 1024;
+/// Most headers a single request may carry.
+pub const MAX_HEADERS: usize = 100;
+
+/// How long a client may keep a connection, and with it a worker, without
+/// getting anywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+  /// Wait for the first byte of a request, including the first one.
+  pub idle: Duration,
+  /// Time from the first byte of a request until its head, and any body
+  /// that has to be skipped, is in. Bounds the whole request rather than
+  /// each read, so trickling a byte at a time doesn't help.
+  pub request: Duration,
+}
+
+impl Default for Timeouts {
+  fn default() -> Timeouts {
+    Timeouts { idle: Duration::from_secs(5), request: Duration::from_secs(10) }
+  }
+}
+
+/// The parts of a request a static file server cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+  pub method: String,
+  pub target: String,
+  pub version: String,
+  /// In the order they arrived, names as sent.
+  pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+  /// First value of the header `name`, matched case-insensitively.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+  }
+
+  /// HTTP/1.1 keeps the connection open unless told otherwise, HTTP/1.0 only when asked.
+  pub fn keep_alive(&self) -> bool {
+    let connection = self.header("Connection").map(str::to_ascii_lowercase);
+    match connection.as_deref() {
+      Some("close") => false,
+      Some("keep-alive") => true,
+      _ => self.version == "HTTP/1.1",
+    }
+  }
+}
+
+/// Reads one request head, up to and including the blank line.
+/// Returns `Ok(None)` if the client hung up between requests.
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Option<Request>> {
+  let mut line = match read_line(reader)? {
+    Some(line) => line,
+    None => return Ok(None),
+  };
+  // a stray empty line before the request line is allowed
+  if line.is_empty() {
+    line = read_line(reader)?.ok_or(Error::Closed)?;
+  }
+
+  let mut parts = line.split(' ');
+  let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+    (Some(method), Some(target), Some(version), None) if !method.is_empty() && !target.is_empty() => (method, target, version),
+    _ => return Err(bad_request("malformed request line")),
+  };
+  if version != "HTTP/1.1" && version != "HTTP/1.0" {
+    return Err(bad_request("unsupported http version"));
+  }
+
+  let mut headers = Vec::new();
+  loop {
+    let line = read_line(reader)?.ok_or_else(|| bad_request("request ended inside the headers"))?;
+    if line.is_empty() {
+      break;
+    }
+    if headers.len() == MAX_HEADERS {
+      return Err(bad_request("too many headers"));
+    }
+    let (name, value) = line.split_once(':').ok_or_else(|| bad_request("malformed header"))?;
+    if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+      return Err(bad_request("malformed header"));
+    }
+    headers.push((name.to_string(), value.trim().to_string()));
+  }
+
+  Ok(Some(Request { method: method.to_string(), target: target.to_string(), version: version.to_string(), headers }))
+}
+
+/// One line without its CRLF, or `None` on a clean EOF.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+  let mut line = Vec::new();
+  let read = reader.take(MAX_LINE_LEN as u64 + 2).read_until(b'\n', &mut line)?;
+  if read == 0 {
+    return Ok(None);
+  }
+  if !line.ends_with(b"\n") {
+    return Err(bad_request(if line.len() > MAX_LINE_LEN { "line too long" } else { "request ended mid-line" }));
+  }
+  line.pop();
+  if line.ends_with(b"\r") {
+    line.pop();
+  }
+  String::from_utf8(line).map(Some).map_err(|_| bad_request("request is not utf-8"))
+}
+
+fn bad_request(reason: &str) -> Error {
+  Error::Protocol(reason.to_string())
+}
+
+/// Guesses a Content-Type from the file extension.
+pub fn content_type(path: &Path) -> &'static str {
+  let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+  match extension.as_deref() {
+    Some("html") | Some("htm") => "text/html; charset=utf-8",
+    Some("css") => "text/css; charset=utf-8",
+    Some("js") => "text/javascript; charset=utf-8",
+    Some("json") => "application/json",
+    Some("txt") => "text/plain; charset=utf-8",
+    Some("svg") => "image/svg+xml",
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("ico") => "image/x-icon",
+    Some("wasm") => "application/wasm",
+    Some("pdf") => "application/pdf",
+    _ => "application/octet-stream",
+  }
+}
+
+/// Why `resolve` turned a request target down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unresolved {
+  /// Not an absolute path, or a `%` escape that doesn't decode to UTF-8.
+  Malformed,
+  /// Tries to leave the root, through `..` or a symlink.
+  Outside,
+}
+
+/// Maps a request target onto a file under `root`.
+pub fn resolve(root: &Path, target: &str) -> std::result::Result<PathBuf, Unresolved> {
+  let path = target.split(['?', '#']).next().unwrap_or("");
+  if !path.starts_with('/') {
+    return Err(Unresolved::Malformed);
+  }
+  let decoded = percent_decode(path).ok_or(Unresolved::Malformed)?;
+
+  let mut resolved = root.to_path_buf();
+  for segment in decoded.split('/') {
+    match segment {
+      "" | "." => continue,
+      ".." => return Err(Unresolved::Outside),
+      // a decoded backslash or NUL could mean something else to the filesystem
+      _ if segment.contains(['\\', '\0']) => return Err(Unresolved::Outside),
+      _ => resolved.push(segment),
+    }
+  }
+  if resolved.is_dir() {
+    resolved.push("index.html");
+  }
+
+  // symlinks inside the root may still point outside it
+  match (resolved.canonicalize(), root.canonicalize()) {
+    (Ok(file), Ok(root)) if !file.starts_with(&root) => Err(Unresolved::Outside),
+    _ => Ok(resolved),
+  }
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+      decoded.push(u8::from_str_radix(hex, 16).ok()?);
+      i += 3;
+    } else {
+      decoded.push(bytes[i]);
+      i += 1;
+    }
+  }
+  String::from_utf8(decoded).ok()
+}
+
+struct Response {
+  status: u16,
+  reason: &'static str,
+  content_type: &'static str,
+  body: Vec<u8>,
+  allow: bool,
+}
+
+impl Response {
+  fn file(path: &Path, body: Vec<u8>) -> Response {
+    Response { status: 200, reason: "OK", content_type: content_type(path), body, allow: false }
+  }
+
+  fn error(status: u16, reason: &'static str) -> Response {
+    let body = format!("{} {}\n", status, reason).into_bytes();
+    Response { status, reason, content_type: "text/plain; charset=utf-8", body, allow: status == 405 }
+  }
+
+  /// `HEAD` gets the same headers as `GET`, Content-Length included, but no body.
+  fn write_to<W: Write>(&self, w: &mut W, head_only: bool, keep_alive: bool) -> io::Result<()> {
+    let mut head = format!(
+      "HTTP/1.1 {} {}\r\nServer: communicator/{}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n",
+      self.status,
+      self.reason,
+      crate::VERSION,
+      self.content_type,
+      self.body.len(),
+      if keep_alive { "keep-alive" } else { "close" },
+    );
+    if self.allow {
+      head.push_str("Allow: GET, HEAD\r\n");
+    }
+    head.push_str("\r\n");
+    w.write_all(head.as_bytes())?;
+    if !head_only {
+      w.write_all(&self.body)?;
+    }
+    w.flush()
+  }
+}
+
+/// Reads from a stream, failing with `TimedOut` once `deadline` has passed.
+/// The read timeout is set again before every read, so a deadline holds
+/// however the reads are split up.
+struct Deadline<'a> {
+  stream: &'a Stream,
+  deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let left = self.deadline.saturating_duration_since(Instant::now());
+    if left == Duration::from_secs(0) {
+      return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+    }
+    self.stream.set_read_timeout(Some(left))?;
+    self.stream.read(buf)
+  }
+}
+
+/// Tells a client there's no room for it. The caller hangs up.
+pub(crate) fn unavailable(stream: &Stream) -> Result<()> {
+  Response::error(503, "Service Unavailable").write_to(&mut &*stream, false, false)?;
+  Ok(())
+}
+
+/// Answers requests on `stream` with files from `root` until the client
+/// closes the connection, asks to, sends something we can't parse, or
+/// runs out of `timeouts`.
+pub fn serve(stream: &Stream, root: &Path, timeouts: Timeouts) -> Result<()> {
+  let mut reader = BufReader::new(Deadline { stream, deadline: Instant::now() + timeouts.idle });
+  let mut writer = stream;
+
+  loop {
+    // an idle client is just let go, pipelined requests are already buffered
+    if reader.buffer().is_empty() {
+      reader.get_mut().deadline = Instant::now() + timeouts.idle;
+      match reader.fill_buf() {
+        Ok([]) | Err(_) => return Ok(()),
+        Ok(_) => {},
+      }
+    }
+    reader.get_mut().deadline = Instant::now() + timeouts.request;
+
+    let request = match read_request(&mut reader) {
+      Ok(Some(request)) => request,
+      Ok(None) | Err(Error::Closed) => return Ok(()),
+      Err(err @ Error::Protocol(_)) => {
+        // we can't tell where the next request would start, so give up on the connection
+        let _ = Response::error(400, "Bad Request").write_to(&mut writer, false, false);
+        return Err(err);
+      },
+      Err(Error::Timeout) => {
+        let _ = Response::error(408, "Request Timeout").write_to(&mut writer, false, false);
+        return Err(Error::Timeout);
+      },
+      Err(err) => return Err(err),
+    };
+
+    let body_len = match body_len(&request) {
+      Ok(len) => len,
+      Err(err) => {
+        let _ = Response::error(400, "Bad Request").write_to(&mut writer, false, false);
+        return Err(err);
+      },
+    };
+    // nothing here reads a body, but it has to be skipped to find the next request
+    io::copy(&mut (&mut reader).take(body_len), &mut io::sink())?;
+
+    let keep_alive = request.keep_alive();
+    let response = respond(&request, root);
+    response.write_to(&mut writer, request.method == "HEAD", keep_alive)?;
+    if !keep_alive {
+      return Ok(());
+    }
+  }
+}
+
+fn body_len(request: &Request) -> Result<u64> {
+  if request.header("Transfer-Encoding").is_some() {
+    return Err(bad_request("chunked request bodies are not supported"));
+  }
+  match request.header("Content-Length") {
+    Some(len) => len.parse().map_err(|_| bad_request("invalid content-length")),
+    None => Ok(0),
+  }
+}
+
+fn respond(request: &Request, root: &Path) -> Response {
+  if request.method != "GET" && request.method != "HEAD" {
+    return Response::error(405, "Method Not Allowed");
+  }
+  let path = match resolve(root, &request.target) {
+    Ok(path) => path,
+    Err(Unresolved::Malformed) => return Response::error(400, "Bad Request"),
+    Err(Unresolved::Outside) => return Response::error(403, "Forbidden"),
+  };
+  match fs::read(&path) {
+    Ok(body) => Response::file(&path, body),
+    Err(_) => Response::error(404, "Not Found"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+
+  fn parse(raw: &str) -> Result<Option<Request>> {
+    read_request(&mut Cursor::new(raw.as_bytes()))
+  }
+
+  #[test]
+  fn parses_request_line_and_headers() {
+    let request = parse("GET /index.html HTTP/1.1\r\nHost: localhost\r\nX-Thing:  spaced  \r\n\r\n").unwrap().unwrap();
+    assert_eq!(request.method, "GET");
+    assert_eq!(request.target, "/index.html");
+    assert_eq!(request.version, "HTTP/1.1");
+    assert_eq!(request.header("host"), Some("localhost"));
+    assert_eq!(request.header("X-THING"), Some("spaced"));
+    assert!(request.keep_alive());
+  }
+
+  #[test]
+  fn rejects_malformed_requests() {
+    assert!(parse("").unwrap().is_none());
+    for raw in &[
+      "GET /\r\n\r\n",
+      "GET / HTTP/1.1 extra\r\n\r\n",
+      "GET / HTTP/2.0\r\n\r\n",
+      "GET / HTTP/1.1\r\nno colon\r\n\r\n",
+      "GET / HTTP/1.1\r\nBad Name: x\r\n\r\n",
+      "GET / HTTP/1.1\r\nHost: x\r\n",
+    ] {
+      assert!(matches!(parse(raw), Err(Error::Protocol(_))), "{:?}", raw);
+    }
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+    assert!(matches!(parse(&long), Err(Error::Protocol(_))));
+  }
+
+  #[test]
+  fn decides_keep_alive_by_version() {
+    let request = |version: &str, connection: &str| Request {
+      method: String::from("GET"),
+      target: String::from("/"),
+      version: version.to_string(),
+      headers: vec![(String::from("Connection"), connection.to_string())],
+    };
+    assert!(!request("HTTP/1.1", "close").keep_alive());
+    assert!(!request("HTTP/1.0", "").keep_alive());
+    assert!(request("HTTP/1.0", "Keep-Alive").keep_alive());
+  }
+
+  #[test]
+  fn guesses_content_types() {
+    assert_eq!(content_type(Path::new("a/index.HTML")), "text/html; charset=utf-8");
+    assert_eq!(content_type(Path::new("logo.png")), "image/png");
+    assert_eq!(content_type(Path::new("Makefile")), "application/octet-stream");
+  }
+
+  #[test]
+  fn resolves_inside_the_root_only() {
+    let root = Path::new("/srv/www");
+    assert_eq!(resolve(root, "/a/b.txt?x=1"), Ok(PathBuf::from("/srv/www/a/b.txt")));
+    assert_eq!(resolve(root, "/a%20b.txt"), Ok(PathBuf::from("/srv/www/a b.txt")));
+    assert_eq!(resolve(root, "/../etc/passwd"), Err(Unresolved::Outside));
+    assert_eq!(resolve(root, "/a/%2e%2e/%2e%2e/etc/passwd"), Err(Unresolved::Outside));
+    assert_eq!(resolve(root, "/a%5c..%5cb"), Err(Unresolved::Outside));
+    assert_eq!(resolve(root, "/bad%zz"), Err(Unresolved::Malformed));
+    assert_eq!(resolve(root, "/bad%f"), Err(Unresolved::Malformed));
+    assert_eq!(resolve(root, "/bad%ff"), Err(Unresolved::Malformed));
+    assert_eq!(resolve(root, "relative"), Err(Unresolved::Malformed));
+  }
+}
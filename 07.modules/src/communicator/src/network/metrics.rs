@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Topics counted one by one; publications to any more go in one bucket,
+/// so clients making up topic names can't grow the stats without bound.
+pub const MAX_TOPICS: usize = 256;
+
+/// Counters the server bumps as traffic goes through.
+/// Connection counts live with the broker, which already knows them.
+#[derive(Default)]
+pub(crate) struct Metrics {
+  bytes_in: AtomicU64,
+  bytes_out: AtomicU64,
+  frames_in: AtomicU64,
+  frames_out: AtomicU64,
+  protocol_errors: AtomicU64,
+  refusals: AtomicU64,
+  handler_errors: AtomicU64,
+  auth_failures: AtomicU64,
+  published: Mutex<BTreeMap<String, u64>>,
+  published_other: AtomicU64,
+  calls: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Metrics {
+  pub(crate) fn received(&self, bytes: usize) {
+    self.frames_in.fetch_add(1, Ordering::Relaxed);
+    self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+  }
+
+  pub(crate) fn sent(&self, bytes: usize) {
+    self.frames_out.fetch_add(1, Ordering::Relaxed);
+    self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+  }
+
+  /// A client sent something that broke the framing, and was hung up on.
+  pub(crate) fn protocol_error(&self) {
+    self.protocol_errors.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// A well-formed request was answered with an `ERROR` frame.
+  pub(crate) fn refused(&self) {
+    self.refusals.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// An RPC handler returned an error or panicked.
+  pub(crate) fn handler_error(&self) {
+    self.handler_errors.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// A client didn't answer the challenge with the right key, or in time, or at all.
+  pub(crate) fn auth_failure(&self) {
+    self.auth_failures.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// A message went out to the subscribers of `topic`.
+  pub(crate) fn published(&self, topic: &str) {
+    let mut published = self.published.lock().unwrap();
+    if let Some(count) = published.get_mut(topic) {
+      *count += 1;
+    } else if published.len() < MAX_TOPICS {
+      published.insert(topic.to_string(), 1);
+    } else {
+      self.published_other.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  pub(crate) fn called(&self, method: &str) {
+    *self.calls.lock().unwrap().entry(method.to_string()).or_insert(0) += 1;
+  }
+
+  pub(crate) fn snapshot(&self, connections_active: usize, connections_total: u64, dropped: u64) -> Stats {
+    Stats {
+      connections_active: connections_active as u64,
+      connections_total,
+      bytes_in: self.bytes_in.load(Ordering::Relaxed),
+      bytes_out: self.bytes_out.load(Ordering::Relaxed),
+      frames_in: self.frames_in.load(Ordering::Relaxed),
+      frames_out: self.frames_out.load(Ordering::Relaxed),
+      dropped,
+      protocol_errors: self.protocol_errors.load(Ordering::Relaxed),
+      refusals: self.refusals.load(Ordering::Relaxed),
+      handler_errors: self.handler_errors.load(Ordering::Relaxed),
+      auth_failures: self.auth_failures.load(Ordering::Relaxed),
+      published: self.published.lock().unwrap().clone(),
+      published_other: self.published_other.load(Ordering::Relaxed),
+      calls: self.calls.lock().unwrap().clone(),
+    }
+  }
+}
+
+/// A copy of the server's counters at one moment.
+/// Bytes and frames count the framed protocol only, headers included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+  pub connections_active: u64,
+  pub connections_total: u64,
+  pub bytes_in: u64,
+  pub bytes_out: u64,
+  pub frames_in: u64,
+  pub frames_out: u64,
+  /// Deliveries thrown away because a client's queue was full.
+  pub dropped: u64,
+  pub protocol_errors: u64,
+  pub refusals: u64,
+  pub handler_errors: u64,
+  pub auth_failures: u64,
+  /// Messages published per topic, counting only those that reached a
+  /// subscriber, for the first `MAX_TOPICS` topics.
+  pub published: BTreeMap<String, u64>,
+  /// Messages published to topics beyond the first `MAX_TOPICS`.
+  pub published_other: u64,
+  /// Calls per registered RPC method.
+  pub calls: BTreeMap<String, u64>,
+}
+
+impl Stats {
+  fn counters(&self) -> [(&'static str, u64); 12] {
+    [
+      ("connections_active", self.connections_active),
+      ("connections_total", self.connections_total),
+      ("bytes_in", self.bytes_in),
+      ("bytes_out", self.bytes_out),
+      ("frames_in", self.frames_in),
+      ("frames_out", self.frames_out),
+      ("dropped", self.dropped),
+      ("protocol_errors", self.protocol_errors),
+      ("refusals", self.refusals),
+      ("handler_errors", self.handler_errors),
+      ("auth_failures", self.auth_failures),
+      ("published_other", self.published_other),
+    ]
+  }
+
+  /// One counter per line, topics and methods quoted.
+  pub fn to_text(&self) -> String {
+    self.to_string()
+  }
+
+  pub fn to_json(&self) -> String {
+    let mut fields: Vec<String> = self.counters().iter().map(|(name, value)| format!("\"{}\":{}", name, value)).collect();
+    fields.push(format!("\"published\":{}", json_object(&self.published)));
+    fields.push(format!("\"calls\":{}", json_object(&self.calls)));
+    format!("{{{}}}", fields.join(","))
+  }
+}
+
+impl fmt::Display for Stats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (name, value) in self.counters().iter() {
+      writeln!(f, "{} {}", name, value)?;
+    }
+    for (topic, count) in &self.published {
+      writeln!(f, "published {:?} {}", topic, count)?;
+    }
+    for (method, count) in &self.calls {
+      writeln!(f, "calls {:?} {}", method, count)?;
+    }
+    Ok(())
+  }
+}
+
+fn json_object(counts: &BTreeMap<String, u64>) -> String {
+  let entries: Vec<String> = counts.iter().map(|(key, count)| format!("{}:{}", json_string(key), count)).collect();
+  format!("{{{}}}", entries.join(","))
+}
+
+fn json_string(s: &str) -> String {
+  let mut quoted = String::with_capacity(s.len() + 2);
+  quoted.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_text_and_json() {
+    let metrics = Metrics::default();
+    metrics.received(10);
+    metrics.sent(7);
+    metrics.published("news");
+    metrics.published("news");
+    metrics.published("say \"hi\"\n");
+    metrics.called("upper");
+    let stats = metrics.snapshot(1, 3, 0);
+
+    let text = stats.to_text();
+    assert!(text.starts_with("connections_active 1\nconnections_total 3\nbytes_in 10\nbytes_out 7\n"));
+    assert!(text.contains("published \"news\" 2\n"));
+    assert!(text.contains("published \"say \\\"hi\\\"\\n\" 1\n"));
+    assert!(text.ends_with("calls \"upper\" 1\n"));
+
+    assert_eq!(
+      stats.to_json(),
+      "{\"connections_active\":1,\"connections_total\":3,\"bytes_in\":10,\"bytes_out\":7,\"frames_in\":1,\
+       \"frames_out\":1,\"dropped\":0,\"protocol_errors\":0,\"refusals\":0,\"handler_errors\":0,\
+       \"auth_failures\":0,\"published_other\":0,\"published\":{\"news\":2,\"say \\\"hi\\\"\\n\":1},\"calls\":{\"upper\":1}}"
+    );
+  }
+
+  #[test]
+  fn caps_the_topics_counted() {
+    let metrics = Metrics::default();
+    for i in 0..MAX_TOPICS + 10 {
+      metrics.published(&i.to_string());
+    }
+    metrics.published("0");
+    let stats = metrics.snapshot(0, 0, 0);
+    assert_eq!(stats.published.len(), MAX_TOPICS);
+    assert_eq!(stats.published["0"], 2);
+    assert_eq!(stats.published_other, 10);
+  }
+
+  #[test]
+  fn escapes_control_characters() {
+    assert_eq!(json_string("a\u{1}b\\"), "\"a\\u0001b\\\\\"");
+  }
+}
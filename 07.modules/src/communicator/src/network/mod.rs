@@ -1,5 +1,24 @@
-fn connect() {
-  
-}
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+
+pub mod frame;
+pub mod http;
+pub mod metrics;
+pub mod pool;
+pub mod server;
+pub mod stream;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
 
-mod server;
\ No newline at end of file
+pub use self::stream::{Addr, Stream};
+
+/// Opens a TCP stream to `addr`.
+/// Frames are small and interactive, so Nagle's algorithm is turned off
+/// rather than letting the kernel hold back the tail of a frame waiting
+/// for more data.
+pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+  let stream = TcpStream::connect(addr)?;
+  stream.set_nodelay(true)?;
+  Ok(stream)
+}
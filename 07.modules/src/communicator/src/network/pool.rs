@@ -0,0 +1,159 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads taking jobs off a shared queue.
+/// Jobs beyond the pool size wait their turn. Dropping the pool lets the
+/// queue drain and then joins every worker.
+pub struct ThreadPool {
+  workers: Vec<Worker>,
+  sender: Option<Sender<Job>>,
+}
+
+impl ThreadPool {
+  /// Create a new ThreadPool.
+  ///
+  /// The size is the number of threads in the pool.
+  ///
+  /// # Panics
+  ///
+  /// The `new` function will panic if the size is zero.
+  pub fn new(size: usize) -> ThreadPool {
+    assert!(size > 0);
+
+    let (sender, receiver) = mpsc::channel();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let workers = (0..size).map(|_| Worker::new(Arc::clone(&receiver))).collect();
+
+    ThreadPool { workers, sender: Some(sender) }
+  }
+
+  pub fn execute<F>(&self, f: F)
+  where
+    F: FnOnce() + Send + 'static,
+  {
+    if let Some(sender) = &self.sender {
+      // workers only hang up once the sender is gone, so this can't fail
+      let _ = sender.send(Box::new(f));
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    self.workers.len()
+  }
+}
+
+impl Drop for ThreadPool {
+  fn drop(&mut self) {
+    // closing the queue is what tells the workers to stop
+    drop(self.sender.take());
+
+    for worker in &mut self.workers {
+      if let Some(thread) = worker.thread.take() {
+        let _ = thread.join();
+      }
+    }
+  }
+}
+
+struct Worker {
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+  fn new(receiver: Arc<Mutex<Receiver<Job>>>) -> Worker {
+    let thread = thread::spawn(move || loop {
+      // the guard is a temporary, so the lock is released before the job runs
+      let job = receiver.lock().unwrap().recv();
+      match job {
+        // a panicking job shouldn't cost the pool a worker
+        Ok(job) => {
+          let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        },
+        Err(_) => break,
+      }
+    });
+
+    Worker { thread: Some(thread) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Barrier;
+  use std::time::Duration;
+
+  static EXITED: AtomicUsize = AtomicUsize::new(0);
+
+  /// Counts a thread as gone when its thread-locals are torn down.
+  struct ExitGuard(Cell<bool>);
+
+  impl Drop for ExitGuard {
+    fn drop(&mut self) {
+      if self.0.get() {
+        EXITED.fetch_add(1, Ordering::SeqCst);
+      }
+    }
+  }
+
+  thread_local! {
+    static GUARD: ExitGuard = const { ExitGuard(Cell::new(false)) };
+  }
+
+  #[test]
+  fn runs_jobs_in_parallel_and_joins_on_drop() {
+    let pool = ThreadPool::new(4);
+    // only passes if all four jobs are running at the same time
+    let barrier = Arc::new(Barrier::new(4));
+    let done = Arc::new(AtomicUsize::new(0));
+    for _ in 0..4 {
+      let barrier = Arc::clone(&barrier);
+      let done = Arc::clone(&done);
+      pool.execute(move || {
+        GUARD.with(|guard| guard.0.set(true));
+        barrier.wait();
+        done.fetch_add(1, Ordering::SeqCst);
+      });
+    }
+
+    drop(pool);
+    assert_eq!(done.load(Ordering::SeqCst), 4);
+    assert_eq!(EXITED.load(Ordering::SeqCst), 4);
+  }
+
+  #[test]
+  fn drains_queued_jobs_before_stopping() {
+    let pool = ThreadPool::new(2);
+    let done = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+      let done = Arc::clone(&done);
+      pool.execute(move || {
+        thread::sleep(Duration::from_millis(5));
+        done.fetch_add(1, Ordering::SeqCst);
+      });
+    }
+    drop(pool);
+    assert_eq!(done.load(Ordering::SeqCst), 10);
+  }
+
+  #[test]
+  fn survives_a_panicking_job() {
+    let pool = ThreadPool::new(1);
+    pool.execute(|| panic!("boom"));
+    let (sender, receiver) = mpsc::channel();
+    pool.execute(move || sender.send(42).unwrap());
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), 42);
+  }
+
+  #[test]
+  #[should_panic]
+  fn rejects_an_empty_pool() {
+    ThreadPool::new(0);
+  }
+}
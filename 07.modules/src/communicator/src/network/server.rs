@@ -1,3 +1,1289 @@
-fn connect() {
-  
-}
\ No newline at end of file
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::net::{Shutdown, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::auth::{self, Key};
+use crate::connection::{check_topic, publish_payload, route_payload, split_publish, split_route, Connection};
+use crate::error::{Error, Result};
+use crate::network::frame::{self, tag, Frame, FrameReader};
+use crate::network::http;
+use crate::network::metrics::{Metrics, Stats};
+use crate::network::pool::ThreadPool;
+use crate::network::stream::{Addr, Listener, Stream};
+#[cfg(unix)]
+use crate::network::unix::{self, SocketFile};
+use crate::rpc::{self, Registry, RpcError};
+use crate::transport::FrameTransport;
+
+/// How many frames may wait for a slow client before the broker starts dropping its deliveries.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// The RPC method that answers with a `Stats` snapshot, as text or, given
+/// the body `json`, as JSON. Method names starting with `admin.` are reserved.
+/// Every client the server lets in may call it, so a server whose topic
+/// and method names are nobody else's business should set `Config::auth_key`.
+pub const ADMIN_STATS: &str = "admin.stats";
+const ADMIN_PREFIX: &str = "admin.";
+
+/// How long a client gets to answer the authentication challenge.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many connections are served at once before new ones are turned away.
+pub const DEFAULT_WORKERS: usize = 64;
+
+/// Threads running RPC handlers, shared by every connection.
+pub const DEFAULT_RPC_WORKERS: usize = 16;
+
+/// Calls one connection may have running or waiting for a handler thread
+/// at once. More are answered with `RpcError::Busy` straight away.
+pub const MAX_CALLS_IN_FLIGHT: usize = 8;
+
+/// Pause after a failed `accept`, which usually means the process is out
+/// of file descriptors; retrying straight away would only spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// Frames queued per connection. Fan-out never waits on a full queue,
+  /// it drops the frame for that one client instead.
+  pub queue_capacity: usize,
+  /// Size of the connection thread pool. Each connection holds a worker
+  /// until it disconnects, so this caps the clients connected at once;
+  /// the rest are told the server is full and hung up on, with an `ERROR`
+  /// frame or a 503.
+  ///
+  /// All told a server runs an accept thread, these workers, a writer
+  /// thread for each framed connection and `rpc_workers`, so at most
+  /// `1 + 2 * workers + rpc_workers` threads.
+  pub workers: usize,
+  /// Size of the pool RPC handlers run on. A slow handler only holds up
+  /// other calls once all of these are busy.
+  pub rpc_workers: usize,
+  /// Speak HTTP/1.1 instead of the framed protocol and serve the files
+  /// under this directory. `GET` and `HEAD` only.
+  pub http_root: Option<PathBuf>,
+  /// When to hang up on HTTP clients that are idle or too slow to send a request.
+  pub http_timeouts: http::Timeouts,
+  /// Challenge every client for this shared key before greeting it, and
+  /// hang up on those that can't answer. See `crate::auth`.
+  pub auth_key: Option<Key>,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      queue_capacity: DEFAULT_QUEUE_CAPACITY,
+      workers: DEFAULT_WORKERS,
+      rpc_workers: DEFAULT_RPC_WORKERS,
+      http_root: None,
+      http_timeouts: http::Timeouts::default(),
+      auth_key: None,
+    }
+  }
+}
+
+/// A running publish/subscribe broker.
+/// Every connection is greeted with a `HELLO` frame carrying its id, after
+/// answering a `CHALLENGE` first if the server was given a key.
+/// `SUBSCRIBE` and `UNSUBSCRIBE` manage a connection's topics, and a `PUBLISH`
+/// frame is fanned out unchanged to every current subscriber of its topic.
+/// `ROUTE` frames are forwarded to the peer they name, with the id swapped
+/// for the sender's. `RPC_REQUEST`s go to the handlers added with `register`,
+/// and `DATA` frames to the `Service`, if there is one. Any other frame is
+/// echoed back unchanged.
+///
+/// With `Config::http_root` set it is a static file server instead.
+pub struct Server {
+  addr: Addr,
+  running: Arc<AtomicBool>,
+  hub: Arc<Hub>,
+  accept: Option<JoinHandle<()>>,
+  /// Removed on shutdown, if nobody has replaced it by then.
+  #[cfg(unix)]
+  socket_file: Option<SocketFile>,
+}
+
+/// Binds a listener on `addr` and starts serving connections in the background.
+/// Bind to port 0 and ask `local_addr` for the port the OS picked.
+pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Server> {
+  connect_with(addr, Config::default())
+}
+
+pub fn connect_with<A: ToSocketAddrs>(addr: A, config: Config) -> Result<Server> {
+  start(Listener::Tcp(TcpListener::bind(addr)?), config)
+}
+
+/// Like `connect`, but listens on a Unix socket file at `path`.
+/// A stale socket file from a server that died is replaced; the file is
+/// removed again on shutdown.
+#[cfg(unix)]
+pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Server> {
+  connect_unix_with(path, Config::default())
+}
+
+#[cfg(unix)]
+pub fn connect_unix_with<P: AsRef<Path>>(path: P, config: Config) -> Result<Server> {
+  let listener = unix::bind(path.as_ref())?;
+  let socket_file = SocketFile::new(path.as_ref())?;
+  let mut server = start(Listener::Unix(listener), config)?;
+  server.socket_file = Some(socket_file);
+  Ok(server)
+}
+
+fn start(listener: Listener, config: Config) -> Result<Server> {
+  let addr = listener.local_addr()?;
+  let running = Arc::new(AtomicBool::new(true));
+  let hub = Arc::new(Hub {
+    calls: ThreadPool::new(config.rpc_workers.max(1)),
+    config,
+    broker: Mutex::new(Broker::default()),
+    registry: Registry::default(),
+    service: RwLock::new(None),
+    metrics: Arc::default(),
+  });
+
+  let accept = {
+    let running = Arc::clone(&running);
+    let hub = Arc::clone(&hub);
+    thread::spawn(move || accept_loop(listener, running, hub))
+  };
+
+  Ok(Server {
+    addr,
+    running,
+    hub,
+    accept: Some(accept),
+    #[cfg(unix)]
+    socket_file: None,
+  })
+}
+
+impl Server {
+  pub fn local_addr(&self) -> Addr {
+    self.addr.clone()
+  }
+
+  /// Stops accepting, disconnects every client and waits for the
+  /// thread pools to wind down, which includes handlers still running.
+  pub fn shutdown(mut self) {
+    self.stop();
+  }
+
+  fn stop(&mut self) {
+    let accept = match self.accept.take() {
+      Some(accept) => accept,
+      None => return,
+    };
+    self.running.store(false, Ordering::SeqCst);
+    // `accept` blocks, so poke it with a throwaway connection
+    let _ = Stream::connect(&self.addr);
+    let _ = accept.join();
+    #[cfg(unix)]
+    {
+      if let Some(socket_file) = self.socket_file.take() {
+        let _ = socket_file.remove();
+      }
+    }
+  }
+
+  /// Serves `method` with `handler` from now on, replacing any earlier handler.
+  /// Calls run on a pool of `Config::rpc_workers` threads, so a slow handler
+  /// doesn't hold up other requests on the same connection. Each connection
+  /// gets at most `MAX_CALLS_IN_FLIGHT` of them at a time.
+  ///
+  /// # Panics
+  ///
+  /// If `method` starts with `admin.`, which is reserved for the server itself.
+  pub fn register<F>(&self, method: &str, handler: F)
+  where
+    F: Fn(&[u8]) -> std::result::Result<Vec<u8>, RpcError> + Send + Sync + 'static,
+  {
+    assert!(!method.starts_with(ADMIN_PREFIX), "{:?} is a reserved method name", method);
+    self.hub.registry.register(method, handler);
+  }
+
+  /// Serves a client over `transport`, e.g. one end of a `Loopback` pair,
+  /// the same way as one that connected over the network. It gets a thread
+  /// of its own instead of a worker, isn't challenged even if the server has
+  /// a key, and always speaks the framed protocol.
+  pub fn attach<T: FrameTransport + 'static>(&self, transport: T) {
+    let transport = Arc::new(transport);
+    let id = {
+      let mut broker = self.hub.broker.lock().unwrap();
+      // checked under the lock, so shutdown either finds it tracked or it's closed here
+      if !self.running.load(Ordering::SeqCst) {
+        let _ = transport.close();
+        return;
+      }
+      broker.track(Arc::clone(&transport))
+    };
+    let hub = Arc::clone(&self.hub);
+    thread::spawn(move || {
+      converse(id, Arc::clone(&transport), &hub);
+      let _ = transport.close();
+      hub.broker.lock().unwrap().links.remove(&id);
+    });
+  }
+
+  /// Hands `DATA` frames to `service` from now on, replacing any earlier one.
+  pub fn set_service<S: Service + 'static>(&self, service: Arc<S>) {
+    *self.hub.service.write().unwrap() = Some(service);
+  }
+
+  /// Blocks for as long as the server runs, for a program that does nothing else.
+  pub fn wait(mut self) {
+    if let Some(accept) = self.accept.take() {
+      let _ = accept.join();
+    }
+  }
+
+  /// Number of clients currently connected.
+  pub fn connection_count(&self) -> usize {
+    self.hub.broker.lock().unwrap().peers.len()
+  }
+
+  /// Number of clients currently subscribed to `topic`.
+  pub fn subscriber_count(&self, topic: &str) -> usize {
+    self.hub.broker.lock().unwrap().topics.get(topic).map_or(0, BTreeSet::len)
+  }
+
+  /// Deliveries thrown away because the receiving client's queue was full.
+  pub fn dropped_count(&self) -> u64 {
+    self.hub.broker.lock().unwrap().dropped
+  }
+
+  /// The server's counters right now. Clients get the same from the `admin.stats` method.
+  pub fn stats(&self) -> Stats {
+    self.hub.stats()
+  }
+}
+
+/// Application logic running inside the server, e.g. a chat, so it can
+/// use the broker's topics and queues instead of a server of its own.
+pub trait Service: Send + Sync {
+  /// Handles a `DATA` frame from `peer`. An error hangs up on `peer`, once
+  /// whatever was already queued for it has been sent.
+  fn message(&self, peers: &Peers, peer: u64, message: &[u8]) -> Result<()>;
+
+  /// `peer` has hung up, and is no longer subscribed to anything.
+  fn left(&self, _peers: &Peers, _peer: u64) {}
+
+  /// Whether only the service may publish to `topic`. Clients that try are refused.
+  fn owns(&self, _topic: &str) -> bool {
+    false
+  }
+}
+
+/// What a `Service` can do with the server's clients.
+/// Like fan-out, none of it waits for a slow client: when its queue is
+/// full the frame is dropped and counted in `Server::dropped_count`.
+pub struct Peers<'a> {
+  hub: &'a Hub,
+}
+
+impl Peers<'_> {
+  /// Sends `message` to `peer` as a `DATA` frame. False if there's no such peer.
+  pub fn send(&self, peer: u64, message: &[u8]) -> bool {
+    let delivery = self.hub.broker.lock().unwrap().deliver(peer, Frame::new(tag::DATA, message));
+    !matches!(delivery, Delivery::Unknown)
+  }
+
+  /// Subscribes `peer` to `topic` as if it had asked to.
+  pub fn subscribe(&self, peer: u64, topic: &str) -> Result<()> {
+    check_topic(topic)?;
+    self.hub.broker.lock().unwrap().subscribe(peer, topic);
+    Ok(())
+  }
+
+  pub fn unsubscribe(&self, peer: u64, topic: &str) -> Result<()> {
+    check_topic(topic)?;
+    self.hub.broker.lock().unwrap().unsubscribe(peer, topic);
+    Ok(())
+  }
+
+  /// Publishes `message` to the subscribers of `topic` but `except`, and
+  /// says how many there were.
+  pub fn publish(&self, topic: &str, message: &[u8], except: Option<u64>) -> Result<usize> {
+    let frame = Frame::new(tag::PUBLISH, publish_payload(topic, message)?);
+    Ok(self.hub.broker.lock().unwrap().publish(topic, &frame, except))
+  }
+}
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+/// Everything the connection threads share.
+/// Jobs on `calls` never hold the hub, or the last of them to finish
+/// would drop the pool from one of its own threads.
+struct Hub {
+  config: Config,
+  broker: Mutex<Broker>,
+  registry: Registry,
+  service: RwLock<Option<Arc<dyn Service>>>,
+  calls: ThreadPool,
+  metrics: Arc<Metrics>,
+}
+
+impl Hub {
+  /// Active connections are the links still open, of either protocol.
+  fn stats(&self) -> Stats {
+    let broker = self.broker.lock().unwrap();
+    self.metrics.snapshot(broker.links.len(), broker.next_id, broker.dropped)
+  }
+}
+
+/// Whatever a connection runs over, as far as shutdown is concerned.
+trait Hangup: Send {
+  fn hang_up(&self);
+}
+
+impl Hangup for Stream {
+  fn hang_up(&self) {
+    let _ = self.shutdown(Shutdown::Both);
+  }
+}
+
+impl<T: FrameTransport> Hangup for Arc<T> {
+  fn hang_up(&self) {
+    let _ = self.close();
+  }
+}
+
+struct Peer {
+  outbox: SyncSender<Frame>,
+  topics: HashSet<String>,
+}
+
+enum Delivery {
+  Queued,
+  Dropped,
+  Unknown,
+}
+
+/// Who is connected and who listens to what.
+/// Subscribers are kept sorted so every topic fans out in the same order.
+/// A link, usually a socket, outlives its peer entry while the writer
+/// flushes what was already queued, so shutdown can still reach it.
+#[derive(Default)]
+struct Broker {
+  next_id: u64,
+  peers: HashMap<u64, Peer>,
+  links: HashMap<u64, Box<dyn Hangup>>,
+  topics: HashMap<String, BTreeSet<u64>>,
+  dropped: u64,
+}
+
+impl Broker {
+  /// Makes a tracked connection a peer that can subscribe and be routed to.
+  fn admit(&mut self, id: u64, outbox: SyncSender<Frame>) {
+    self.peers.insert(id, Peer { outbox, topics: HashSet::new() });
+  }
+
+  /// Remembers a link so shutdown can close it, without making it a peer.
+  fn track<H: Hangup + 'static>(&mut self, link: H) -> u64 {
+    self.next_id += 1;
+    self.links.insert(self.next_id, Box::new(link));
+    self.next_id
+  }
+
+  /// Forgets `id` and all its subscriptions, which also closes its outbox.
+  fn leave(&mut self, id: u64) {
+    let peer = match self.peers.remove(&id) {
+      Some(peer) => peer,
+      None => return,
+    };
+    for topic in peer.topics {
+      self.remove_subscriber(&topic, id);
+    }
+  }
+
+  fn subscribe(&mut self, id: u64, topic: &str) {
+    if let Some(peer) = self.peers.get_mut(&id) {
+      peer.topics.insert(topic.to_string());
+      self.topics.entry(topic.to_string()).or_default().insert(id);
+    }
+  }
+
+  fn unsubscribe(&mut self, id: u64, topic: &str) {
+    if let Some(peer) = self.peers.get_mut(&id) {
+      peer.topics.remove(topic);
+      self.remove_subscriber(topic, id);
+    }
+  }
+
+  fn remove_subscriber(&mut self, topic: &str, id: u64) {
+    if let Some(subscribers) = self.topics.get_mut(topic) {
+      subscribers.remove(&id);
+      if subscribers.is_empty() {
+        self.topics.remove(topic);
+      }
+    }
+  }
+
+  /// Fans `frame` out to the subscribers of `topic` but `except`, and says how many there were.
+  fn publish(&mut self, topic: &str, frame: &Frame, except: Option<u64>) -> usize {
+    let subscribers: Vec<u64> = match self.topics.get(topic) {
+      Some(subscribers) => subscribers.iter().cloned().filter(|&id| Some(id) != except).collect(),
+      None => return 0,
+    };
+    for &id in &subscribers {
+      self.deliver(id, frame.clone());
+    }
+    subscribers.len()
+  }
+
+  /// Queues `frame` for `to` without ever blocking on a slow reader.
+  fn deliver(&mut self, to: u64, frame: Frame) -> Delivery {
+    let peer = match self.peers.get(&to) {
+      Some(peer) => peer,
+      None => return Delivery::Unknown,
+    };
+    match peer.outbox.try_send(frame) {
+      Ok(()) => Delivery::Queued,
+      Err(TrySendError::Full(_)) => {
+        self.dropped += 1;
+        Delivery::Dropped
+      },
+      // its writer has died, the connection thread is about to clean up
+      Err(TrySendError::Disconnected(_)) => Delivery::Dropped,
+    }
+  }
+}
+
+fn accept_loop(listener: Listener, running: Arc<AtomicBool>, hub: Arc<Hub>) {
+  let pool = ThreadPool::new(hub.config.workers.max(1));
+  // connections holding a worker, or about to get the next free one
+  let serving = Arc::new(AtomicUsize::new(0));
+
+  loop {
+    let stream = listener.accept();
+    if !running.load(Ordering::SeqCst) {
+      break;
+    }
+    let (stream, registered) = match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+      Ok((registered, stream)) => (stream, registered),
+      Err(_) => {
+        thread::sleep(ACCEPT_BACKOFF);
+        continue;
+      },
+    };
+    // one more than the pool would have to wait for somebody else to hang up
+    if serving.load(Ordering::SeqCst) >= pool.size() {
+      turn_away(&stream, &hub.config);
+      continue;
+    }
+    serving.fetch_add(1, Ordering::SeqCst);
+    // track before handing off so shutdown can't miss a fresh connection
+    let id = hub.broker.lock().unwrap().track(registered);
+    let hub = Arc::clone(&hub);
+    let serving = Arc::clone(&serving);
+    pool.execute(move || {
+      match &hub.config.http_root {
+        Some(root) => {
+          let _ = http::serve(&stream, root, hub.config.http_timeouts);
+        },
+        None => serve(id, &stream, &hub),
+      }
+      let _ = stream.shutdown(Shutdown::Both);
+      serving.fetch_sub(1, Ordering::SeqCst);
+      hub.broker.lock().unwrap().links.remove(&id);
+    });
+  }
+
+  // closing the sockets unblocks every reader still waiting on a frame
+  for link in hub.broker.lock().unwrap().links.values() {
+    link.hang_up();
+  }
+  // connections still waiting for a worker find their socket closed and return at once
+  drop(pool);
+}
+
+/// Says the server is full, in whichever protocol it speaks, and hangs up.
+/// A fresh socket has room for that much, so this doesn't hold up accepting.
+fn turn_away(stream: &Stream, config: &Config) {
+  let _ = match config.http_root {
+    Some(_) => http::unavailable(stream),
+    None => frame::write_frame(&mut &*stream, &error_frame("server is full"), frame::DEFAULT_MAX_LEN),
+  };
+  let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Runs one connection from the challenge, if any, to hanging up.
+fn serve(id: u64, stream: &Stream, hub: &Arc<Hub>) {
+  let admitted = match &hub.config.auth_key {
+    Some(key) => authenticate(stream, key),
+    None => Ok(()),
+  };
+  match admitted {
+    Ok(()) => {
+      let connection = stream
+        .set_nodelay(true)
+        .and_then(|()| stream.try_clone())
+        .map_err(Error::from)
+        .and_then(|clone| Connection::new(clone, id));
+      if let Ok(connection) = connection {
+        converse(id, Arc::new(connection), hub);
+      }
+    },
+    // a client that never answers, or hangs up halfway, failed as much as one with the wrong key
+    Err(err) => {
+      hub.metrics.auth_failure();
+      let reason = match err {
+        Error::Rejected(reason) => reason,
+        Error::Timeout => String::from("authentication timed out"),
+        _ => String::from("authentication failed"),
+      };
+      let _ = frame::write_frame(&mut &*stream, &error_frame(&reason), frame::DEFAULT_MAX_LEN);
+    },
+  }
+}
+
+/// Sends a fresh nonce and waits for the client to prove it holds `key`.
+fn authenticate(stream: &Stream, key: &Key) -> Result<()> {
+  let nonce = auth::nonce();
+  frame::write_frame(&mut &*stream, &Frame::new(tag::CHALLENGE, nonce.to_vec()), frame::DEFAULT_MAX_LEN)?;
+  stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+  // never read past where a valid answer ends, anything after it belongs to the conversation
+  let answer = FrameReader::new(stream.take((frame::HEADER_LEN + auth::MAC_LEN) as u64)).read_frame();
+  stream.set_read_timeout(None)?;
+  match answer {
+    Ok(frame) if frame.tag == tag::AUTH && auth::verify(key.as_bytes(), &nonce, &frame.payload) => Ok(()),
+    Ok(_) | Err(Error::Protocol(_)) | Err(Error::FrameTooLarge { .. }) => {
+      Err(Error::Rejected(String::from("authentication failed")))
+    },
+    Err(err) => Err(err),
+  }
+}
+
+/// Serves an admitted peer: a writer thread drains the outbox while this thread reads.
+fn converse<T: FrameTransport + 'static>(id: u64, link: Arc<T>, hub: &Arc<Hub>) {
+  let (outbox, queue) = mpsc::sync_channel::<Frame>(hub.config.queue_capacity.max(1));
+  hub.broker.lock().unwrap().admit(id, outbox.clone());
+  let outbox = Arc::new(outbox);
+  let _ = outbox.try_send(Frame::new(tag::HELLO, id.to_be_bytes().to_vec()));
+
+  let writer = {
+    let link = Arc::clone(&link);
+    let hub = Arc::clone(hub);
+    thread::spawn(move || {
+      for frame in queue {
+        if link.send_frame(&frame).is_err() {
+          break;
+        }
+        hub.metrics.sent(frame::HEADER_LEN + frame.payload.len());
+      }
+    })
+  };
+
+  let _ = handle_connection(id, &*link, &outbox, hub);
+
+  // with every sender gone the writer flushes what's queued, e.g. a final error, and stops
+  hub.broker.lock().unwrap().leave(id);
+  let service = hub.service.read().unwrap().clone();
+  if let Some(service) = service {
+    service.left(&Peers { hub }, id);
+  }
+  drop(outbox);
+  let _ = writer.join();
+}
+
+fn handle_connection<T: FrameTransport>(id: u64, link: &T, outbox: &Arc<SyncSender<Frame>>, hub: &Arc<Hub>) -> Result<()> {
+  let broker = &hub.broker;
+  // replies to the client itself may wait for room, that only slows down this one connection
+  let reply = |frame: Frame| outbox.send(frame).map_err(|_| Error::Closed);
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  loop {
+    let frame = match link.recv_frame() {
+      Ok(frame) => frame,
+      Err(Error::Closed) => return Ok(()),
+      Err(err) => {
+        // the stream is out of sync, say why before hanging up
+        hub.metrics.protocol_error();
+        let _ = reply(refusal_frame(&err));
+        return Err(err);
+      },
+    };
+    hub.metrics.received(frame::HEADER_LEN + frame.payload.len());
+    let service = hub.service.read().unwrap().clone();
+    let refusal = match frame.tag {
+      tag::ROUTE => route(id, &frame, broker),
+      tag::SUBSCRIBE => topic_of(&frame).map(|topic| broker.lock().unwrap().subscribe(id, topic)),
+      tag::UNSUBSCRIBE => topic_of(&frame).map(|topic| broker.lock().unwrap().unsubscribe(id, topic)),
+      tag::PUBLISH => split_publish(&frame.payload).and_then(|(topic, _)| {
+        if service.as_ref().is_some_and(|service| service.owns(topic)) {
+          return Err(Error::Rejected(format!("only the server publishes to {}", topic)));
+        }
+        // only topics someone listens to are counted, the rest cost nothing
+        if broker.lock().unwrap().publish(topic, &frame, None) > 0 {
+          hub.metrics.published(topic);
+        }
+        Ok(())
+      }),
+      tag::RPC_REQUEST => call(&frame, hub, outbox, &calls),
+      _ => {
+        match &service {
+          Some(service) if frame.tag == tag::DATA => service.message(&Peers { hub }, id, &frame.payload)?,
+          _ => reply(frame)?,
+        }
+        Ok(())
+      },
+    };
+    if let Err(err) = refusal {
+      hub.metrics.refused();
+      reply(refusal_frame(&err))?;
+    }
+  }
+}
+
+fn route(from: u64, frame: &Frame, broker: &Mutex<Broker>) -> Result<()> {
+  let (to, message) = split_route(&frame.payload)?;
+  let forward = Frame::new(tag::ROUTE, route_payload(from, message));
+  match broker.lock().unwrap().deliver(to, forward) {
+    Delivery::Unknown => Err(Error::Rejected(format!("unknown peer {}", to))),
+    Delivery::Queued | Delivery::Dropped => Ok(()),
+  }
+}
+
+/// Answers `admin.stats` here and now, and hands other calls to the handler pool.
+/// Like every other reply, answers wait for room in the caller's own queue,
+/// which only holds up a client that doesn't read them.
+fn call(frame: &Frame, hub: &Hub, outbox: &Arc<SyncSender<Frame>>, calls: &Arc<AtomicUsize>) -> Result<()> {
+  let (call, method, body) = rpc::split_request(&frame.payload)?;
+  let reply = |frame: Frame| outbox.send(frame).map_err(|_| Error::Closed);
+  if method == ADMIN_STATS {
+    let stats = hub.stats();
+    return reply(match body {
+      b"" | b"text" => rpc::response(call, rpc::OK, stats.to_text().as_bytes()),
+      b"json" => rpc::response(call, rpc::OK, stats.to_json().as_bytes()),
+      _ => rpc::response(call, rpc::HANDLER_FAILED, b"stats come as text or json"),
+    });
+  }
+  // counted until the answer is queued, so a client that stops reading
+  // can't tie up more than its share of the pool
+  if calls.load(Ordering::SeqCst) >= MAX_CALLS_IN_FLIGHT {
+    hub.metrics.refused();
+    return reply(rpc::response(call, rpc::BUSY, b""));
+  }
+  if hub.registry.knows(method) {
+    hub.metrics.called(method);
+  }
+
+  calls.fetch_add(1, Ordering::SeqCst);
+  let metrics = Arc::clone(&hub.metrics);
+  // a handler still running doesn't keep a disconnected client's writer alive
+  let answers: Weak<SyncSender<Frame>> = Arc::downgrade(outbox);
+  let finished = Arc::clone(calls);
+  hub.registry.dispatch(frame, &hub.calls, move |answer| {
+    match rpc::status(&answer) {
+      Some(rpc::UNKNOWN_METHOD) => metrics.refused(),
+      Some(rpc::HANDLER_FAILED) => metrics.handler_error(),
+      _ => {},
+    }
+    if let Some(outbox) = answers.upgrade() {
+      let _ = outbox.send(answer);
+    }
+    finished.fetch_sub(1, Ordering::SeqCst);
+  })
+}
+
+fn topic_of(frame: &Frame) -> Result<&str> {
+  let topic = std::str::from_utf8(&frame.payload).map_err(|_| Error::Protocol(String::from("topic is not utf-8")))?;
+  check_topic(topic)?;
+  Ok(topic)
+}
+
+fn error_frame(reason: &str) -> Frame {
+  Frame::new(tag::ERROR, reason)
+}
+
+fn refusal_frame(err: &Error) -> Frame {
+  match err {
+    Error::Rejected(reason) => error_frame(reason),
+    other => error_frame(&other.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::connection::publish_payload;
+  use crate::loopback;
+  use std::convert::TryInto;
+  use std::io::{BufRead, BufReader, Read, Write};
+  use std::net::TcpStream;
+  use std::time::{Duration, Instant};
+
+  fn open(server: &Server) -> (TcpStream, FrameReader<TcpStream>, u64) {
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    let mut reader = FrameReader::new(stream.try_clone().unwrap());
+    let hello = reader.read_frame().unwrap();
+    assert_eq!(hello.tag, tag::HELLO);
+    (stream, reader, u64::from_be_bytes(hello.payload[..].try_into().unwrap()))
+  }
+
+  fn send(stream: &mut TcpStream, frame: &Frame) {
+    frame::write_frame(stream, frame, frame::DEFAULT_MAX_LEN).unwrap();
+  }
+
+  /// Round-trips an echo, after which the server has handled everything sent before it.
+  fn sync(stream: &mut TcpStream, reader: &mut FrameReader<TcpStream>) {
+    send(stream, &Frame::new(tag::DATA, "sync"));
+    while reader.read_frame().unwrap() != Frame::new(tag::DATA, "sync") {}
+  }
+
+  fn wait_until<F: Fn() -> bool>(done: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert!(done());
+  }
+
+  fn publish(topic: &str, message: &str) -> Frame {
+    Frame::new(tag::PUBLISH, publish_payload(topic, message.as_bytes()).unwrap())
+  }
+
+  #[test]
+  fn greets_and_echoes() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+
+    let ping = Frame::new(tag::DATA, "ping");
+    send(&mut stream, &ping);
+    assert_eq!(reader.read_frame().unwrap(), ping);
+  }
+
+  #[test]
+  fn routes_to_peer() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut a, mut a_reader, a_id) = open(&server);
+    let (_b, mut b_reader, b_id) = open(&server);
+
+    send(&mut a, &Frame::new(tag::ROUTE, route_payload(b_id, b"over here")));
+    assert_eq!(b_reader.read_frame().unwrap(), Frame::new(tag::ROUTE, route_payload(a_id, b"over here")));
+
+    send(&mut a, &Frame::new(tag::ROUTE, route_payload(999, b"anyone?")));
+    assert_eq!(a_reader.read_frame().unwrap(), error_frame("unknown peer 999"));
+  }
+
+  #[test]
+  fn rejects_malformed_requests() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+
+    send(&mut stream, &Frame::new(tag::ROUTE, vec![1, 2, 3]));
+    assert_eq!(reader.read_frame().unwrap().tag, tag::ERROR);
+    send(&mut stream, &Frame::new(tag::SUBSCRIBE, ""));
+    assert_eq!(reader.read_frame().unwrap().tag, tag::ERROR);
+    send(&mut stream, &Frame::new(tag::PUBLISH, vec![9, b'x']));
+    assert_eq!(reader.read_frame().unwrap().tag, tag::ERROR);
+  }
+
+  #[test]
+  fn hangs_up_on_oversized_frames() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+
+    let header = ((frame::DEFAULT_MAX_LEN + 1) as u32).to_be_bytes();
+    stream.write_all(&[&header[..], &[tag::DATA]].concat()).unwrap();
+    assert_eq!(reader.read_frame().unwrap().tag, tag::ERROR);
+    assert!(matches!(reader.read_frame(), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn fans_out_in_order_per_topic() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut publisher, _publisher_reader, _) = open(&server);
+    let mut subscribers: Vec<_> = (0..3).map(|_| open(&server)).collect();
+    for (stream, reader, _) in subscribers.iter_mut() {
+      send(stream, &Frame::new(tag::SUBSCRIBE, "news"));
+      sync(stream, reader);
+    }
+    let (mut bystander, mut bystander_reader, _) = open(&server);
+    send(&mut bystander, &Frame::new(tag::SUBSCRIBE, "sports"));
+    sync(&mut bystander, &mut bystander_reader);
+
+    for i in 0..50 {
+      send(&mut publisher, &publish("news", &i.to_string()));
+    }
+    send(&mut publisher, &publish("sports", "goal"));
+
+    for (_, reader, _) in subscribers.iter_mut() {
+      for i in 0..50 {
+        assert_eq!(reader.read_frame().unwrap(), publish("news", &i.to_string()));
+      }
+    }
+    assert_eq!(bystander_reader.read_frame().unwrap(), publish("sports", "goal"));
+  }
+
+  #[test]
+  fn unsubscribe_stops_delivery() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+
+    send(&mut stream, &Frame::new(tag::SUBSCRIBE, "t"));
+    send(&mut stream, &publish("t", "first"));
+    send(&mut stream, &Frame::new(tag::UNSUBSCRIBE, "t"));
+    send(&mut stream, &publish("t", "second"));
+    sync(&mut stream, &mut reader);
+
+    // `sync` has already skipped past "first", what matters is that nothing else is waiting
+    assert_eq!(server.subscriber_count("t"), 0);
+    stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+    assert!(matches!(reader.read_frame(), Err(Error::Timeout)));
+  }
+
+  #[test]
+  fn slow_subscriber_does_not_block_the_broker() {
+    // roomy enough for the ten frames `fast` gets below, tiny next to the flood
+    let server = connect_with("127.0.0.1:0", Config { queue_capacity: 16, ..Config::default() }).unwrap();
+    let (mut slow, mut slow_reader, _) = open(&server);
+    let (mut publisher, mut publisher_reader, _) = open(&server);
+    send(&mut slow, &Frame::new(tag::SUBSCRIBE, "firehose"));
+    sync(&mut slow, &mut slow_reader);
+
+    // far more than the socket buffers and the queue can hold, and `slow` never reads
+    let big = "x".repeat(64 * 1024);
+    for _ in 0..400 {
+      send(&mut publisher, &publish("firehose", &big));
+    }
+    sync(&mut publisher, &mut publisher_reader);
+    assert!(server.dropped_count() > 0);
+
+    let (mut fast, mut fast_reader, _) = open(&server);
+    send(&mut fast, &Frame::new(tag::SUBSCRIBE, "firehose"));
+    sync(&mut fast, &mut fast_reader);
+    for i in 0..10 {
+      send(&mut publisher, &publish("firehose", &i.to_string()));
+    }
+    for i in 0..10 {
+      assert_eq!(fast_reader.read_frame().unwrap(), publish("firehose", &i.to_string()));
+    }
+    drop(slow);
+  }
+
+  #[test]
+  fn counts_traffic() {
+    let server = connect("127.0.0.1:0").unwrap();
+    server.register("upper", |body| Ok(body.to_ascii_uppercase()));
+    server.register("fail", |_| Err(RpcError::HandlerFailed(String::from("no"))));
+    let (mut a, mut a_reader, _) = open(&server);
+    let (mut b, mut b_reader, _) = open(&server);
+    let size = |frame: &Frame| (frame::HEADER_LEN + frame.payload.len()) as u64;
+    // both greetings
+    let (mut bytes_in, mut bytes_out) = (0, 2 * 13);
+    let mut exchange = |stream: &mut TcpStream, reader: &mut FrameReader<TcpStream>, frame: Frame, answers: usize| {
+      send(stream, &frame);
+      bytes_in += size(&frame);
+      for _ in 0..answers {
+        bytes_out += size(&reader.read_frame().unwrap());
+      }
+    };
+
+    exchange(&mut b, &mut b_reader, Frame::new(tag::SUBSCRIBE, "news"), 0);
+    exchange(&mut b, &mut b_reader, Frame::new(tag::DATA, "sync"), 1);
+    for i in 0..3 {
+      exchange(&mut a, &mut b_reader, publish("news", &i.to_string()), 1);
+    }
+    // nobody listens, so it isn't counted
+    exchange(&mut a, &mut a_reader, publish("void", "x"), 0);
+    exchange(&mut a, &mut a_reader, Frame::new(tag::ROUTE, vec![1]), 1);
+    for method in &["upper", "fail", "missing", "upper"] {
+      exchange(&mut a, &mut a_reader, Frame::new(tag::RPC_REQUEST, rpc::request_payload(1, method, b"x")), 1);
+    }
+
+    // the writer counts a frame just after the client could have read it
+    wait_until(|| server.stats().bytes_out == bytes_out);
+    let stats = server.stats();
+    assert_eq!((stats.connections_active, stats.connections_total), (2, 2));
+    assert_eq!((stats.frames_in, stats.bytes_in), (11, bytes_in));
+    assert_eq!(stats.frames_out, 11);
+    assert_eq!((stats.protocol_errors, stats.refusals, stats.handler_errors), (0, 2, 1));
+    assert_eq!(stats.published.into_iter().collect::<Vec<_>>(), [(String::from("news"), 3)]);
+    assert_eq!(stats.calls.into_iter().collect::<Vec<_>>(), [(String::from("fail"), 1), (String::from("upper"), 2)]);
+
+    let header = ((frame::DEFAULT_MAX_LEN + 1) as u32).to_be_bytes();
+    a.write_all(&[&header[..], &[tag::DATA]].concat()).unwrap();
+    wait_until(|| server.stats().connections_active == 1);
+    assert_eq!(server.stats().protocol_errors, 1);
+  }
+
+  #[test]
+  fn caps_calls_in_flight_per_connection() {
+    let server = connect("127.0.0.1:0").unwrap();
+    server.register("sleep", |body| {
+      thread::sleep(Duration::from_millis(200));
+      Ok(body.to_vec())
+    });
+    let (mut stream, mut reader, _) = open(&server);
+    let (mut other, mut other_reader, _) = open(&server);
+
+    for call in 0..=MAX_CALLS_IN_FLIGHT as u64 {
+      send(&mut stream, &Frame::new(tag::RPC_REQUEST, rpc::request_payload(call, "sleep", b"z")));
+    }
+    // the one over the limit is turned away before any handler is done
+    assert_eq!(reader.read_frame().unwrap(), rpc::response(MAX_CALLS_IN_FLIGHT as u64, rpc::BUSY, b""));
+    // the limit is per connection
+    send(&mut other, &Frame::new(tag::RPC_REQUEST, rpc::request_payload(0, "sleep", b"o")));
+    assert_eq!(other_reader.read_frame().unwrap(), rpc::response(0, rpc::OK, b"o"));
+    let mut answered: Vec<_> = (0..MAX_CALLS_IN_FLIGHT).map(|_| reader.read_frame().unwrap()).collect();
+    answered.sort_by_key(|frame| frame.payload.clone());
+    let expected: Vec<_> = (0..MAX_CALLS_IN_FLIGHT as u64).map(|call| rpc::response(call, rpc::OK, b"z")).collect();
+    assert_eq!(answered, expected);
+    assert_eq!(server.stats().refusals, 1);
+
+    // with the earlier calls answered there's room again
+    send(&mut stream, &Frame::new(tag::RPC_REQUEST, rpc::request_payload(99, "sleep", b"z")));
+    assert_eq!(reader.read_frame().unwrap(), rpc::response(99, rpc::OK, b"z"));
+  }
+
+  #[test]
+  fn serves_attached_transports() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+    let (near, far) = loopback::pair();
+    server.attach(far);
+
+    assert_eq!(near.recv_frame().unwrap().tag, tag::HELLO);
+    near.send_frame(&Frame::new(tag::SUBSCRIBE, "news")).unwrap();
+    near.send_frame(&Frame::new(tag::DATA, "sync")).unwrap();
+    assert_eq!(near.recv_frame().unwrap(), Frame::new(tag::DATA, "sync"));
+    send(&mut stream, &publish("news", "over the wire"));
+    assert_eq!(near.recv_frame().unwrap(), publish("news", "over the wire"));
+    assert_eq!(server.connection_count(), 2);
+
+    server.shutdown();
+    assert!(matches!(near.recv_frame(), Err(Error::Closed)));
+    assert!(matches!(reader.read_frame(), Err(Error::Closed)));
+  }
+
+  /// Shouts every message to the others in `#loud`, and lets the room know who left.
+  struct Megaphone;
+
+  impl Service for Megaphone {
+    fn message(&self, peers: &Peers, peer: u64, message: &[u8]) -> Result<()> {
+      if message == b"bye" {
+        return Err(Error::Closed);
+      }
+      peers.subscribe(peer, "#loud")?;
+      peers.publish("#loud", &message.to_ascii_uppercase(), Some(peer))?;
+      peers.send(peer, b"ok");
+      Ok(())
+    }
+
+    fn left(&self, peers: &Peers, peer: u64) {
+      let _ = peers.publish("#loud", format!("{} left", peer).as_bytes(), None);
+    }
+
+    fn owns(&self, topic: &str) -> bool {
+      topic.starts_with('#')
+    }
+  }
+
+  #[test]
+  fn runs_a_service() {
+    let server = connect("127.0.0.1:0").unwrap();
+    server.set_service(Arc::new(Megaphone));
+    let (mut a, mut a_reader, _) = open(&server);
+    let (mut b, mut b_reader, b_id) = open(&server);
+
+    send(&mut a, &Frame::new(tag::DATA, "hi"));
+    assert_eq!(a_reader.read_frame().unwrap(), Frame::new(tag::DATA, "ok"));
+    send(&mut b, &Frame::new(tag::DATA, "hey"));
+    assert_eq!(b_reader.read_frame().unwrap(), Frame::new(tag::DATA, "ok"));
+    assert_eq!(a_reader.read_frame().unwrap(), publish("#loud", "HEY"));
+
+    // clients can listen in, but not speak for the service
+    send(&mut a, &publish("#loud", "forged"));
+    assert_eq!(a_reader.read_frame().unwrap(), error_frame("only the server publishes to #loud"));
+
+    send(&mut b, &Frame::new(tag::DATA, "bye"));
+    assert!(matches!(b_reader.read_frame(), Err(Error::Closed)));
+    assert_eq!(a_reader.read_frame().unwrap(), publish("#loud", &format!("{} left", b_id)));
+  }
+
+  #[test]
+  fn forgets_disconnected_peers() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (mut stream, mut reader, _) = open(&server);
+    send(&mut stream, &Frame::new(tag::SUBSCRIBE, "a"));
+    send(&mut stream, &Frame::new(tag::SUBSCRIBE, "b"));
+    sync(&mut stream, &mut reader);
+    assert_eq!(server.connection_count(), 1);
+    assert_eq!(server.subscriber_count("a"), 1);
+
+    drop(reader);
+    drop(stream);
+    wait_until(|| server.connection_count() == 0);
+    assert_eq!(server.subscriber_count("a"), 0);
+    assert_eq!(server.subscriber_count("b"), 0);
+  }
+
+  #[test]
+  fn refuses_replayed_answers() {
+    let key = Key::new("open sesame");
+    let server = connect_with("127.0.0.1:0", Config { auth_key: Some(key.clone()), ..Config::default() }).unwrap();
+    let challenge = |reader: &mut FrameReader<TcpStream>| {
+      let challenge = reader.read_frame().unwrap();
+      assert_eq!(challenge.tag, tag::CHALLENGE);
+      challenge.payload
+    };
+
+    let mut first = TcpStream::connect(server.local_addr()).unwrap();
+    let mut first_reader = FrameReader::new(first.try_clone().unwrap());
+    let nonce = challenge(&mut first_reader);
+    let overheard = Frame::new(tag::AUTH, auth::answer(key.as_bytes(), &nonce).to_vec());
+    send(&mut first, &overheard);
+    assert_eq!(first_reader.read_frame().unwrap().tag, tag::HELLO);
+
+    let mut second = TcpStream::connect(server.local_addr()).unwrap();
+    let mut second_reader = FrameReader::new(second.try_clone().unwrap());
+    assert_ne!(challenge(&mut second_reader), nonce);
+    send(&mut second, &overheard);
+    assert_eq!(second_reader.read_frame().unwrap(), error_frame("authentication failed"));
+    assert!(matches!(second_reader.read_frame(), Err(Error::Closed)));
+    assert_eq!(server.stats().auth_failures, 1);
+    assert_eq!(server.connection_count(), 1);
+  }
+
+  #[test]
+  fn counts_clients_that_leave_the_challenge_unanswered() {
+    let server = connect_with("127.0.0.1:0", Config { auth_key: Some(Key::new("open sesame")), ..Config::default() }).unwrap();
+
+    let silent = TcpStream::connect(server.local_addr()).unwrap();
+    assert_eq!(FrameReader::new(silent.try_clone().unwrap()).read_frame().unwrap().tag, tag::CHALLENGE);
+    drop(silent);
+    wait_until(|| server.stats().auth_failures == 1);
+
+    let mut halfway = TcpStream::connect(server.local_addr()).unwrap();
+    assert_eq!(FrameReader::new(halfway.try_clone().unwrap()).read_frame().unwrap().tag, tag::CHALLENGE);
+    halfway.write_all(&[tag::AUTH, 0]).unwrap();
+    drop(halfway);
+    wait_until(|| server.stats().auth_failures == 2);
+    assert_eq!(server.connection_count(), 0);
+  }
+
+  #[test]
+  fn serves_clients_in_parallel() {
+    let server = connect_with("127.0.0.1:0", Config { workers: 4, ..Config::default() }).unwrap();
+    // every client is greeted by its own worker and then holds on to it
+    let clients: Vec<_> = (0..4).map(|_| open(&server)).collect();
+    let threads: Vec<_> = clients
+      .into_iter()
+      .enumerate()
+      .map(|(i, (mut stream, mut reader, _))| {
+        thread::spawn(move || {
+          for round in 0..20 {
+            let ping = Frame::new(tag::DATA, format!("{}:{}", i, round));
+            send(&mut stream, &ping);
+            assert_eq!(reader.read_frame().unwrap(), ping);
+          }
+          (stream, reader)
+        })
+      })
+      .collect();
+    let clients: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+    assert_eq!(server.connection_count(), 4);
+    drop(clients);
+  }
+
+  #[test]
+  fn turns_clients_away_when_full() {
+    let server = connect_with("127.0.0.1:0", Config { workers: 2, ..Config::default() }).unwrap();
+    let first = open(&server);
+    let _second = open(&server);
+
+    let mut refused = FrameReader::new(TcpStream::connect(server.local_addr()).unwrap());
+    assert_eq!(refused.read_frame().unwrap(), error_frame("server is full"));
+    assert!(matches!(refused.read_frame(), Err(Error::Closed)));
+    assert!(matches!(crate::client::connect(server.local_addr()), Err(Error::Rejected(_))));
+
+    drop(first);
+    wait_until(|| server.stats().connections_active == 1);
+    open(&server);
+  }
+
+  /// A fresh directory with a small site in `www` and a secret next to it.
+  fn web_root(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("communicator-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("www/data")).unwrap();
+    std::fs::write(dir.join("www/index.html"), "<h1>hi</h1>").unwrap();
+    std::fs::write(dir.join("www/style.css"), "h1 { color: red }").unwrap();
+    std::fs::write(dir.join("www/data/a.json"), "[1, 2]").unwrap();
+    std::fs::write(dir.join("secret.txt"), "hunter2").unwrap();
+    dir.join("www")
+  }
+
+  fn http_server(name: &str) -> Server {
+    connect_with("127.0.0.1:0", Config { http_root: Some(web_root(name)), ..Config::default() }).unwrap()
+  }
+
+  /// An HTTP server with one worker that gives up on clients quickly.
+  fn impatient_http_server(name: &str) -> Server {
+    let http_timeouts = http::Timeouts { idle: Duration::from_millis(100), request: Duration::from_millis(300) };
+    let config = Config { http_root: Some(web_root(name)), http_timeouts, workers: 1, ..Config::default() };
+    connect_with("127.0.0.1:0", config).unwrap()
+  }
+
+  /// Status, headers and body; HEAD responses say how long a body would be but don't send one.
+  fn read_response(reader: &mut BufReader<TcpStream>, head: bool) -> (u16, Vec<(String, String)>, Vec<u8>) {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+    let mut headers = Vec::new();
+    loop {
+      line.clear();
+      reader.read_line(&mut line).unwrap();
+      if line == "\r\n" {
+        break;
+      }
+      let (name, value) = line.trim_end().split_once(": ").unwrap();
+      headers.push((name.to_string(), value.to_string()));
+    }
+    let len: usize = headers.iter().find(|(name, _)| name == "Content-Length").unwrap().1.parse().unwrap();
+    let mut body = vec![0; if head { 0 } else { len }];
+    reader.read_exact(&mut body).unwrap();
+    (status, headers, body)
+  }
+
+  fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+    &headers.iter().find(|(key, _)| key == name).unwrap().1
+  }
+
+  fn http_client(server: &Server) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+  }
+
+  #[test]
+  fn serves_files_with_keep_alive() {
+    let server = http_server("files");
+    let (mut stream, mut reader) = http_client(&server);
+
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let (status, headers, body) = read_response(&mut reader, false);
+    assert_eq!(status, 200);
+    assert_eq!(header(&headers, "Content-Type"), "text/html; charset=utf-8");
+    assert_eq!(header(&headers, "Connection"), "keep-alive");
+    assert_eq!(body, b"<h1>hi</h1>");
+
+    // pipelined on the same connection
+    stream.write_all(b"HEAD /style.css HTTP/1.1\r\n\r\nGET /data/a.json?v=2 HTTP/1.1\r\n\r\n").unwrap();
+    let (status, headers, body) = read_response(&mut reader, true);
+    assert_eq!((status, header(&headers, "Content-Length"), body.len()), (200, "17", 0));
+    assert_eq!(header(&headers, "Content-Type"), "text/css; charset=utf-8");
+    let (status, headers, body) = read_response(&mut reader, false);
+    assert_eq!((status, header(&headers, "Content-Type"), &body[..]), (200, "application/json", &b"[1, 2]"[..]));
+  }
+
+  #[test]
+  fn answers_http_errors() {
+    let server = http_server("errors");
+    let (mut stream, mut reader) = http_client(&server);
+
+    stream.write_all(b"GET /missing.html HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader, false).0, 404);
+
+    stream.write_all(b"POST /index.html HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+    let (status, headers, _) = read_response(&mut reader, false);
+    assert_eq!((status, header(&headers, "Allow")), (405, "GET, HEAD"));
+
+    for target in &["/../secret.txt", "/data/%2e%2e/%2e%2e/secret.txt", "/data/..%2f..%2fsecret.txt"] {
+      stream.write_all(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()).unwrap();
+      let (status, _, body) = read_response(&mut reader, false);
+      assert_eq!(status, 403, "{}", target);
+      assert!(!body.starts_with(b"hunter2"));
+    }
+
+    stream.write_all(b"GET /bad%zz HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader, false).0, 400);
+
+    stream.write_all(b"nonsense\r\n\r\n").unwrap();
+    let (status, headers, _) = read_response(&mut reader, false);
+    assert_eq!((status, header(&headers, "Connection")), (400, "close"));
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+  }
+
+  #[test]
+  fn closes_when_asked() {
+    let server = http_server("close");
+    let (mut stream, mut reader) = http_client(&server);
+
+    stream.write_all(b"GET /index.html HTTP/1.0\r\n\r\n").unwrap();
+    let (status, headers, _) = read_response(&mut reader, false);
+    assert_eq!((status, header(&headers, "Connection")), (200, "close"));
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+  }
+
+  #[test]
+  fn drops_silent_http_clients() {
+    let server = impatient_http_server("silent");
+    // holds the only worker, and sends nothing
+    let (_silent, mut silent_reader) = http_client(&server);
+    wait_until(|| server.stats().connections_active == 1);
+    let (_, mut full) = http_client(&server);
+    assert_eq!(read_response(&mut full, false).0, 503);
+
+    assert_eq!(silent_reader.read(&mut [0; 1]).unwrap(), 0);
+    wait_until(|| server.stats().connections_active == 0);
+    let (mut stream, mut reader) = http_client(&server);
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader, false).0, 200);
+    // the same goes for a kept-alive connection that goes quiet
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+  }
+
+  #[test]
+  fn times_out_slow_requests() {
+    let server = impatient_http_server("slow");
+    let (mut stream, mut reader) = http_client(&server);
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    // every header comes well within the idle timeout, but the request never ends
+    let trickle = thread::spawn(move || {
+      for _ in 0..20 {
+        thread::sleep(Duration::from_millis(50));
+        if stream.write_all(b"X-Slow: yes\r\n").is_err() {
+          break;
+        }
+      }
+    });
+
+    let (status, headers, _) = read_response(&mut reader, false);
+    assert_eq!((status, header(&headers, "Connection")), (408, "close"));
+    trickle.join().unwrap();
+  }
+
+  #[test]
+  fn shutdown_closes_idle_http_clients() {
+    let server = http_server("shutdown");
+    let (mut stream, mut reader) = http_client(&server);
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader, false).0, 200);
+
+    server.shutdown();
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+  }
+
+  #[test]
+  fn shutdown_disconnects_clients() {
+    let server = connect("127.0.0.1:0").unwrap();
+    let (_stream, mut reader, _) = open(&server);
+
+    server.shutdown();
+    assert!(matches!(reader.read_frame(), Err(Error::Closed)));
+  }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+use std::vec;
+
+/// Where a server listens: a TCP address or a socket file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Addr {
+  Tcp(SocketAddr),
+  #[cfg(unix)]
+  Unix(PathBuf),
+}
+
+impl fmt::Display for Addr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Addr::Tcp(addr) => write!(f, "{}", addr),
+      #[cfg(unix)]
+      Addr::Unix(path) => write!(f, "unix:{}", path.display()),
+    }
+  }
+}
+
+/// Lets a TCP `Addr` go wherever a `SocketAddr` would; a socket path
+/// fails with `InvalidInput`, connect to those with `network::unix::connect`.
+impl ToSocketAddrs for Addr {
+  type Iter = vec::IntoIter<SocketAddr>;
+
+  fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+    match self {
+      Addr::Tcp(addr) => Ok(vec![*addr].into_iter()),
+      #[cfg(unix)]
+      Addr::Unix(path) => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is a unix socket, not an ip address", path.display()),
+      )),
+    }
+  }
+}
+
+/// A connected byte stream of either kind.
+/// Connections and the server only ever need these few operations, so
+/// they work the same over TCP and Unix sockets.
+#[derive(Debug)]
+pub enum Stream {
+  Tcp(TcpStream),
+  #[cfg(unix)]
+  Unix(UnixStream),
+}
+
+impl Stream {
+  pub fn connect(addr: &Addr) -> io::Result<Stream> {
+    match addr {
+      Addr::Tcp(addr) => super::connect(addr).map(Stream::Tcp),
+      #[cfg(unix)]
+      Addr::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+    }
+  }
+
+  pub fn try_clone(&self) -> io::Result<Stream> {
+    match self {
+      Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+      #[cfg(unix)]
+      Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+    }
+  }
+
+  /// The other end. A Unix client that never bound a path has no name of its own,
+  /// so from the server's side this is an empty path.
+  pub fn peer_addr(&self) -> io::Result<Addr> {
+    match self {
+      Stream::Tcp(stream) => stream.peer_addr().map(Addr::Tcp),
+      #[cfg(unix)]
+      Stream::Unix(stream) => {
+        let addr = stream.peer_addr()?;
+        Ok(Addr::Unix(addr.as_pathname().map(PathBuf::from).unwrap_or_default()))
+      },
+    }
+  }
+
+  pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+    match self {
+      Stream::Tcp(stream) => stream.shutdown(how),
+      #[cfg(unix)]
+      Stream::Unix(stream) => stream.shutdown(how),
+    }
+  }
+
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    match self {
+      Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+      #[cfg(unix)]
+      Stream::Unix(stream) => stream.set_read_timeout(timeout),
+    }
+  }
+
+  /// Turns off Nagle's algorithm; Unix sockets don't have one.
+  pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+    match self {
+      Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+      #[cfg(unix)]
+      Stream::Unix(_) => Ok(()),
+    }
+  }
+}
+
+impl From<TcpStream> for Stream {
+  fn from(stream: TcpStream) -> Stream {
+    Stream::Tcp(stream)
+  }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+  fn from(stream: UnixStream) -> Stream {
+    Stream::Unix(stream)
+  }
+}
+
+impl Read for Stream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    (&*self).read(buf)
+  }
+}
+
+impl Read for &Stream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Stream::Tcp(stream) => (&*stream).read(buf),
+      #[cfg(unix)]
+      Stream::Unix(stream) => (&*stream).read(buf),
+    }
+  }
+}
+
+impl Write for Stream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    (&*self).write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    (&*self).flush()
+  }
+}
+
+impl Write for &Stream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Stream::Tcp(stream) => (&*stream).write(buf),
+      #[cfg(unix)]
+      Stream::Unix(stream) => (&*stream).write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Stream::Tcp(stream) => (&*stream).flush(),
+      #[cfg(unix)]
+      Stream::Unix(stream) => (&*stream).flush(),
+    }
+  }
+}
+
+/// A bound listener of either kind.
+pub(crate) enum Listener {
+  Tcp(TcpListener),
+  #[cfg(unix)]
+  Unix(UnixListener),
+}
+
+impl Listener {
+  pub(crate) fn local_addr(&self) -> io::Result<Addr> {
+    match self {
+      Listener::Tcp(listener) => listener.local_addr().map(Addr::Tcp),
+      #[cfg(unix)]
+      Listener::Unix(listener) => {
+        let addr = listener.local_addr()?;
+        Ok(Addr::Unix(addr.as_pathname().map(PathBuf::from).unwrap_or_default()))
+      },
+    }
+  }
+
+  pub(crate) fn accept(&self) -> io::Result<Stream> {
+    match self {
+      Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+      #[cfg(unix)]
+      Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+    }
+  }
+}
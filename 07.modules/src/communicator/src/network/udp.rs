@@ -0,0 +1,682 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::rng::Drops;
+use crate::transport::Transport;
+
+/// Kind byte and sequence number in front of every datagram.
+pub const HEADER_LEN: usize = 9;
+/// The most a single IPv4 UDP datagram can carry, minus our header.
+pub const MAX_PAYLOAD: usize = 65_507 - HEADER_LEN;
+
+/// Datagram kinds. Reliable and unreliable messages are numbered separately.
+pub mod kind {
+  pub const UNRELIABLE: u8 = 0x01;
+  pub const RELIABLE: u8 = 0x02;
+  /// Acknowledges the one reliable message with the same sequence number.
+  pub const ACK: u8 = 0x03;
+}
+
+/// Just enough of a socket for `Datagrams` to run on, so tests can put a `Lossy` one in between.
+pub trait Socket: Send + Sync + 'static {
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+  fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Socket for UdpSocket {
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+    UdpSocket::send_to(self, buf, addr)
+  }
+
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    UdpSocket::recv_from(self, buf)
+  }
+
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    UdpSocket::set_read_timeout(self, timeout)
+  }
+
+  fn local_addr(&self) -> io::Result<SocketAddr> {
+    UdpSocket::local_addr(self)
+  }
+}
+
+/// Wraps a socket and silently loses a share of the datagrams it sends.
+/// Losses come from a seeded generator, so a test sees the same pattern every run.
+pub struct Lossy<S> {
+  inner: S,
+  drops: Drops,
+}
+
+impl<S: Socket> Lossy<S> {
+  pub fn new(inner: S, drop_rate: f64, seed: u64) -> Lossy<S> {
+    Lossy { inner, drops: Drops::new(drop_rate, seed) }
+  }
+}
+
+impl<S: Socket> Socket for Lossy<S> {
+  fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+    if self.drops.should_drop() {
+      // as far as the sender can tell it went out fine
+      return Ok(buf.len());
+    }
+    self.inner.send_to(buf, addr)
+  }
+
+  fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    self.inner.recv_from(buf)
+  }
+
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.inner.set_read_timeout(timeout)
+  }
+
+  fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.inner.local_addr()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// Whether `Transport::send` goes reliable and ordered, or fire-and-forget.
+  pub reliable: bool,
+  /// How long to wait for an ACK before sending a reliable message again.
+  pub retransmit_timeout: Duration,
+  /// Resends of one message before the peer is given up on.
+  pub max_retransmits: u32,
+  /// How far past the oldest unacknowledged reliable message `send` may
+  /// go before it waits for room. It is also the receive window: reliable
+  /// messages further ahead of the next one due are ignored, so both ends
+  /// need the same value.
+  pub max_in_flight: usize,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options { reliable: true, retransmit_timeout: Duration::from_millis(100), max_retransmits: 20, max_in_flight: 256 }
+  }
+}
+
+/// A UDP link to one peer.
+///
+/// Unreliable messages may be lost, but never arrive twice or older than
+/// one already delivered, which suits telemetry where only the latest
+/// reading matters. Reliable messages are acknowledged one by one and
+/// resent until they are, and the receiver puts them back in order and
+/// throws away duplicates. If a reliable message goes unacknowledged for
+/// `max_retransmits` resends, the peer is taken to be gone and every call
+/// fails with `Error::Closed` from then on.
+pub struct Datagrams<S: Socket = UdpSocket> {
+  shared: Arc<Shared<S>>,
+  inbox: Mutex<Receiver<Vec<u8>>>,
+  read_timeout: Mutex<Option<Duration>>,
+  worker: Option<JoinHandle<()>>,
+}
+
+struct Shared<S> {
+  socket: S,
+  peer: SocketAddr,
+  options: Options,
+  link: Mutex<Link>,
+  /// Signalled whenever an ACK frees room or the link goes down.
+  acked: Condvar,
+  closed: AtomicBool,
+}
+
+struct Link {
+  next_unreliable: u64,
+  next_reliable: u64,
+  unacked: BTreeMap<u64, Unacked>,
+  /// Next reliable sequence number to hand out, and the ones that came early.
+  expected: u64,
+  early: BTreeMap<u64, Vec<u8>>,
+  /// Newest unreliable sequence number delivered.
+  latest: Option<u64>,
+  /// Taken when the link goes down, which wakes up `recv`.
+  deliver: Option<Sender<Vec<u8>>>,
+  failed: bool,
+  retransmits: u64,
+  duplicates: u64,
+}
+
+struct Unacked {
+  datagram: Vec<u8>,
+  sent_at: Instant,
+  retries: u32,
+}
+
+/// Binds `local` and talks to `peer` with the default options.
+pub fn connect<A: ToSocketAddrs, B: ToSocketAddrs>(local: A, peer: B) -> Result<Datagrams> {
+  connect_with(local, peer, Options::default())
+}
+
+pub fn connect_with<A: ToSocketAddrs, B: ToSocketAddrs>(local: A, peer: B, options: Options) -> Result<Datagrams> {
+  let socket = UdpSocket::bind(local)?;
+  let peer = peer.to_socket_addrs()?.next().ok_or_else(|| Error::InvalidMessage(String::from("no peer address")))?;
+  Datagrams::new(socket, peer, options)
+}
+
+impl<S: Socket> Datagrams<S> {
+  /// Runs over an already bound `socket`, e.g. a `Lossy` one.
+  /// Fails if `options.max_in_flight` is 0, which would leave no room to send anything.
+  pub fn new(socket: S, peer: SocketAddr, options: Options) -> Result<Datagrams<S>> {
+    if options.max_in_flight == 0 {
+      return Err(Error::InvalidMessage(String::from("max_in_flight must be at least 1")));
+    }
+    // the worker wakes up this often to resend what's overdue
+    let tick = (options.retransmit_timeout / 4).max(Duration::from_millis(1));
+    socket.set_read_timeout(Some(tick))?;
+    let (deliver, inbox) = mpsc::channel();
+    let shared = Arc::new(Shared {
+      socket,
+      peer,
+      options,
+      link: Mutex::new(Link {
+        next_unreliable: 0,
+        next_reliable: 0,
+        unacked: BTreeMap::new(),
+        expected: 0,
+        early: BTreeMap::new(),
+        latest: None,
+        deliver: Some(deliver),
+        failed: false,
+        retransmits: 0,
+        duplicates: 0,
+      }),
+      acked: Condvar::new(),
+      closed: AtomicBool::new(false),
+    });
+    let worker = {
+      let shared = Arc::clone(&shared);
+      thread::spawn(move || shared.run())
+    };
+    Ok(Datagrams { shared, inbox: Mutex::new(inbox), read_timeout: Mutex::new(None), worker: Some(worker) })
+  }
+
+  pub fn local_addr(&self) -> Result<SocketAddr> {
+    Ok(self.shared.socket.local_addr()?)
+  }
+
+  pub fn peer_addr(&self) -> SocketAddr {
+    self.shared.peer
+  }
+
+  /// Sends `message` once, with no acknowledgement.
+  pub fn send_unreliable(&self, message: &[u8]) -> Result<()> {
+    check_len(message)?;
+    let seq = {
+      let mut link = self.shared.link.lock().unwrap();
+      self.shared.check_open(&link)?;
+      link.next_unreliable += 1;
+      link.next_unreliable - 1
+    };
+    self.shared.socket.send_to(&datagram(kind::UNRELIABLE, seq, message), self.shared.peer)?;
+    Ok(())
+  }
+
+  /// Sends `message` and keeps resending it until the peer acknowledges it.
+  /// Returns once it is on its way; waits first if it would be `max_in_flight`
+  /// or more past the oldest message not yet acknowledged.
+  pub fn send_reliable(&self, message: &[u8]) -> Result<()> {
+    check_len(message)?;
+    let shared = &self.shared;
+    let mut link = shared.link.lock().unwrap();
+    loop {
+      shared.check_open(&link)?;
+      // a span, not a count, so the peer never has to hold more than that many early
+      let oldest = link.unacked.keys().next().copied().unwrap_or(link.next_reliable);
+      if link.next_reliable - oldest < shared.options.max_in_flight as u64 {
+        break;
+      }
+      link = shared.acked.wait(link).unwrap();
+    }
+    let seq = link.next_reliable;
+    link.next_reliable += 1;
+    let datagram = datagram(kind::RELIABLE, seq, message);
+    // a send that fails is as good as lost, and resent like one; skipping
+    // the sequence number instead would leave the peer waiting on the gap
+    let _ = shared.socket.send_to(&datagram, shared.peer);
+    link.unacked.insert(seq, Unacked { datagram, sent_at: Instant::now(), retries: 0 });
+    Ok(())
+  }
+
+  /// Waits until every reliable message sent so far has been acknowledged.
+  pub fn flush(&self, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut link = self.shared.link.lock().unwrap();
+    loop {
+      // a link that gave up has nothing left unacknowledged, but that's no success
+      self.shared.check_open(&link)?;
+      if link.unacked.is_empty() {
+        return Ok(());
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(Error::Timeout);
+      }
+      link = self.shared.acked.wait_timeout(link, deadline - now).unwrap().0;
+    }
+  }
+
+  /// Bounds how long a receive may block before failing with `Error::Timeout`;
+  /// `None` waits forever.
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+    *self.read_timeout.lock().unwrap() = timeout;
+  }
+
+  /// Reliable messages sent again because their ACK was late.
+  pub fn retransmits(&self) -> u64 {
+    self.shared.link.lock().unwrap().retransmits
+  }
+
+  /// Incoming messages thrown away because they had already been delivered.
+  pub fn duplicates(&self) -> u64 {
+    self.shared.link.lock().unwrap().duplicates
+  }
+}
+
+impl<S: Socket> Transport for Datagrams<S> {
+  fn send(&self, message: &[u8]) -> Result<()> {
+    if self.shared.options.reliable {
+      self.send_reliable(message)
+    } else {
+      self.send_unreliable(message)
+    }
+  }
+
+  /// The next message of either kind, reliable ones in the order they were sent.
+  fn recv(&self) -> Result<Vec<u8>> {
+    let timeout = *self.read_timeout.lock().unwrap();
+    let inbox = self.inbox.lock().unwrap();
+    let received = match timeout {
+      Some(timeout) => inbox.recv_timeout(timeout),
+      None => inbox.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    match received {
+      Ok(message) => Ok(message),
+      Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+      // closed, or the link gave up on the peer
+      Err(RecvTimeoutError::Disconnected) => Err(Error::Closed),
+    }
+  }
+
+  fn close(&self) -> Result<()> {
+    self.shared.closed.store(true, Ordering::SeqCst);
+    self.shared.link.lock().unwrap().deliver = None;
+    self.shared.acked.notify_all();
+    Ok(())
+  }
+}
+
+impl<S: Socket> Drop for Datagrams<S> {
+  fn drop(&mut self) {
+    let _ = self.close();
+    if let Some(worker) = self.worker.take() {
+      let _ = worker.join();
+    }
+  }
+}
+
+impl<S: Socket> Shared<S> {
+  /// Fails with `Error::Closed` once we closed the link or it gave up on the peer.
+  fn check_open(&self, link: &Link) -> Result<()> {
+    if self.closed.load(Ordering::SeqCst) || link.failed {
+      return Err(Error::Closed);
+    }
+    Ok(())
+  }
+
+  /// Receives until closed, resending overdue messages between reads.
+  fn run(&self) {
+    let mut buf = vec![0; HEADER_LEN + MAX_PAYLOAD];
+    while !self.closed.load(Ordering::SeqCst) {
+      match self.socket.recv_from(&mut buf) {
+        Ok((len, from)) if from == self.peer => self.handle(&buf[..len]),
+        // strangers, timeouts, and errors from the peer's port being closed
+        // for a moment are all just a reason to check on the resends
+        _ => {},
+      }
+      self.resend_overdue();
+    }
+  }
+
+  fn handle(&self, datagram: &[u8]) {
+    if datagram.len() < HEADER_LEN {
+      return;
+    }
+    let seq = u64::from_be_bytes(datagram[1..HEADER_LEN].try_into().unwrap());
+    let payload = &datagram[HEADER_LEN..];
+    let mut guard = self.link.lock().unwrap();
+    let link = &mut *guard;
+
+    match datagram[0] {
+      kind::ACK if link.unacked.remove(&seq).is_some() => self.acked.notify_all(),
+      kind::RELIABLE => {
+        // beyond the window nothing is kept or acknowledged; a peer with
+        // the same window never sends that far ahead, so it's junk
+        if seq >= link.expected.saturating_add(self.options.max_in_flight as u64) {
+          return;
+        }
+        // always answer, the first ACK may be the thing that got lost
+        let _ = self.socket.send_to(&datagram_header(kind::ACK, seq), self.peer);
+        if seq < link.expected || link.early.contains_key(&seq) {
+          link.duplicates += 1;
+          return;
+        }
+        link.early.insert(seq, payload.to_vec());
+        while let Some(message) = link.early.remove(&link.expected) {
+          link.expected += 1;
+          if let Some(deliver) = &link.deliver {
+            let _ = deliver.send(message);
+          }
+        }
+      },
+      kind::UNRELIABLE => {
+        if link.latest.is_some_and(|latest| seq <= latest) {
+          link.duplicates += 1;
+          return;
+        }
+        link.latest = Some(seq);
+        if let Some(deliver) = &link.deliver {
+          let _ = deliver.send(payload.to_vec());
+        }
+      },
+      _ => {},
+    }
+  }
+
+  fn resend_overdue(&self) {
+    let mut link = self.link.lock().unwrap();
+    let now = Instant::now();
+    let mut gave_up = false;
+    let mut resent = 0;
+    for unacked in link.unacked.values_mut() {
+      if now.duration_since(unacked.sent_at) < self.options.retransmit_timeout {
+        continue;
+      }
+      if unacked.retries == self.options.max_retransmits {
+        gave_up = true;
+        break;
+      }
+      let _ = self.socket.send_to(&unacked.datagram, self.peer);
+      unacked.sent_at = now;
+      unacked.retries += 1;
+      resent += 1;
+    }
+    link.retransmits += resent;
+    if gave_up {
+      link.failed = true;
+      link.unacked.clear();
+      link.deliver = None;
+      self.acked.notify_all();
+    }
+  }
+}
+
+fn check_len(message: &[u8]) -> Result<()> {
+  if message.len() > MAX_PAYLOAD {
+    return Err(Error::FrameTooLarge { len: message.len(), max: MAX_PAYLOAD });
+  }
+  Ok(())
+}
+
+fn datagram_header(kind: u8, seq: u64) -> Vec<u8> {
+  let mut datagram = Vec::with_capacity(HEADER_LEN);
+  datagram.push(kind);
+  datagram.extend_from_slice(&seq.to_be_bytes());
+  datagram
+}
+
+fn datagram(kind: u8, seq: u64, payload: &[u8]) -> Vec<u8> {
+  let mut datagram = datagram_header(kind, seq);
+  datagram.extend_from_slice(payload);
+  datagram
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn quick() -> Options {
+    Options { retransmit_timeout: Duration::from_millis(20), ..Options::default() }
+  }
+
+  fn socket() -> UdpSocket {
+    UdpSocket::bind("127.0.0.1:0").unwrap()
+  }
+
+  /// Two lossy ends talking to each other.
+  fn lossy_pair(drop_rate: f64, options: Options) -> (Datagrams<Lossy<UdpSocket>>, Datagrams<Lossy<UdpSocket>>) {
+    let (a, b) = (socket(), socket());
+    let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+    let a = Datagrams::new(Lossy::new(a, drop_rate, 1), b_addr, options.clone()).unwrap();
+    let b = Datagrams::new(Lossy::new(b, drop_rate, 2), a_addr, options).unwrap();
+    (a, b)
+  }
+
+  /// A plain socket posing as the peer, so tests can hand-craft datagrams.
+  fn raw_peer() -> (UdpSocket, Datagrams) {
+    let raw = socket();
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let ours = Datagrams::new(socket(), raw.local_addr().unwrap(), quick()).unwrap();
+    (raw, ours)
+  }
+
+  #[test]
+  fn carries_unreliable_messages() {
+    let (a, b) = lossy_pair(0.0, Options { reliable: false, ..quick() });
+    a.send(b"temperature=21").unwrap();
+    assert_eq!(b.recv().unwrap(), b"temperature=21");
+  }
+
+  #[test]
+  fn delivers_everything_in_order_despite_loss() {
+    let (a, b) = lossy_pair(0.3, quick());
+    let sender = thread::spawn(move || {
+      for i in 0..300u32 {
+        a.send(&i.to_be_bytes()).unwrap();
+      }
+      a.flush(Duration::from_secs(20)).unwrap();
+      a.retransmits()
+    });
+
+    b.set_read_timeout(Some(Duration::from_secs(20)));
+    for i in 0..300u32 {
+      assert_eq!(b.recv().unwrap(), i.to_be_bytes());
+    }
+    assert!(sender.join().unwrap() > 0);
+    b.set_read_timeout(Some(Duration::from_millis(100)));
+    assert!(matches!(b.recv(), Err(Error::Timeout)));
+  }
+
+  #[test]
+  fn suppresses_duplicates_and_reorders() {
+    let (raw, ours) = raw_peer();
+    let to = ours.local_addr().unwrap();
+    for seq in &[1, 0, 1, 0, 2] {
+      raw.send_to(&datagram(kind::RELIABLE, *seq, &[*seq as u8]), to).unwrap();
+    }
+
+    ours.set_read_timeout(Some(Duration::from_secs(5)));
+    assert_eq!(ours.recv().unwrap(), [0]);
+    assert_eq!(ours.recv().unwrap(), [1]);
+    assert_eq!(ours.recv().unwrap(), [2]);
+    // every copy is acknowledged, in case an earlier ACK was lost
+    let mut acks = Vec::new();
+    let mut buf = [0; 64];
+    for _ in 0..5 {
+      let (len, _) = raw.recv_from(&mut buf).unwrap();
+      assert_eq!((buf[0], len), (kind::ACK, HEADER_LEN));
+      acks.push(buf[8]);
+    }
+    assert_eq!(acks, [1, 0, 1, 0, 2]);
+    assert_eq!(ours.duplicates(), 2);
+  }
+
+  #[test]
+  fn ignores_messages_beyond_the_window() {
+    let raw = socket();
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let ours = Datagrams::new(socket(), raw.local_addr().unwrap(), Options { max_in_flight: 4, ..quick() }).unwrap();
+    let to = ours.local_addr().unwrap();
+    for seq in &[u64::MAX, 4, 3, 1, 2, 0, 4] {
+      raw.send_to(&datagram(kind::RELIABLE, *seq, &[*seq as u8]), to).unwrap();
+    }
+
+    ours.set_read_timeout(Some(Duration::from_secs(5)));
+    for seq in 0..5 {
+      assert_eq!(ours.recv().unwrap(), [seq]);
+    }
+    // only what was in the window at the time got an ACK
+    let mut acks = Vec::new();
+    let mut buf = [0; 64];
+    for _ in 0..5 {
+      raw.recv_from(&mut buf).unwrap();
+      acks.push(buf[8]);
+    }
+    assert_eq!(acks, [3, 1, 2, 0, 4]);
+  }
+
+  #[test]
+  fn sender_stays_within_the_window() {
+    let raw = socket();
+    raw.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let ours = Datagrams::new(socket(), raw.local_addr().unwrap(), Options { max_in_flight: 2, ..quick() }).unwrap();
+    ours.send_reliable(b"0").unwrap();
+    ours.send_reliable(b"1").unwrap();
+    let mut buf = [0; 64];
+    let (_, from) = raw.recv_from(&mut buf).unwrap();
+    // 1 is acknowledged but 0 isn't, so 2 would be two past the oldest
+    raw.send_to(&datagram_header(kind::ACK, 1), from).unwrap();
+    let ours = Arc::new(ours);
+    let sender = {
+      let ours = Arc::clone(&ours);
+      thread::spawn(move || ours.send_reliable(b"2"))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!sender.is_finished());
+    raw.send_to(&datagram_header(kind::ACK, 0), from).unwrap();
+    sender.join().unwrap().unwrap();
+  }
+
+  /// Fails the first reliable send, the way a full buffer or a refused port would.
+  struct Flaky {
+    inner: UdpSocket,
+    failed: AtomicBool,
+  }
+
+  impl Socket for Flaky {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+      if buf[0] == kind::RELIABLE && !self.failed.swap(true, Ordering::SeqCst) {
+        return Err(io::Error::other("no buffer space"));
+      }
+      self.inner.send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+      self.inner.recv_from(buf)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+      self.inner.set_read_timeout(timeout)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+      self.inner.local_addr()
+    }
+  }
+
+  #[test]
+  fn recovers_from_a_failed_send() {
+    let (a, b) = (socket(), socket());
+    let b_addr = b.local_addr().unwrap();
+    let a_addr = a.local_addr().unwrap();
+    let flaky = Flaky { inner: a, failed: AtomicBool::new(false) };
+    let a = Datagrams::new(flaky, b_addr, quick()).unwrap();
+    let b = Datagrams::new(b, a_addr, quick()).unwrap();
+
+    a.send_reliable(b"lost").unwrap();
+    a.send_reliable(b"kept").unwrap();
+    b.set_read_timeout(Some(Duration::from_secs(5)));
+    assert_eq!(b.recv().unwrap(), b"lost");
+    assert_eq!(b.recv().unwrap(), b"kept");
+    a.flush(Duration::from_secs(5)).unwrap();
+  }
+
+  #[test]
+  fn needs_room_for_one_message() {
+    let options = Options { max_in_flight: 0, ..quick() };
+    assert!(matches!(Datagrams::new(socket(), socket().local_addr().unwrap(), options), Err(Error::InvalidMessage(_))));
+  }
+
+  #[test]
+  fn drops_stale_unreliable_messages() {
+    let (raw, ours) = raw_peer();
+    let to = ours.local_addr().unwrap();
+    for seq in &[5, 3, 5, 6] {
+      raw.send_to(&datagram(kind::UNRELIABLE, *seq, &[*seq as u8]), to).unwrap();
+    }
+
+    ours.set_read_timeout(Some(Duration::from_secs(5)));
+    assert_eq!(ours.recv().unwrap(), [5]);
+    assert_eq!(ours.recv().unwrap(), [6]);
+    assert_eq!(ours.duplicates(), 2);
+  }
+
+  #[test]
+  fn resends_until_acknowledged() {
+    let (raw, ours) = raw_peer();
+    ours.send_reliable(b"hello").unwrap();
+
+    let mut buf = [0; 64];
+    let (first, _) = raw.recv_from(&mut buf).unwrap();
+    let (second, from) = raw.recv_from(&mut buf).unwrap();
+    assert_eq!((first, second), (HEADER_LEN + 5, HEADER_LEN + 5));
+    assert!(ours.retransmits() >= 1);
+
+    raw.send_to(&datagram_header(kind::ACK, 0), from).unwrap();
+    ours.flush(Duration::from_secs(5)).unwrap();
+  }
+
+  #[test]
+  fn gives_up_on_a_silent_peer() {
+    let silent = socket();
+    let options = Options { max_retransmits: 3, retransmit_timeout: Duration::from_millis(10), ..Options::default() };
+    let ours = Datagrams::new(socket(), silent.local_addr().unwrap(), options).unwrap();
+
+    ours.send(b"anyone?").unwrap();
+    assert!(matches!(ours.flush(Duration::from_secs(5)), Err(Error::Closed)));
+    assert!(matches!(ours.recv(), Err(Error::Closed)));
+    assert!(matches!(ours.send(b"still there?"), Err(Error::Closed)));
+  }
+
+  #[test]
+  fn rejects_oversized_messages() {
+    let (a, _b) = lossy_pair(0.0, quick());
+    let big = vec![0; MAX_PAYLOAD + 1];
+    assert!(matches!(a.send(&big), Err(Error::FrameTooLarge { .. })));
+  }
+
+  #[test]
+  fn close_wakes_a_blocked_reader() {
+    let (a, _b) = lossy_pair(0.0, quick());
+    let a = Arc::new(a);
+    let reader = {
+      let a = Arc::clone(&a);
+      thread::spawn(move || a.recv())
+    };
+    thread::sleep(Duration::from_millis(20));
+    a.close().unwrap();
+    assert!(matches!(reader.join().unwrap(), Err(Error::Closed)));
+  }
+}
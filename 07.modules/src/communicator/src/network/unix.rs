@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+/// Opens a stream to the socket file at `path`.
+pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
+  UnixStream::connect(path)
+}
+
+/// Binds a listener at `path`, first clearing away a socket file left
+/// behind by a server that is no longer running.
+/// A socket something still answers on, or any file that isn't a socket,
+/// is left alone and reported as `AddrInUse`.
+///
+/// The only way to tell is to knock, so a live server sees a connection
+/// that hangs up straight away.
+pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+  let path = path.as_ref();
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.file_type().is_socket() => match UnixStream::connect(path) {
+      Ok(probe) => {
+        let _ = probe.shutdown(Shutdown::Both);
+        return Err(in_use(path));
+      },
+      // nobody listens, the server that made it is gone
+      Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+      // gone in the meantime
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+      // e.g. no permission to connect; that says nothing about whether it's stale
+      Err(err) => return Err(err),
+    },
+    Ok(_) => return Err(in_use(path)),
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+    Err(err) => return Err(err),
+  }
+  UnixListener::bind(path)
+}
+
+/// The socket file a listener was bound to, so it can be cleaned up later
+/// without removing a file another server has since put at the same path.
+/// The change time goes along with device and inode, as a freed inode
+/// number is soon handed out again.
+#[derive(Debug)]
+pub(crate) struct SocketFile {
+  path: PathBuf,
+  id: (u64, u64, i64, i64),
+}
+
+fn file_id(metadata: &fs::Metadata) -> (u64, u64, i64, i64) {
+  (metadata.dev(), metadata.ino(), metadata.ctime(), metadata.ctime_nsec())
+}
+
+impl SocketFile {
+  /// Remembers what is at `path` right now, which should be a fresh listener's socket.
+  pub(crate) fn new(path: &Path) -> io::Result<SocketFile> {
+    Ok(SocketFile { path: path.to_path_buf(), id: file_id(&fs::symlink_metadata(path)?) })
+  }
+
+  /// Removes the file if it is still the one we bound.
+  pub(crate) fn remove(&self) -> io::Result<()> {
+    if file_id(&fs::symlink_metadata(&self.path)?) != self.id {
+      return Ok(());
+    }
+    fs::remove_file(&self.path)
+  }
+}
+
+fn in_use(path: &Path) -> io::Error {
+  io::Error::new(io::ErrorKind::AddrInUse, format!("{} is already in use", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("communicator-unix-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("socket")
+  }
+
+  #[test]
+  fn replaces_a_stale_socket() {
+    let path = temp_path("stale");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let _listener = bind(&path).unwrap();
+    connect(&path).unwrap();
+  }
+
+  #[test]
+  fn refuses_a_live_socket() {
+    let path = temp_path("live");
+    let listener = bind(&path).unwrap();
+    assert_eq!(bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+    // the probe doesn't linger
+    let (mut probe, _) = listener.accept().unwrap();
+    assert_eq!(io::Read::read(&mut probe, &mut [0; 1]).unwrap(), 0);
+  }
+
+  #[test]
+  fn removes_only_its_own_socket_file() {
+    let path = temp_path("own");
+    let _first = bind(&path).unwrap();
+    let file = SocketFile::new(&path).unwrap();
+
+    // someone cleared the path and another server took it over
+    fs::remove_file(&path).unwrap();
+    let _second = bind(&path).unwrap();
+    file.remove().unwrap();
+    connect(&path).unwrap();
+
+    let file = SocketFile::new(&path).unwrap();
+    file.remove().unwrap();
+    assert!(!path.exists());
+    assert_eq!(file.remove().unwrap_err().kind(), io::ErrorKind::NotFound);
+  }
+
+  #[test]
+  fn leaves_other_files_alone() {
+    let path = temp_path("file");
+    fs::write(&path, "precious").unwrap();
+    assert_eq!(bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "precious");
+  }
+}
@@ -0,0 +1,39 @@
+use std::sync::Mutex;
+
+/// xorshift64, so injected faults are the same on every run and need no crates.
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+  /// Any seed works; the low bit is set so it can't be zero, the one state xorshift can't leave.
+  pub(crate) fn new(seed: u64) -> XorShift {
+    XorShift(seed | 1)
+  }
+
+  /// Uniform in [0, 1).
+  pub(crate) fn next_f64(&mut self) -> f64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    (self.0 >> 11) as f64 / (1u64 << 53) as f64
+  }
+}
+
+/// Decides which messages a lossy link throws away, each with chance `rate`.
+/// Shared by every sender on the link, so it takes `&self`.
+pub(crate) struct Drops {
+  rate: f64,
+  rng: Mutex<XorShift>,
+}
+
+impl Drops {
+  pub(crate) fn new(rate: f64, seed: u64) -> Drops {
+    Drops { rate, rng: Mutex::new(XorShift::new(seed)) }
+  }
+
+  pub(crate) fn should_drop(&self) -> bool {
+    if self.rate <= 0.0 {
+      return false;
+    }
+    self.rng.lock().unwrap().next_f64() < self.rate
+  }
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::client;
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::network::frame::{tag, Frame};
+use crate::network::pool::ThreadPool;
+use crate::transport::FrameTransport;
+
+/// How long `RpcClient::call` waits for an answer unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest method name the wire format can carry.
+pub const MAX_METHOD_LEN: usize = 255;
+
+/// Response status bytes.
+pub(crate) const OK: u8 = 0;
+pub(crate) const UNKNOWN_METHOD: u8 = 1;
+pub(crate) const HANDLER_FAILED: u8 = 2;
+pub(crate) const BUSY: u8 = 3;
+
+/// Why a remote call produced no result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+  /// The server has no handler registered under this name.
+  UnknownMethod(String),
+  /// No answer arrived in time. The handler may still run to completion.
+  Timeout,
+  /// The handler ran and reported an error, or panicked.
+  HandlerFailed(String),
+  /// The connection went away before the answer came back.
+  TransportLost,
+  /// The server turned the call away because too many others from this
+  /// connection were still running. Nothing ran; trying again later may work.
+  Busy,
+}
+
+impl fmt::Display for RpcError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RpcError::UnknownMethod(method) => write!(f, "unknown method {:?}", method),
+      RpcError::Timeout => write!(f, "call timed out"),
+      RpcError::HandlerFailed(reason) => write!(f, "handler failed: {}", reason),
+      RpcError::TransportLost => write!(f, "connection lost"),
+      RpcError::Busy => write!(f, "too many calls in flight"),
+    }
+  }
+}
+
+impl std::error::Error for RpcError {}
+
+type Handler = Arc<dyn Fn(&[u8]) -> std::result::Result<Vec<u8>, RpcError> + Send + Sync>;
+
+/// The server's method table.
+#[derive(Default)]
+pub(crate) struct Registry {
+  handlers: RwLock<HashMap<String, Handler>>,
+}
+
+impl Registry {
+  pub(crate) fn register<F>(&self, method: &str, handler: F)
+  where
+    F: Fn(&[u8]) -> std::result::Result<Vec<u8>, RpcError> + Send + Sync + 'static,
+  {
+    self.handlers.write().unwrap().insert(method.to_string(), Arc::new(handler));
+  }
+
+  pub(crate) fn knows(&self, method: &str) -> bool {
+    self.handlers.read().unwrap().contains_key(method)
+  }
+
+  /// Answers the request in `frame` through `respond`, running the handler on `pool`.
+  /// Only a malformed request is an error, everything else is reported in the response.
+  pub(crate) fn dispatch<F>(&self, frame: &Frame, pool: &ThreadPool, respond: F) -> Result<()>
+  where
+    F: FnOnce(Frame) + Send + 'static,
+  {
+    let (id, method, body) = split_request(&frame.payload)?;
+    let handler = match self.handlers.read().unwrap().get(method) {
+      Some(handler) => Arc::clone(handler),
+      None => {
+        respond(response(id, UNKNOWN_METHOD, method.as_bytes()));
+        return Ok(());
+      },
+    };
+    let body = body.to_vec();
+    pool.execute(move || {
+      let answer = match panic::catch_unwind(AssertUnwindSafe(|| handler(&body))) {
+        Ok(Ok(result)) => response(id, OK, &result),
+        Ok(Err(RpcError::UnknownMethod(method))) => response(id, UNKNOWN_METHOD, method.as_bytes()),
+        Ok(Err(RpcError::HandlerFailed(reason))) => response(id, HANDLER_FAILED, reason.as_bytes()),
+        Ok(Err(other)) => response(id, HANDLER_FAILED, other.to_string().as_bytes()),
+        Err(_) => response(id, HANDLER_FAILED, b"handler panicked"),
+      };
+      respond(answer);
+    });
+    Ok(())
+  }
+}
+
+type Reply = std::result::Result<Vec<u8>, RpcError>;
+
+/// Calls that have been sent and not answered yet.
+#[derive(Default)]
+struct Pending {
+  calls: HashMap<u64, Sender<Reply>>,
+  lost: bool,
+}
+
+/// The calling side of RPC, over a `Connection` unless told otherwise.
+/// Any number of threads can share one client; each call gets its own
+/// request id, and a background thread hands every answer to whoever asked.
+pub struct RpcClient<T: FrameTransport + 'static = Connection> {
+  transport: Arc<T>,
+  pending: Arc<Mutex<Pending>>,
+  next_id: AtomicU64,
+  timeout: Duration,
+  reader: Option<JoinHandle<()>>,
+}
+
+impl RpcClient {
+  /// Connects to the server at `addr` for making calls.
+  pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<RpcClient> {
+    Ok(RpcClient::new(client::connect(addr)?))
+  }
+}
+
+impl<T: FrameTransport + 'static> RpcClient<T> {
+  /// Takes over `transport`. Frames other than responses are discarded from now on.
+  pub fn new(transport: T) -> RpcClient<T> {
+    let transport = Arc::new(transport);
+    let pending = Arc::new(Mutex::new(Pending::default()));
+    let reader = {
+      let transport = Arc::clone(&transport);
+      let pending = Arc::clone(&pending);
+      thread::spawn(move || read_responses(&*transport, &pending))
+    };
+    RpcClient {
+      transport,
+      pending,
+      next_id: AtomicU64::new(1),
+      timeout: DEFAULT_TIMEOUT,
+      reader: Some(reader),
+    }
+  }
+
+  /// Changes the timeout `call` uses.
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+  }
+
+  pub fn call(&self, method: &str, body: &[u8]) -> Reply {
+    self.call_timeout(method, body, self.timeout)
+  }
+
+  pub fn call_timeout(&self, method: &str, body: &[u8], timeout: Duration) -> Reply {
+    if method.is_empty() || method.len() > MAX_METHOD_LEN {
+      return Err(RpcError::UnknownMethod(method.to_string()));
+    }
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    let (sender, answer) = mpsc::channel();
+    {
+      let mut pending = self.pending.lock().unwrap();
+      if pending.lost {
+        return Err(RpcError::TransportLost);
+      }
+      pending.calls.insert(id, sender);
+    }
+
+    if self.transport.send_frame(&Frame::new(tag::RPC_REQUEST, request_payload(id, method, body))).is_err() {
+      self.pending.lock().unwrap().calls.remove(&id);
+      return Err(RpcError::TransportLost);
+    }
+    match answer.recv_timeout(timeout) {
+      Ok(reply) => reply,
+      Err(RecvTimeoutError::Timeout) => {
+        // a late answer finds nobody waiting and is dropped
+        self.pending.lock().unwrap().calls.remove(&id);
+        Err(RpcError::Timeout)
+      },
+      Err(RecvTimeoutError::Disconnected) => Err(RpcError::TransportLost),
+    }
+  }
+
+  /// Calls waiting for an answer right now.
+  pub fn in_flight(&self) -> usize {
+    self.pending.lock().unwrap().calls.len()
+  }
+}
+
+impl<T: FrameTransport + 'static> Drop for RpcClient<T> {
+  fn drop(&mut self) {
+    let _ = self.transport.close();
+    if let Some(reader) = self.reader.take() {
+      let _ = reader.join();
+    }
+  }
+}
+
+fn read_responses<T: FrameTransport>(transport: &T, pending: &Mutex<Pending>) {
+  loop {
+    let frame = match transport.recv_frame() {
+      Ok(frame) => frame,
+      Err(Error::Timeout) => continue,
+      Err(_) => break,
+    };
+    if frame.tag != tag::RPC_RESPONSE {
+      continue;
+    }
+    if let Ok((id, reply)) = split_response(&frame.payload) {
+      if let Some(caller) = pending.lock().unwrap().calls.remove(&id) {
+        let _ = caller.send(reply);
+      }
+    }
+  }
+  // dropping the senders wakes every waiting caller with `TransportLost`
+  let mut pending = pending.lock().unwrap();
+  pending.lost = true;
+  pending.calls.clear();
+}
+
+pub(crate) fn request_payload(id: u64, method: &str, body: &[u8]) -> Vec<u8> {
+  let mut payload = id.to_be_bytes().to_vec();
+  payload.push(method.len() as u8);
+  payload.extend_from_slice(method.as_bytes());
+  payload.extend_from_slice(body);
+  payload
+}
+
+pub(crate) fn split_request(payload: &[u8]) -> Result<(u64, &str, &[u8])> {
+  let malformed = || Error::Protocol(String::from("malformed rpc request"));
+  if payload.len() < 9 {
+    return Err(malformed());
+  }
+  let id = u64::from_be_bytes(payload[..8].try_into().unwrap());
+  let len = payload[8] as usize;
+  let method = payload.get(9..9 + len).ok_or_else(malformed)?;
+  let method = std::str::from_utf8(method).map_err(|_| malformed())?;
+  Ok((id, method, &payload[9 + len..]))
+}
+
+pub(crate) fn response(id: u64, status: u8, body: &[u8]) -> Frame {
+  let mut payload = id.to_be_bytes().to_vec();
+  payload.push(status);
+  payload.extend_from_slice(body);
+  Frame::new(tag::RPC_RESPONSE, payload)
+}
+
+/// The status byte of a response frame built by `response`.
+pub(crate) fn status(frame: &Frame) -> Option<u8> {
+  frame.payload.get(8).cloned()
+}
+
+fn split_response(payload: &[u8]) -> Result<(u64, Reply)> {
+  if payload.len() < 9 {
+    return Err(Error::Protocol(String::from("malformed rpc response")));
+  }
+  let id = u64::from_be_bytes(payload[..8].try_into().unwrap());
+  let body = &payload[9..];
+  let text = || String::from_utf8_lossy(body).into_owned();
+  let reply = match payload[8] {
+    OK => Ok(body.to_vec()),
+    UNKNOWN_METHOD => Err(RpcError::UnknownMethod(text())),
+    BUSY => Err(RpcError::Busy),
+    _ => Err(RpcError::HandlerFailed(text())),
+  };
+  Ok((id, reply))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::loopback::{self, Faults};
+  use crate::network::server::{self, Server};
+  use std::time::Instant;
+
+  fn serve() -> Server {
+    let server = server::connect("127.0.0.1:0").unwrap();
+    server.register("upper", |body| Ok(body.to_ascii_uppercase()));
+    server.register("fail", |_| Err(RpcError::HandlerFailed(String::from("bad input"))));
+    server.register("panic", |_| panic!("boom"));
+    server.register("sleep", |body| {
+      let millis = std::str::from_utf8(body).unwrap().parse().unwrap();
+      thread::sleep(Duration::from_millis(millis));
+      Ok(body.to_vec())
+    });
+    server
+  }
+
+  #[test]
+  fn calls_a_handler() {
+    let server = serve();
+    let client = RpcClient::connect(server.local_addr()).unwrap();
+
+    assert_eq!(client.call("upper", b"shout").unwrap(), b"SHOUT");
+    assert_eq!(client.in_flight(), 0);
+  }
+
+  #[test]
+  fn reports_unknown_methods() {
+    let server = serve();
+    let client = RpcClient::connect(server.local_addr()).unwrap();
+
+    assert_eq!(client.call("lower", b"x"), Err(RpcError::UnknownMethod(String::from("lower"))));
+    assert_eq!(client.call("", b"x"), Err(RpcError::UnknownMethod(String::new())));
+  }
+
+  #[test]
+  fn reports_handler_failures() {
+    let server = serve();
+    let client = RpcClient::connect(server.local_addr()).unwrap();
+
+    assert_eq!(client.call("fail", b""), Err(RpcError::HandlerFailed(String::from("bad input"))));
+    assert_eq!(client.call("panic", b""), Err(RpcError::HandlerFailed(String::from("handler panicked"))));
+    // the connection is still good afterwards
+    assert_eq!(client.call("upper", b"ok").unwrap(), b"OK");
+  }
+
+  #[test]
+  fn times_out_and_recovers() {
+    let server = serve();
+    let client = RpcClient::connect(server.local_addr()).unwrap();
+
+    assert_eq!(client.call_timeout("sleep", b"300", Duration::from_millis(30)), Err(RpcError::Timeout));
+    assert_eq!(client.in_flight(), 0);
+    assert_eq!(client.call("upper", b"next").unwrap(), b"NEXT");
+  }
+
+  #[test]
+  fn runs_calls_concurrently_over_one_connection() {
+    let server = serve();
+    let client = Arc::new(RpcClient::connect(server.local_addr()).unwrap());
+
+    let started = Instant::now();
+    let callers: Vec<_> = (0..8)
+      .map(|i| {
+        let client = Arc::clone(&client);
+        // different lengths, so answers come back out of order
+        let millis = (200 + i * 10).to_string();
+        thread::spawn(move || (millis.clone(), client.call("sleep", millis.as_bytes()).unwrap()))
+      })
+      .collect();
+    for caller in callers {
+      let (asked, answered) = caller.join().unwrap();
+      assert_eq!(answered, asked.into_bytes());
+    }
+    // eight 200ms calls one after another would take at least 1.6s
+    assert!(started.elapsed() < Duration::from_millis(1200), "{:?}", started.elapsed());
+  }
+
+  #[test]
+  fn fails_calls_when_the_connection_drops() {
+    let server = serve();
+    let client = Arc::new(RpcClient::connect(server.local_addr()).unwrap());
+
+    let caller = {
+      let client = Arc::clone(&client);
+      thread::spawn(move || client.call("sleep", b"2000"))
+    };
+    while client.in_flight() == 0 {
+      thread::sleep(Duration::from_millis(5));
+    }
+    server.shutdown();
+    assert_eq!(caller.join().unwrap(), Err(RpcError::TransportLost));
+    assert_eq!(client.call("upper", b"x"), Err(RpcError::TransportLost));
+  }
+
+  #[test]
+  fn calls_over_a_lossy_loopback() {
+    let server = serve();
+    let faults = Faults { latency: Duration::from_millis(2), drop_rate: 0.3, seed: 11, ..Faults::default() };
+    let (near, far) = loopback::pair_with(faults);
+    server.attach(far);
+    let client = RpcClient::new(near);
+
+    let mut answered = 0;
+    for i in 0..30 {
+      match client.call_timeout("upper", format!("m{}", i).as_bytes(), Duration::from_millis(100)) {
+        Ok(reply) => {
+          assert_eq!(reply, format!("M{}", i).into_bytes());
+          answered += 1;
+        },
+        Err(err) => assert_eq!(err, RpcError::Timeout),
+      }
+    }
+    // a call is lost if either its request or its answer is
+    assert!(answered > 0 && answered < 30, "{}", answered);
+    assert_eq!(client.in_flight(), 0);
+  }
+
+  #[test]
+  fn loses_calls_when_a_loopback_is_cut() {
+    let server = serve();
+    // the greeting and two round trips
+    let (near, far) = loopback::pair_with(Faults { disconnect_after: Some(5), ..Faults::default() });
+    server.attach(far);
+    let client = RpcClient::new(near);
+
+    assert_eq!(client.call("upper", b"a").unwrap(), b"A");
+    assert_eq!(client.call("upper", b"b").unwrap(), b"B");
+    assert_eq!(client.call("upper", b"c"), Err(RpcError::TransportLost));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.connection_count() > 0 && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.connection_count(), 0);
+  }
+
+  #[test]
+  fn serves_stats_to_admins() {
+    let server = serve();
+    let client = RpcClient::connect(server.local_addr()).unwrap();
+    client.call("upper", b"x").unwrap();
+
+    let text = String::from_utf8(client.call(server::ADMIN_STATS, b"").unwrap()).unwrap();
+    assert!(text.contains("connections_active 1\n"), "{}", text);
+    assert!(text.contains("calls \"upper\" 1\n"), "{}", text);
+    let json = String::from_utf8(client.call(server::ADMIN_STATS, b"json").unwrap()).unwrap();
+    assert!(json.starts_with("{\"connections_active\":1,"), "{}", json);
+    assert!(json.ends_with("\"calls\":{\"upper\":1}}"), "{}", json);
+    assert!(matches!(client.call(server::ADMIN_STATS, b"xml"), Err(RpcError::HandlerFailed(_))));
+  }
+
+  #[test]
+  #[should_panic(expected = "reserved")]
+  fn reserves_admin_methods() {
+    serve().register("admin.reboot", |_| Ok(Vec::new()));
+  }
+
+  #[test]
+  fn encodes_requests_and_responses() {
+    let payload = request_payload(7, "m", b"body");
+    assert_eq!(split_request(&payload).unwrap(), (7, "m", &b"body"[..]));
+    assert!(split_request(&payload[..9]).is_err());
+
+    let frame = response(9, HANDLER_FAILED, b"why");
+    assert_eq!(split_response(&frame.payload).unwrap(), (9, Err(RpcError::HandlerFailed(String::from("why")))));
+    assert_eq!(split_response(&response(3, BUSY, b"").payload).unwrap(), (3, Err(RpcError::Busy)));
+  }
+}
@@ -0,0 +1,27 @@
+use crate::error::Result;
+use crate::network::frame::Frame;
+
+/// A bidirectional, message-oriented channel to a peer.
+/// Methods take `&self` so one thread can block in `recv` while
+/// others keep sending on the same transport.
+pub trait Transport: Send + Sync {
+  /// Sends one message.
+  fn send(&self, message: &[u8]) -> Result<()>;
+
+  /// Blocks until the next message arrives.
+  /// Returns `Error::Closed` once the peer has gone away.
+  fn recv(&self) -> Result<Vec<u8>>;
+
+  /// Closes both directions. Blocked and later calls fail with `Error::Closed`.
+  fn close(&self) -> Result<()>;
+}
+
+/// A transport that carries whole frames, which is all the server's
+/// connection logic and `RpcClient` need to run over it.
+/// `Connection` and `Loopback` both are one.
+pub trait FrameTransport: Transport {
+  fn send_frame(&self, frame: &Frame) -> Result<()>;
+
+  /// Blocks until the next frame arrives, with the same errors as `recv`.
+  fn recv_frame(&self) -> Result<Frame>;
+}
@@ -1,5 +1,42 @@
 extern crate communicator;
 
-fn main() {
-    communicator::client::connect();
+mod chat;
+
+use std::env;
+use std::io;
+use std::process;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+const USAGE: &str = "usage:
+    modules server [addr]
+    modules client <nick> [addr]
+
+commands once connected: /nick <name>, /join <room>, /who, /quit";
+
+fn main() -> communicator::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args[..] {
+        ["server"] | ["server", _] => {
+            let server = chat::server::serve(args.get(1).copied().unwrap_or(DEFAULT_ADDR))?;
+            println!("communicator {} chat listening on {}", communicator::VERSION, server.local_addr());
+            server.wait();
+            Ok(())
+        },
+        ["client", nick] | ["client", nick, _] => {
+            if !chat::valid_name(nick) {
+                eprintln!("nicknames are 1 to {} letters, digits, - or _", chat::MAX_NAME_LEN);
+                process::exit(2);
+            }
+            let addr = args.get(2).copied().unwrap_or(DEFAULT_ADDR);
+            let stdin = io::stdin();
+            chat::client::run(addr, nick, stdin.lock(), &mut io::stdout())
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    }
 }